@@ -0,0 +1,174 @@
+use url::Url;
+
+use tetra_pkgmgr::{
+    TetraRoot,
+    config::Config,
+    model::{Checksum, PartSource, RecipeSource, Source},
+    net::{Downloader, check_url_security},
+    store::Cache,
+    util::{Style, format_bytes, paint},
+};
+
+use crate::output::{self, CheckedPackage, Report, Status};
+
+/// Checks that every source of a package, or only the selected one, can be
+/// downloaded, asking the servers for headers only. Returns false if any source is unavailable.
+pub fn run(
+    tetra_root: &TetraRoot,
+    config: &Config,
+    package_id: String,
+    exact: bool,
+    selector: Option<String>,
+) -> bool {
+    let mut report = Report::default();
+
+    let Some(package) = CheckedPackage::load(&mut report, tetra_root, package_id, exact, selector)
+    else {
+        return false;
+    };
+
+    for source in package.sources() {
+        check_source(&mut report, config, &package.cache, source);
+    }
+
+    println!();
+    if report.failed {
+        println!("Result: {}", paint("FAIL", Style::Error));
+    } else {
+        println!("Result: {}", paint("PASS", Style::Success));
+    }
+
+    !report.failed
+}
+
+fn check_source(report: &mut Report, config: &Config, cache: &Cache, source: &RecipeSource) {
+    if let Err(e) = check_url_security(source, config) {
+        report.check(Status::Fail, &e.to_string(), None);
+        return;
+    }
+
+    // The URL of a split source only names the joined file, its parts are what is served
+    match source.parts() {
+        Ok(parts) if !parts.is_empty() => return check_parts(report, config, &parts),
+        Ok(_) => {}
+        Err(e) => return report.check(Status::Fail, &e.to_string(), None),
+    }
+
+    let probe = match Downloader::probe(source, &config.download_limits()) {
+        Ok(p) => p,
+        Err(e) => {
+            report.check(
+                Status::Fail,
+                &format!("Unreachable, {e}"),
+                Some("The server or mirror may be down"),
+            );
+            return;
+        }
+    };
+
+    if let Some(target) = &probe.redirect
+        && (300..400).contains(&probe.status)
+    {
+        report.check(
+            Status::Fail,
+            &format!(
+                "Redirects to {}, but redirects are not followed",
+                output::display_url(target)
+            ),
+            Some("Update the recipe URL, or allow the source to follow redirects"),
+        );
+        return;
+    }
+
+    let redirected = match (Url::parse(&probe.url), source.request_url()) {
+        (Ok(reached), Ok(requested)) => Url::parse(&requested).is_ok_and(|r| r != reached),
+        _ => false,
+    };
+
+    if !probe.is_success() {
+        report.check(
+            Status::Fail,
+            &format!("Server responded with {}", probe.status),
+            Some("The source may have been moved or removed"),
+        );
+        return;
+    }
+
+    let size = match probe.size {
+        Some(size) => format_bytes(size),
+        None => "unknown size".to_string(),
+    };
+
+    let cached_size = source.checksum().ok().and_then(|h| cache.blob_size(h));
+
+    match (probe.size, cached_size) {
+        (Some(remote), Some(cached)) if remote != cached && source.mutable => report.check(
+            Status::Warn,
+            &format!(
+                "Available, {size}, but the cached copy has {}",
+                format_bytes(cached)
+            ),
+            Some("The mutable source likely changed upstream"),
+        ),
+        (Some(remote), Some(cached)) if remote != cached => report.check(
+            Status::Fail,
+            &format!(
+                "Server sends {size}, but the cached copy matching the hash has {}",
+                format_bytes(cached)
+            ),
+            Some("The server does not serve the content the recipe expects"),
+        ),
+        _ if redirected => report.check(
+            Status::Pass,
+            &format!(
+                "Available, {} with {size} from {}",
+                probe.status,
+                output::display_url(&probe.url)
+            ),
+            None,
+        ),
+        _ => report.check(
+            Status::Pass,
+            &format!("Available, {} with {size}", probe.status),
+            None,
+        ),
+    }
+}
+
+fn check_parts(report: &mut Report, config: &Config, parts: &[PartSource]) {
+    for (index, part) in parts.iter().enumerate() {
+        let name = format!("Part {} of {}", index + 1, parts.len());
+
+        match Downloader::probe(part, &config.download_limits()) {
+            Ok(probe) if probe.is_success() => report.check(
+                Status::Pass,
+                &format!(
+                    "{name} available, {} with {}",
+                    probe.status,
+                    match probe.size {
+                        Some(size) => format_bytes(size),
+                        None => "unknown size".to_string(),
+                    }
+                ),
+                None,
+            ),
+            Ok(probe) => report.check(
+                Status::Fail,
+                &format!(
+                    "{name} at {} responded with {}",
+                    output::display_url(&part.url),
+                    probe.status
+                ),
+                Some("The part may have been moved or removed"),
+            ),
+            Err(e) => report.check(
+                Status::Fail,
+                &format!(
+                    "{name} at {} is unreachable, {e}",
+                    output::display_url(&part.url)
+                ),
+                Some("The server may be down"),
+            ),
+        }
+    }
+}
@@ -0,0 +1,377 @@
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::util::{UserDirs, decode_hex, expand_path};
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Tetra root used when neither `--root` nor `TETRA_ROOT` is given
+    pub root: Option<PathBuf>,
+
+    /// Directories repositories are searched in, which defaults to `<root>/repo`. A
+    /// repository in an earlier directory shadows one with the same id in a later one.
+    /// The single `repo_dir` of older configs is still read.
+    #[serde(alias = "repo_dir", deserialize_with = "one_or_many")]
+    pub repo_dirs: Vec<PathBuf>,
+
+    /// Overrides the location of the source cache, which defaults to `<root>/cache`
+    pub cache_dir: Option<PathBuf>,
+
+    /// File holding a secret that namespaces the cache, entries are then stored under
+    /// keyed digests so they cannot be found by guessing content hashes
+    pub cache_key_file: Option<PathBuf>,
+
+    /// Hex encoded Ed25519 public keys by repository id. The signed recipe index of a
+    /// repository with a key is verified, and its recipes are refused if they do not match.
+    pub repo_keys: BTreeMap<String, String>,
+
+    /// Store cache blobs zstd-compressed, they are hashed over their uncompressed contents
+    pub compress_cache: bool,
+
+    /// Octal permissions of cache blobs and records, e.g. "0644". They are left to the
+    /// umask when not set.
+    pub cache_file_mode: Option<String>,
+
+    /// Octal permissions of cache directories, e.g. "0755"
+    pub cache_dir_mode: Option<String>,
+
+    /// Cache the sources of each recipe in a namespace keyed by the hash of its sources.
+    /// A recipe edited in place then fetches again instead of reusing blobs of its old
+    /// sources, at the cost of storing blobs shared between recipes more than once.
+    pub pin_cache_to_recipe: bool,
+
+    /// Largest number of bytes the cache blobs may take on disk. Storing a blob that
+    /// would exceed it first evicts the least recently used blobs, except those used in
+    /// the last 15 minutes. Unbounded when not set.
+    pub max_cache_size: Option<u64>,
+
+    /// Downloads totalling more than this many bytes ask for confirmation first
+    pub confirm_threshold: u64,
+
+    /// What to do when a confirmation is needed but there is no terminal to ask on
+    pub non_interactive: NonInteractive,
+
+    /// Refuse to download sources over plain, unencrypted protocols
+    pub require_https: bool,
+
+    /// Host that replaces the host of every repository base URL, pinning one mirror
+    pub mirror_host: Option<String>,
+
+    /// Order the URL and mirrors of a source are tried in
+    pub mirror_strategy: MirrorStrategy,
+
+    /// Remember the servers found down in `<root>/mirror-health.yml`, so later runs also
+    /// skip them until their backoff ends. They are only skipped for the run otherwise.
+    pub persist_mirror_health: bool,
+
+    /// Hosts trusted to serve sources without TLS, e.g. internal mirrors
+    pub insecure_hosts: Vec<String>,
+
+    /// HTTP gateways `ipfs://` sources are downloaded through, e.g. `https://ipfs.io`.
+    /// They are tried like mirrors, in the order of the mirror strategy.
+    pub ipfs_gateways: Vec<String>,
+
+    /// Environment variables source URLs may reference as `${NAME}`, besides those
+    /// starting with `TETRA_`, e.g. a token of a signed URL
+    pub url_env_vars: Vec<String>,
+
+    /// URL used by `tetra doctor` to check network reachability
+    pub doctor_url: Option<String>,
+
+    /// Largest number of bytes a single source may download before it is aborted
+    pub max_source_size: Option<u64>,
+
+    /// Seconds to wait for a connection to a source server
+    pub connect_timeout: u64,
+
+    /// Seconds a single source download may take in total
+    pub transfer_timeout: Option<u64>,
+
+    /// How often an interrupted download is resumed before giving up
+    pub download_retries: u32,
+
+    /// Most redirects a download follows, 0 stops downloads from following redirects
+    pub max_redirects: u32,
+
+    /// Largest number of concurrent operations, the number of CPUs when not set
+    pub max_parallel: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NonInteractive {
+    #[default]
+    Proceed,
+    Abort,
+}
+
+/// How the servers of a source with mirrors are chosen. The others remain fallbacks
+/// in case the chosen one fails.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MirrorStrategy {
+    /// The source URL first, then its mirrors in recipe order
+    #[default]
+    Ordered,
+
+    /// The server that answers a probe fastest, each server is probed once per run
+    Fastest,
+    Random,
+}
+
+impl FromStr for MirrorStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ordered" => Ok(MirrorStrategy::Ordered),
+            "fastest" => Ok(MirrorStrategy::Fastest),
+            "random" => Ok(MirrorStrategy::Random),
+            _ => Err(format!(
+                "unknown mirror strategy {s}, expected ordered, fastest or random"
+            )),
+        }
+    }
+}
+
+/// When the cached download of a mutable source is used without asking the server
+/// whether it changed. Sources pinned by a hash are always served from the cache.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PreferCache {
+    /// Use any intact cached download
+    Always,
+
+    /// Use cached downloads younger than this
+    Age(Duration),
+
+    /// Revalidate every time with a conditional request
+    #[default]
+    Never,
+}
+
+impl PreferCache {
+    /// Whether a cached download of this age is used as it is
+    pub fn reuses(&self, age: Duration) -> bool {
+        match self {
+            PreferCache::Always => true,
+            PreferCache::Age(max) => age < *max,
+            PreferCache::Never => false,
+        }
+    }
+}
+
+impl FromStr for PreferCache {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(PreferCache::Always),
+            "never" => Ok(PreferCache::Never),
+            _ => match s.strip_prefix("age:") {
+                Some(duration) => parse_duration(duration).map(PreferCache::Age),
+                None => Err(format!(
+                    "unknown cache policy {s}, expected always, never or age:DURATION"
+                )),
+            },
+        }
+    }
+}
+
+/// Parses octal permission bits, e.g. `0644` or `0o2775`
+fn parse_mode(s: &str) -> Result<u32, String> {
+    let digits = s.trim().trim_start_matches("0o");
+
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("{s:?} is not an octal mode such as 0644")),
+    }
+}
+
+/// Parses a duration such as `90s`, `30m`, `12h`, `7d` or `2w`
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (amount, unit) = s.split_at(split);
+
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("invalid duration {s}, expected e.g. 30m, 12h or 7d"))?;
+
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "invalid duration unit in {s}, expected s, m, h, d or w"
+            ));
+        }
+    };
+
+    Ok(Duration::from_secs(amount.saturating_mul(unit_secs)))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DownloadLimits {
+    pub max_size: Option<u64>,
+    pub connect_timeout: Duration,
+    pub transfer_timeout: Option<Duration>,
+    pub retries: u32,
+    pub max_redirects: u32,
+}
+
+impl Default for DownloadLimits {
+    fn default() -> Self {
+        Config::default().download_limits()
+    }
+}
+
+/// Reads a list that may also be given as a single value
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            root: None,
+            repo_dirs: Vec::new(),
+            cache_dir: None,
+            cache_key_file: None,
+            repo_keys: BTreeMap::new(),
+            compress_cache: false,
+            cache_file_mode: None,
+            cache_dir_mode: None,
+            pin_cache_to_recipe: false,
+            max_cache_size: None,
+            confirm_threshold: 500 * 1024 * 1024,
+            non_interactive: NonInteractive::default(),
+            require_https: false,
+            mirror_host: None,
+            mirror_strategy: MirrorStrategy::default(),
+            persist_mirror_health: false,
+            insecure_hosts: Vec::new(),
+            ipfs_gateways: Vec::new(),
+            url_env_vars: Vec::new(),
+            doctor_url: None,
+            max_source_size: None,
+            connect_timeout: 30,
+            transfer_timeout: None,
+            download_retries: 3,
+            max_redirects: 10,
+            max_parallel: None,
+        }
+    }
+}
+
+impl Config {
+    const DEFAULT_CONFIG_PATH: &str = "/etc/tetra/config.yml";
+    const CACHE_KEY_CONTEXT: &str = "tetra-pkgmgr 2026-10-15 cache namespace key";
+
+    /// The config file named by `TETRA_CONFIG`, otherwise `config.yml` in the user config
+    /// directory of a per-user install or `/etc/tetra/config.yml`
+    pub fn get_config_path(user_dirs: Option<&UserDirs>) -> PathBuf {
+        match (std::env::var("TETRA_CONFIG"), user_dirs) {
+            (Ok(path), _) => PathBuf::from(path),
+            (Err(_), Some(dirs)) => dirs.config.join("config.yml"),
+            (Err(_), None) => PathBuf::from(Self::DEFAULT_CONFIG_PATH),
+        }
+    }
+
+    /// Loads the configuration, a missing config file yields the defaults
+    pub fn load(user_dirs: Option<&UserDirs>) -> Result<Self> {
+        let path = Self::get_config_path(user_dirs);
+
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+
+        Self::from_path(&path)
+    }
+
+    /// Derives the cache namespace key from the configured key file, if any
+    pub fn cache_key(&self) -> Result<Option<[u8; 32]>> {
+        let Some(key_file) = &self.cache_key_file else {
+            return Ok(None);
+        };
+
+        let key_file = expand_path(&key_file.to_string_lossy())?;
+        let secret = std::fs::read(&key_file)
+            .map_err(|e| anyhow!("Failed to read cache key file {key_file:#?}, {e}"))?;
+
+        if secret.is_empty() {
+            return Err(anyhow!("Cache key file {key_file:#?} is empty"));
+        }
+
+        Ok(Some(blake3::derive_key(Self::CACHE_KEY_CONTEXT, &secret)))
+    }
+
+    /// Parses the configured cache permissions, the file mode and the directory mode
+    pub fn cache_modes(&self) -> Result<(Option<u32>, Option<u32>)> {
+        let parse = |name, mode: &Option<String>| {
+            mode.as_deref()
+                .map(|m| parse_mode(m).map_err(|e| anyhow!("{name} {e}")))
+        };
+
+        Ok((
+            parse("cache_file_mode", &self.cache_file_mode).transpose()?,
+            parse("cache_dir_mode", &self.cache_dir_mode).transpose()?,
+        ))
+    }
+
+    /// Decodes the configured repository keys
+    pub fn repo_keys(&self) -> Result<BTreeMap<String, [u8; 32]>> {
+        let mut keys = BTreeMap::new();
+
+        for (repo, key) in &self.repo_keys {
+            let key = decode_hex(key.trim())
+                .ok()
+                .and_then(|k| <[u8; 32]>::try_from(k).ok())
+                .ok_or(anyhow!(
+                    "Key of repository {repo} is not a hex encoded Ed25519 public key"
+                ))?;
+
+            keys.insert(repo.clone(), key);
+        }
+
+        Ok(keys)
+    }
+
+    pub fn download_limits(&self) -> DownloadLimits {
+        DownloadLimits {
+            max_size: self.max_source_size,
+            connect_timeout: Duration::from_secs(self.connect_timeout),
+            transfer_timeout: self.transfer_timeout.map(Duration::from_secs),
+            retries: self.download_retries,
+            max_redirects: self.max_redirects,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let config_s = std::fs::read_to_string(path)?;
+        let config: Self = serde_yaml::from_str(&config_s)?;
+        Ok(config)
+    }
+}
@@ -0,0 +1,419 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Write},
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::SystemTime,
+};
+
+use tetra_pkgmgr::{
+    TetraRoot,
+    config::Config,
+    model::{Checksum, PackageId, RecipeSource},
+    net::{Download, Downloader, check_url_security},
+    repo::{Repository, SignedIndex},
+    store::{Cache, CacheStatus, CacheUseScope},
+    util::{is_interrupted, print_error, print_warn},
+};
+
+use crate::output;
+
+/// A request line, e.g. `{"op": "fetch", "package": "core/zlib@1.3"}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+enum Request {
+    Ping,
+    Resolve {
+        package: String,
+
+        #[serde(default)]
+        exact: bool,
+    },
+    Fetch {
+        package: String,
+
+        #[serde(default)]
+        exact: bool,
+    },
+}
+
+/// The response line to a request, `ok` is false if it failed with `error`
+#[derive(Debug, Default, Serialize)]
+struct Response {
+    ok: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recipe: Option<PathBuf>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+
+    /// Arch of the resolved recipe, unset for an arch-less recipe
+    #[serde(skip_serializing_if = "Option::is_none")]
+    arch: Option<String>,
+
+    /// Cached sources of a fetched package
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sources: Option<Vec<FetchedSource>>,
+
+    /// Optional sources of a fetched package that could not be fetched
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    skipped: Vec<SkippedSource>,
+}
+
+#[derive(Debug, Serialize)]
+struct FetchedSource {
+    url: String,
+    hash: String,
+    path: PathBuf,
+
+    /// Whether the file at `path` is stored zstd compressed, clients then decompress it
+    compressed: bool,
+
+    /// Whether the source was downloaded for this request rather than found in the cache
+    fetched: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct SkippedSource {
+    url: String,
+    error: String,
+}
+
+/// The repositories held in memory, reloaded once their files change on disk
+#[derive(Debug)]
+struct Repos {
+    repos: Vec<Repository>,
+    stamp: Vec<Option<SystemTime>>,
+}
+
+struct Daemon<'a> {
+    tetra_root: &'a TetraRoot,
+    config: &'a Config,
+    repos: RwLock<Repos>,
+}
+
+/// Serves resolve and fetch requests on a Unix socket until interrupted. Every line a
+/// client sends is a JSON request, answered by one JSON line.
+pub fn run(tetra_root: &TetraRoot, config: &Config, socket: Option<PathBuf>) {
+    let socket = socket.unwrap_or_else(|| tetra_root.get_daemon_socket_path());
+
+    let repos = match load_repos(tetra_root, config) {
+        Ok(r) => r,
+        Err(e) => {
+            print_error(&format!("Failed to load repositories: {e}"));
+            return;
+        }
+    };
+
+    let listener = match bind(&socket) {
+        Ok(l) => l,
+        Err(e) => {
+            print_error(&format!("Failed to listen on {socket:#?}: {e}"));
+            return;
+        }
+    };
+
+    println!("Loaded {} repositories", repos.repos.len());
+    println!("Listening on {socket:#?}");
+
+    let daemon = Daemon {
+        tetra_root,
+        config,
+        repos: RwLock::new(repos),
+    };
+
+    std::thread::scope(|scope| {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let daemon = &daemon;
+                    scope.spawn(move || daemon.serve(stream));
+                }
+                Err(e) => print_warn(&format!("Failed to accept a connection, {e}")),
+            }
+        }
+    });
+}
+
+/// Listens on the socket, replacing a socket file left behind by a daemon that is gone.
+/// Anything else at the path is left alone.
+fn bind(socket: &Path) -> Result<UnixListener> {
+    match std::fs::symlink_metadata(socket) {
+        Ok(meta) if meta.file_type().is_socket() => {
+            if UnixStream::connect(socket).is_ok() {
+                return Err(anyhow!("Another daemon is listening on it"));
+            }
+
+            std::fs::remove_file(socket)?;
+        }
+        Ok(_) => return Err(anyhow!("It exists and is not a socket")),
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(UnixListener::bind(socket)?)
+}
+
+impl Daemon<'_> {
+    fn serve(&self, stream: UnixStream) {
+        let mut writer = &stream;
+
+        for line in BufReader::new(&stream).lines() {
+            let Ok(line) = line else {
+                return;
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            // Blobs a request uses are only kept from eviction until it is answered
+            let _scope = CacheUseScope::enter();
+            let response = match serde_json::from_str(&line) {
+                Ok(request) => self.handle(request),
+                Err(e) => Err(anyhow!("Invalid request, {e}")),
+            };
+
+            let response = response.unwrap_or_else(|e| Response {
+                error: Some(e.to_string()),
+                ..Default::default()
+            });
+
+            // A download cut short by Ctrl-C stops the daemon once it answered
+            if is_interrupted() {
+                crate::exit_interrupted();
+            }
+
+            let Ok(mut json) = serde_json::to_string(&response) else {
+                return;
+            };
+            json.push('\n');
+
+            // A client that went away is not worth reporting
+            if writer.write_all(json.as_bytes()).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn handle(&self, request: Request) -> Result<Response> {
+        match request {
+            Request::Ping => Ok(Response {
+                ok: true,
+                ..Default::default()
+            }),
+            Request::Resolve { package, exact } => self.resolve(package, exact),
+            Request::Fetch { package, exact } => self.fetch(package, exact),
+        }
+    }
+
+    fn resolve(&self, package: String, exact: bool) -> Result<Response> {
+        let id = PackageId::from_id_str(package);
+        let arches = self.tetra_root.get_arch_preference();
+
+        self.with_repo(&id.repo, |repo| {
+            let path = repo.resolve_package_id(&id, &arches, exact)?;
+            let recipe = repo.load_recipe(&path)?;
+
+            Ok(Response {
+                ok: true,
+                arch: Repository::recipe_arch(&path),
+                recipe: Some(path),
+                name: Some(recipe.name),
+                version: Some(recipe.version),
+                ..Default::default()
+            })
+        })
+    }
+
+    fn fetch(&self, package: String, exact: bool) -> Result<Response> {
+        let id = PackageId::from_id_str(package);
+        let arches = self.tetra_root.get_arch_preference();
+
+        let recipe = self.with_repo(&id.repo, |repo| {
+            let path = repo.resolve_package_id(&id, &arches, exact)?;
+            repo.load_recipe(&path)
+        })?;
+
+        let cache = self.tetra_root.cache()?.for_recipe(&recipe);
+        let mut sources = Vec::new();
+        let mut skipped = Vec::new();
+
+        for source in &recipe.sources {
+            let (hash, fetched) = match self.fetch_source(&cache, source, &id) {
+                Ok(fetched) => fetched,
+                Err(e) if source.optional && !is_interrupted() => {
+                    let url = output::display_url(&source.url);
+                    print_warn(&format!("Skipping optional source {url}, {e}"));
+                    skipped.push(SkippedSource {
+                        url,
+                        error: e.to_string(),
+                    });
+                    continue;
+                }
+                Err(e) => {
+                    return Err(anyhow!(
+                        "Failed to fetch {}, {e}",
+                        output::display_url(&source.url)
+                    ));
+                }
+            };
+
+            let path = cache.blob_path(hash);
+            sources.push(FetchedSource {
+                url: output::display_url(&source.url),
+                hash: hash.to_string(),
+                compressed: Cache::is_compressed(&path),
+                path,
+                fetched,
+            });
+        }
+
+        Ok(Response {
+            ok: true,
+            name: Some(recipe.name),
+            version: Some(recipe.version),
+            sources: Some(sources),
+            skipped,
+            ..Default::default()
+        })
+    }
+
+    /// Makes sure a source is cached, returning its hash and whether it was downloaded
+    fn fetch_source(
+        &self,
+        cache: &Cache,
+        source: &RecipeSource,
+        id: &PackageId,
+    ) -> Result<(blake3::Hash, bool)> {
+        // Mutable sources are revalidated against their last download on every request
+        let previous = if source.mutable {
+            match cache.read_mutable_record(&source.url)? {
+                Some(p) => match cache.validate(blake3::Hash::from_hex(&p.hash)?)? {
+                    CacheStatus::Valid => Some(p),
+                    _ => None,
+                },
+                None => None,
+            }
+        } else {
+            let hash = source.checksum()?;
+            if cache.validate(hash)?.is_valid() {
+                return Ok((hash, false));
+            }
+            None
+        };
+
+        check_url_security(source, self.config)?;
+
+        let mut downloader = Downloader::new(self.tetra_root, source, id)?
+            .with_limits(self.config.download_limits())
+            .with_mirror_strategy(self.config.mirror_strategy);
+
+        match downloader.download(previous.as_ref())? {
+            Download::NotModified => {
+                let Some(previous) = previous else {
+                    return Err(anyhow!("Server answered not modified to a plain request"));
+                };
+                Ok((blake3::Hash::from_hex(&previous.hash)?, false))
+            }
+            Download::Fetched(provenance) => {
+                downloader.send_to_cache(cache, &provenance)?;
+
+                if source.mutable {
+                    cache.write_mutable_record(&provenance)?;
+                }
+
+                Ok((blake3::Hash::from_hex(&provenance.hash)?, true))
+            }
+        }
+    }
+
+    /// Runs `f` on the repository with the given id, reloading the repositories first
+    /// if any of them changed on disk
+    fn with_repo<T>(&self, id: &str, f: impl FnOnce(&Repository) -> Result<T>) -> Result<T> {
+        self.refresh()?;
+
+        let repos = self
+            .repos
+            .read()
+            .map_err(|_| anyhow!("Repository state is poisoned"))?;
+
+        let repo = repos
+            .repos
+            .iter()
+            .find(|r| r.id == id)
+            .ok_or(anyhow!("Cannot find repository with ID {id}"))?;
+
+        f(repo)
+    }
+
+    fn refresh(&self) -> Result<()> {
+        let outdated = {
+            let repos = self
+                .repos
+                .read()
+                .map_err(|_| anyhow!("Repository state is poisoned"))?;
+            repos.stamp != stamp(self.tetra_root, &repos.repos)
+        };
+
+        if !outdated {
+            return Ok(());
+        }
+
+        let mut repos = self
+            .repos
+            .write()
+            .map_err(|_| anyhow!("Repository state is poisoned"))?;
+
+        // Another request may have reloaded them while this one waited
+        if repos.stamp != stamp(self.tetra_root, &repos.repos) {
+            *repos = load_repos(self.tetra_root, self.config)?;
+            log::info!("Reloaded {} repositories", repos.repos.len());
+        }
+
+        Ok(())
+    }
+}
+
+fn load_repos(tetra_root: &TetraRoot, config: &Config) -> Result<Repos> {
+    let mut repos = tetra_root.repos()?;
+
+    if let Some(host) = &config.mirror_host {
+        for repo in &mut repos {
+            repo.pin_mirror(host)?;
+        }
+    }
+
+    let stamp = stamp(tetra_root, &repos);
+    Ok(Repos { repos, stamp })
+}
+
+/// Modification times of the files the loaded repositories come from. Recipes are read
+/// on every request, only the repository directories, metadata and archives are held.
+fn stamp(tetra_root: &TetraRoot, repos: &[Repository]) -> Vec<Option<SystemTime>> {
+    let mut paths = tetra_root.get_repo_dirs();
+
+    for repo in repos {
+        paths.push(repo.path.clone());
+        paths.push(repo.path.join("repo.yml"));
+        paths.push(repo.path.join(SignedIndex::FILE_NAME));
+        paths.push(repo.path.join(SignedIndex::SIGNATURE_FILE_NAME));
+    }
+
+    paths
+        .iter()
+        .map(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+        .collect()
+}
@@ -0,0 +1,210 @@
+use anyhow::Result;
+use serde::Serialize;
+
+use tetra_pkgmgr::{
+    TetraRoot,
+    model::{PackageId, Recipe},
+    repo::{ChangeKind, FieldChange, ListChange, RecipeDiff, Repository, Variant, VariantChange},
+    util::{Style, paint, print_error},
+};
+
+use crate::{OutputFormat, output, print_serialized};
+
+#[derive(Debug, Serialize)]
+struct Diff {
+    old: String,
+    new: String,
+
+    /// Flavour and arch recipes one version has and the other does not
+    variants: VariantChange,
+
+    /// Set when both recipes could be resolved for the arch
+    recipe: Option<RecipeDiff>,
+
+    /// Why the recipes could not be compared, e.g. as one version lacks the arch
+    error: Option<String>,
+}
+
+/// Compares the recipes of two package ids, usually two versions of a package
+pub fn run(
+    tetra_root: &TetraRoot,
+    old_id: String,
+    new_id: String,
+    exact: bool,
+    format: OutputFormat,
+) {
+    let old_id = PackageId::from_id_str(old_id);
+    let new_id = PackageId::from_id_str(new_id);
+    let arches = tetra_root.get_arch_preference();
+
+    let mut resolved = Vec::new();
+
+    for id in [&old_id, &new_id] {
+        let repo = match tetra_root.repo(&id.repo) {
+            Ok(Some(r)) => r,
+            Ok(None) => {
+                print_error(&format!("Cannot find repository with ID {}", id.repo));
+                return;
+            }
+            Err(e) => {
+                print_error(&format!("Failed to locate repositories: {e}"));
+                return;
+            }
+        };
+
+        let package = match repo.find_package(&id.name) {
+            Ok(Some(p)) => p,
+            Ok(None) => {
+                println!("Package with name {} could not be found", id.name);
+                return;
+            }
+            Err(e) => {
+                print_error(&format!("Failed to read package {}: {e}", id.name));
+                return;
+            }
+        };
+
+        let Some(version) = package.version(&id.version).cloned() else {
+            println!("Package {} has no version {}", id.name, id.version);
+            return;
+        };
+
+        resolved.push((version, load(&repo, id, &arches, exact)));
+    }
+
+    let (new_version, new_recipe) = resolved.pop().unwrap();
+    let (old_version, old_recipe) = resolved.pop().unwrap();
+
+    let (recipe, error) = match (old_recipe, new_recipe) {
+        (Ok(old), Ok(new)) => (Some(RecipeDiff::between(&old, &new)), None),
+        (Err(e), _) => (None, Some(format!("Failed to resolve {old_id}, {e}"))),
+        (_, Err(e)) => (None, Some(format!("Failed to resolve {new_id}, {e}"))),
+    };
+
+    let mut diff = Diff {
+        old: old_id.to_string(),
+        new: new_id.to_string(),
+        variants: VariantChange::between(&old_version, &new_version),
+        recipe,
+        error,
+    };
+
+    // Source URLs may carry credentials, even in the serialized output
+    for source in diff.recipe.iter_mut().flat_map(|r| &mut r.sources) {
+        for field in &mut source.fields {
+            if field.field == "url" || field.field == "sha256sums" {
+                field.old = field.old.as_deref().map(output::display_url);
+                field.new = field.new.as_deref().map(output::display_url);
+            }
+        }
+    }
+
+    if print_serialized(&diff, format) {
+        return;
+    }
+
+    println!("Old: {}", diff.old);
+    println!("New: {}", diff.new);
+
+    if !diff.variants.is_empty() {
+        println!("Variants:");
+        for variant in &diff.variants.added {
+            println!("    + {}", variant_label(variant));
+        }
+        for variant in &diff.variants.removed {
+            println!("    - {}", variant_label(variant));
+        }
+    }
+
+    if let Some(error) = &diff.error {
+        println!("Recipes: not compared, {error}");
+        return;
+    }
+
+    let Some(recipe) = &diff.recipe else {
+        return;
+    };
+
+    if recipe.is_empty() {
+        println!("Recipes: identical");
+        return;
+    }
+
+    for field in &recipe.fields {
+        println!("{}: {}", capitalize(&field.field), change_label(field));
+    }
+
+    print_list("Depends", &recipe.depends);
+    print_list("Conflicts", &recipe.conflicts);
+    print_list("Provides", &recipe.provides);
+
+    if !recipe.sources.is_empty() {
+        println!("Sources:");
+    }
+
+    for source in &recipe.sources {
+        let marker = match source.kind {
+            ChangeKind::Added => '+',
+            ChangeKind::Removed => '-',
+            ChangeKind::Changed => '~',
+        };
+
+        println!("    {marker} {}", source.reference);
+
+        for field in &source.fields {
+            println!("      {}: {}", field.field, change_label(field));
+        }
+
+        for warning in &source.warnings {
+            println!("      {} {warning}", paint("WARN:", Style::Warn));
+        }
+    }
+}
+
+fn load(repo: &Repository, id: &PackageId, arches: &[String], exact: bool) -> Result<Recipe> {
+    repo.resolve_package_id(id, arches, exact)
+        .and_then(|path| repo.load_recipe(&path))
+}
+
+fn print_list(title: &str, change: &ListChange) {
+    if change.is_empty() {
+        return;
+    }
+
+    println!("{title}:");
+    for added in &change.added {
+        println!("    + {added}");
+    }
+    for removed in &change.removed {
+        println!("    - {removed}");
+    }
+}
+
+fn change_label(change: &FieldChange) -> String {
+    format!(
+        "{} -> {}",
+        change.old.as_deref().unwrap_or("-"),
+        change.new.as_deref().unwrap_or("-")
+    )
+}
+
+fn variant_label(variant: &Variant) -> String {
+    let flavours = if variant.flavours.is_empty() {
+        "no flavours".to_string()
+    } else {
+        format!("flavours {}", variant.flavours.join(":"))
+    };
+
+    match &variant.arch {
+        Some(arch) => format!("{flavours}, arch {arch}"),
+        None => format!("{flavours}, any arch"),
+    }
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
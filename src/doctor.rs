@@ -0,0 +1,284 @@
+use std::{
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tetra_pkgmgr::{
+    ArchSource, LAYOUT_VERSION, TetraRoot, config::Config, net::probe_url, repo::Repository,
+};
+
+use crate::output::{self, Report, Status};
+
+/// Longest the network check takes when no transfer timeout is configured
+const NETWORK_CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Runs all environment checks, returning false if any of them failed
+pub fn run(tetra_root: &TetraRoot, config: &Config) -> bool {
+    let mut report = Report::default();
+
+    check_root(&mut report, tetra_root);
+    check_layout(&mut report, tetra_root);
+    check_dir(&mut report, "Cache", &tetra_root.get_cache_dir());
+    check_dir(&mut report, "Temp", &tetra_root.get_temp_dir_path());
+    check_arch(&mut report, tetra_root);
+    check_repos(&mut report, tetra_root);
+    check_network(&mut report, config);
+
+    !report.failed
+}
+
+fn is_writable(dir: &Path) -> bool {
+    let probe = dir.join(".tetra-doctor-probe");
+
+    let created = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .is_ok();
+
+    if created {
+        let _ = std::fs::remove_file(&probe);
+    }
+
+    created
+}
+
+fn check_root(report: &mut Report, tetra_root: &TetraRoot) {
+    let root = &tetra_root.root;
+
+    if !root.is_dir() {
+        report.check(
+            Status::Fail,
+            &format!("Tetra root {} does not exist", root.display()),
+            Some("Create the directory or select an existing root with --root or TETRA_ROOT"),
+        );
+        return;
+    }
+
+    if !is_writable(root) {
+        report.check(
+            Status::Fail,
+            &format!("Tetra root {} is not writable", root.display()),
+            Some("Run tetra as a user with write access to the root"),
+        );
+        return;
+    }
+
+    report.check(
+        Status::Pass,
+        &format!("Tetra root {} is writable", root.display()),
+        None,
+    );
+}
+
+fn check_layout(report: &mut Report, tetra_root: &TetraRoot) {
+    match tetra_root.layout_version() {
+        Ok(version) if version < LAYOUT_VERSION => report.check(
+            Status::Warn,
+            &format!("Root layout is version {version}, the current version is {LAYOUT_VERSION}"),
+            Some("Run tetra migrate to upgrade it"),
+        ),
+        Ok(version) if version > LAYOUT_VERSION => report.check(
+            Status::Fail,
+            &format!("Root layout is version {version}, newer than version {LAYOUT_VERSION} of this tetra"),
+            Some("Upgrade tetra"),
+        ),
+        Ok(version) => report.check(
+            Status::Pass,
+            &format!("Root layout is version {version}"),
+            None,
+        ),
+        Err(e) => report.check(Status::Fail, &e.to_string(), None),
+    }
+}
+
+fn check_dir(report: &mut Report, name: &str, dir: &Path) {
+    if !dir.is_dir() {
+        report.check(
+            Status::Warn,
+            &format!("{name} directory {} does not exist", dir.display()),
+            Some("It is created on first use, make sure its parent is writable"),
+        );
+        return;
+    }
+
+    if !is_writable(dir) {
+        report.check(
+            Status::Fail,
+            &format!("{name} directory {} is not writable", dir.display()),
+            Some("Fix the permissions of the directory"),
+        );
+        return;
+    }
+
+    report.check(
+        Status::Pass,
+        &format!("{name} directory {} is writable", dir.display()),
+        None,
+    );
+}
+
+fn check_arch(report: &mut Report, tetra_root: &TetraRoot) {
+    let (arch, source) = tetra_root.resolve_default_arch();
+
+    if source == ArchSource::Detected {
+        let hint = format!(
+            "Write the architecture name to {} to set it explicitly",
+            tetra_root.root.join("arch").display()
+        );
+        report.check(
+            Status::Warn,
+            &format!("Default architecture {arch} was auto-detected"),
+            Some(&hint),
+        );
+        return;
+    }
+
+    let fallbacks = tetra_root.get_arch_preference().split_off(1);
+    let message = if fallbacks.is_empty() {
+        format!("Default architecture is {arch} (from {source})")
+    } else {
+        format!(
+            "Default architecture is {arch}, falling back to {} (from {source})",
+            fallbacks.join(", ")
+        )
+    };
+
+    report.check(Status::Pass, &message, None);
+}
+
+fn check_repos(report: &mut Report, tetra_root: &TetraRoot) {
+    let repo_dirs = tetra_root.get_repo_dirs();
+    let mut loaded: Vec<(String, PathBuf)> = Vec::new();
+
+    for repo_dir in &repo_dirs {
+        let entries = match std::fs::read_dir(repo_dir) {
+            Ok(e) => e,
+            Err(e) => {
+                report.check(
+                    Status::Fail,
+                    &format!(
+                        "Repository directory {} cannot be read, {e}",
+                        repo_dir.display()
+                    ),
+                    Some("Create the directory and add at least one repository to it"),
+                );
+                continue;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !path.is_dir() && !Repository::is_archive_path(&path) {
+                continue;
+            }
+
+            if let Ok(id) = Repository::path_id(&path)
+                && let Some((_, shadowing)) = loaded.iter().find(|(loaded_id, _)| *loaded_id == id)
+            {
+                report.check(
+                    Status::Pass,
+                    &format!(
+                        "Repository {id} in {} is shadowed by {}",
+                        path.display(),
+                        shadowing.display()
+                    ),
+                    None,
+                );
+                continue;
+            }
+
+            match Repository::from_path(&path) {
+                Ok(mut repo) => {
+                    report.check(
+                        Status::Pass,
+                        &format!("Repository {} ({}) loaded", repo.id, repo.name),
+                        None,
+                    );
+                    check_signature(report, tetra_root, &mut repo);
+                    loaded.push((repo.id, path));
+                }
+                Err(e) => report.check(
+                    Status::Warn,
+                    &format!("Repository {} failed to load, {e}", path.display()),
+                    Some("Check that the directory contains a valid repo.yml"),
+                ),
+            }
+        }
+    }
+
+    if loaded.is_empty() {
+        let dirs = repo_dirs
+            .iter()
+            .map(|d| d.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        report.check(
+            Status::Fail,
+            &format!("No repositories found in {dirs}"),
+            Some("Add a repository directory containing a repo.yml and a pkgs directory"),
+        );
+    }
+}
+
+fn check_signature(report: &mut Report, tetra_root: &TetraRoot, repo: &mut Repository) {
+    let Some(key) = tetra_root.repo_keys.get(&repo.id) else {
+        let message = if repo.is_signed() {
+            format!(
+                "Repository {} is signed, but has no key configured",
+                repo.id
+            )
+        } else {
+            format!("Repository {} is not signed", repo.id)
+        };
+        report.check(
+            Status::Warn,
+            &message,
+            Some("Its recipes are not verified, add its public key to repo_keys"),
+        );
+        return;
+    };
+
+    match repo.verify_signature(key) {
+        Ok(()) => report.check(
+            Status::Pass,
+            &format!("Repository {} signature verified", repo.id),
+            None,
+        ),
+        Err(e) => report.check(
+            Status::Fail,
+            &e.to_string(),
+            Some("Its recipes are refused until the signed index matches"),
+        ),
+    }
+}
+
+fn check_network(report: &mut Report, config: &Config) {
+    let Some(url) = &config.doctor_url else {
+        report.check(
+            Status::Warn,
+            "Network check skipped, no test URL configured",
+            Some("Set doctor_url in the configuration to enable it"),
+        );
+        return;
+    };
+
+    // The check is made like downloads are, but never waits on a stalled server for long
+    let mut limits = config.download_limits();
+    limits.transfer_timeout = limits.transfer_timeout.or(Some(NETWORK_CHECK_TIMEOUT));
+
+    let result = probe_url(url, &limits, true);
+
+    let url = output::display_url(url);
+
+    match result {
+        Ok(_) => report.check(Status::Pass, &format!("{url} is reachable"), None),
+        Err(e) => report.check(
+            Status::Fail,
+            &format!("{url} is not reachable, {e}"),
+            Some("Check your network connection and proxy settings"),
+        ),
+    }
+}
@@ -0,0 +1,9 @@
+pub mod config;
+pub mod model;
+pub mod net;
+pub mod repo;
+pub mod root;
+pub mod store;
+pub mod util;
+
+pub use root::{ArchSource, LAYOUT_VERSION, Migration, TetraRoot};
@@ -1,411 +1,2712 @@
-mod model;
-mod store;
+mod check_sources;
+#[cfg(unix)]
+mod daemon;
+mod diff;
+mod doctor;
+mod output;
+mod recipe_hash;
+mod tree;
+mod verify;
 
 use anyhow::{Result, anyhow};
-use curl::easy::Easy;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use indicatif::{ProgressBar, ProgressStyle};
-use serde::Deserialize;
+use serde::Serialize;
 use std::{
-    fs::File,
-    io::Write,
+    io::{IsTerminal, Write},
     path::{Path, PathBuf},
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use crate::{
-    model::{Checksum, PackageId, Recipe, Source},
-    store::Cache,
+use tetra_pkgmgr::{
+    ArchSource, LAYOUT_VERSION, TetraRoot,
+    config::{Config, MirrorStrategy, NonInteractive, PreferCache, parse_duration},
+    model::{Checksum, LintIssue, PackageId, PackageQuery, Recipe, RecipeSource},
+    net::{
+        Download, Downloader, check_url_security, persist_mirror_health, reset_mirror_health,
+        set_ipfs_gateways, set_redirect_policy,
+    },
+    repo::{
+        RecipeChange, Repository, RepositoryIndex, SignedIndex, SourceReferences, changed_since,
+        resolve_closure,
+    },
+    store::{Cache, CacheStatus, Provenance, TempFile},
+    util::{
+        Style, UserDirs, confirm, expand_path, format_bytes, format_unix_time, init_color,
+        interrupt, is_interrupted, paint, parallel_map, parse_unix_time, print_error, print_warn,
+        print_warn_to_stderr, set_max_parallel, set_messages_to_stderr, set_url_env_vars,
+    },
 };
 
-#[derive(Debug)]
-struct TetraRoot {
-    pub root: PathBuf,
+#[derive(Debug, Parser)]
+#[command(name = "tetra", about = "The Tetra package manager")]
+struct Cli {
+    /// Answer yes to all confirmation prompts
+    #[arg(short, long, global = true)]
+    yes: bool,
+
+    /// Hide progress bars and download summaries
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Refuse to download sources that are not fetched over https
+    #[arg(long, global = true)]
+    require_https: bool,
+
+    /// Tetra root to operate on, takes precedence over TETRA_ROOT and the config
+    #[arg(long, alias = "tetra-root", global = true)]
+    root: Option<PathBuf>,
+
+    /// Per-user install: read the config and arch file from $XDG_CONFIG_HOME/tetra, keep
+    /// the root in $XDG_DATA_HOME/tetra and the cache in $XDG_CACHE_HOME/tetra. Also set
+    /// by TETRA_USER=1. A root chosen with --root, TETRA_ROOT or the config is used whole.
+    #[arg(long, global = true)]
+    user: bool,
+
+    /// Fetch sources relative to a repository base URL from this host instead
+    #[arg(long, global = true)]
+    mirror_host: Option<String>,
+
+    /// Order the URL and mirrors of a source are tried in: ordered, fastest or random
+    #[arg(long, global = true)]
+    mirror_strategy: Option<MirrorStrategy>,
+
+    /// Forget which mirrors were found down, so they are tried again right away
+    #[arg(long, global = true)]
+    reset_mirror_health: bool,
+
+    /// Default architecture, or a comma separated list in order of preference, takes
+    /// precedence over TETRA_ARCH and the arch files
+    #[arg(long, global = true)]
+    arch: Option<String>,
+
+    /// Search for repositories in this directory instead of the one in the tetra root,
+    /// may be repeated with earlier directories taking precedence
+    #[arg(long, global = true)]
+    repo_dir: Vec<PathBuf>,
+
+    /// Use a cache directory outside of the tetra root
+    #[arg(long, global = true)]
+    cache_dir: Option<PathBuf>,
+
+    /// Largest number of concurrent operations, defaults to the number of CPUs
+    #[arg(long, global = true)]
+    max_parallel: Option<usize>,
+
+    /// Print without colors, also disabled by NO_COLOR or when not printing to a terminal
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Print URLs in full instead of redacting credentials in them, for debugging
+    #[arg(long, global = true)]
+    show_secrets: bool,
+
+    /// Serve download and cache counters for Prometheus on this address, e.g.
+    /// 127.0.0.1:9464, while the command runs
+    #[cfg(feature = "metrics")]
+    #[arg(long, global = true)]
+    metrics_listen: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
 }
 
-impl TetraRoot {
-    const DEFAULT_TETRA_ROOT: &str = "/var/tetra";
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Resolve a package and fetch its sources into the cache
+    Fetch(FetchArgs),
+
+    /// Show a package recipe and the cache state of its sources
+    Info {
+        /// Package id, e.g. repo/name@version:flavour#arch
+        package_id: String,
+
+        /// Require an exact recipe match, disabling the version prefix, default-arch and
+        /// arch-less fallbacks
+        #[arg(long)]
+        exact: bool,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+
+    /// Print the recipe tetra acts on for a package, with the recipes it extends and its
+    /// sources file merged in and its source URLs resolved. Environment references in the
+    /// URLs are only filled in with --show-secrets.
+    DumpRecipe {
+        /// Package id, e.g. repo/name@version:flavour#arch
+        package_id: String,
+
+        /// Require an exact recipe match, disabling the version prefix, default-arch and
+        /// arch-less fallbacks
+        #[arg(long)]
+        exact: bool,
+
+        /// Text output is YAML as well
+        #[arg(long, value_enum, default_value_t = OutputFormat::Yaml)]
+        format: OutputFormat,
+    },
+
+    /// List every source needed to build a package and its dependencies
+    Closure {
+        /// Package id, e.g. repo/name@version:flavour#arch
+        package_id: String,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+
+    /// Print the dependency tree of a package
+    Tree {
+        /// Package id, e.g. repo/name@version:flavour#arch
+        package_id: String,
+
+        /// Only show this many levels of dependencies
+        #[arg(long)]
+        depth: Option<usize>,
+
+        /// Repeat the dependencies of packages that are reached more than once
+        #[arg(long)]
+        duplicates: bool,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+
+    /// Compare the recipes of two package ids, e.g. two versions of a package
+    Diff {
+        /// Package id to compare from, e.g. repo/name@1.2
+        old_id: String,
+
+        /// Package id to compare to, e.g. repo/name@1.3
+        new_id: String,
+
+        /// Require an exact recipe match, disabling the version prefix, default-arch and
+        /// arch-less fallbacks
+        #[arg(long)]
+        exact: bool,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+
+    /// List every recipe matching a partial package id
+    Resolve {
+        /// Package id with optional version, flavours and arch, e.g. repo/name@version
+        query: String,
+    },
+
+    /// List the packages that declare they install a file
+    Provides {
+        /// Absolute path of the file, e.g. /usr/bin/foo
+        path: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+
+    /// Remove leftover temp files and garbage from the cache
+    Clean(CleanArgs),
+
+    /// Check the tetra environment for common problems
+    Doctor,
+
+    /// Upgrade the tetra root to the current on-disk layout
+    Migrate {
+        /// Only report what would be changed
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Keep the repositories loaded and serve resolve and fetch requests on a Unix socket
+    #[cfg(unix)]
+    Daemon {
+        /// Socket to listen on, daemon.sock in the tetra root by default
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+
+    /// Check that every source of a package can still be downloaded, without downloading it
+    CheckSources {
+        /// Package id, e.g. repo/name@version:flavour#arch
+        package_id: String,
+
+        /// Require an exact recipe match, disabling the version prefix, default-arch and
+        /// arch-less fallbacks
+        #[arg(long)]
+        exact: bool,
+
+        /// Only check the source with this index, name, URL or unique part of its URL
+        #[arg(long)]
+        source: Option<String>,
+    },
+
+    /// Check the cached sources of a package against its recipe, without downloading
+    Verify {
+        /// Package id, e.g. repo/name@version:flavour#arch
+        package_id: String,
+
+        /// Require an exact recipe match, disabling the version prefix, default-arch and
+        /// arch-less fallbacks
+        #[arg(long)]
+        exact: bool,
+
+        /// Only check the source with this index, name, URL or unique part of its URL
+        #[arg(long)]
+        source: Option<String>,
+    },
+
+    /// Export recipe metadata for search tools
+    Index {
+        #[command(subcommand)]
+        command: IndexCommand,
+    },
+
+    /// Help writing recipes
+    Recipe {
+        #[command(subcommand)]
+        command: RecipeCommand,
+    },
+
+    /// Inspect the configured repositories
+    Repo {
+        #[command(subcommand)]
+        command: RepoCommand,
+    },
+
+    /// Inspect the source cache
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+}
 
-    fn get_tetra_root() -> PathBuf {
-        if cfg!(debug_assertions) {
-            let root = std::env::var("TETRA_ROOT").unwrap_or(Self::DEFAULT_TETRA_ROOT.to_string());
-            return PathBuf::from(root);
-        }
+#[derive(Debug, Args)]
+struct FetchArgs {
+    /// Package id, e.g. repo/name@version:flavour#arch
+    package_id: String,
+
+    /// Require an exact recipe match, disabling the version prefix, default-arch and
+    /// arch-less fallbacks
+    #[arg(long)]
+    exact: bool,
+
+    /// Only fetch the source with this index, name, URL or unique part of its URL
+    #[arg(long)]
+    source: Option<String>,
+
+    /// Download the selected source to this path instead of the cache
+    #[arg(long)]
+    output: Option<PathBuf>,
+
+    /// Stream the selected source to stdout instead of the cache, verifying it as it is
+    /// written. The exit status is 1 if it failed, and the output must be discarded.
+    #[arg(long, requires = "source", conflicts_with_all = ["output", "refresh", "no_cache"])]
+    stdout: bool,
+
+    /// Download sources again even if they are cached, replacing the cached copy
+    #[arg(long)]
+    refresh: bool,
+
+    /// Download and verify sources in the temp directory without adding them to the cache
+    #[arg(long, conflicts_with = "output")]
+    no_cache: bool,
+
+    /// When a cached mutable source is used without revalidating it: always, never or
+    /// age:DURATION, e.g. age:12h. Sources with a hash always come from the cache.
+    #[arg(long, default_value = "never", conflicts_with_all = ["refresh", "no_cache"])]
+    prefer_cache: PreferCache,
+
+    /// Print how the cache was looked up for each source and what was decided because
+    /// of it, e.g. whether a cached file matched its hash
+    #[arg(long)]
+    explain_cache: bool,
+}
 
-        PathBuf::from(Self::DEFAULT_TETRA_ROOT)
-    }
+#[derive(Debug, Args)]
+struct CleanArgs {
+    /// Sweep the temp directory, this is the default when nothing is selected
+    #[arg(long)]
+    temp: bool,
 
-    pub fn new() -> Self {
-        Self {
-            root: Self::get_tetra_root(),
-        }
-    }
+    /// Remove corrupt and partially copied blobs from the cache
+    #[arg(long)]
+    cache: bool,
 
-    pub fn repos(&self) -> Result<Vec<Repository>> {
-        let mut repos = Vec::new();
-        let repo_dir = self.root.join("repo");
+    /// Clean both the temp directory and the cache
+    #[arg(long)]
+    all: bool,
 
-        let paths = std::fs::read_dir(repo_dir)?;
-        for path in paths {
-            let path = path?;
-            if path.path().is_dir() {
-                let repo = Repository::from_path(&path.path())?;
-                repos.push(repo);
-            }
-        }
+    /// Only report what would be removed
+    #[arg(long)]
+    dry_run: bool,
+}
 
-        Ok(repos)
+impl Command {
+    /// Whether the command prints data serialized as JSON or YAML, or an index
+    fn prints_serialized(&self) -> bool {
+        let serialized = |format: &OutputFormat| *format != OutputFormat::Text;
+
+        match self {
+            Command::Info { format, .. }
+            | Command::DumpRecipe { format, .. }
+            | Command::Closure { format, .. }
+            | Command::Tree { format, .. }
+            | Command::Diff { format, .. }
+            | Command::Provides { format, .. } => serialized(format),
+            Command::Repo {
+                command: RepoCommand::List { format, json },
+            } => *json || serialized(format),
+            Command::Repo {
+                command: RepoCommand::Changed { format, .. },
+            } => serialized(format),
+            Command::Index {
+                command: IndexCommand::Export { output, .. },
+            } => output.is_none(),
+            _ => false,
+        }
     }
+}
 
-    pub fn cache(&self) -> Result<Cache> {
-        let cache_dir = self.root.join("cache");
+#[derive(Debug, Subcommand)]
+enum IndexCommand {
+    /// Write the metadata of every recipe in a repository without fetching sources
+    Export {
+        /// Id of the repository to export
+        repo: String,
+
+        #[arg(long, value_enum, default_value_t = IndexFormat::Json)]
+        format: IndexFormat,
+
+        /// Write the index to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Write the recipe hashes of a repository directory, to be signed as its recipes.index
+    Recipes {
+        /// Repository directory, containing repo.yml and pkgs
+        path: PathBuf,
+
+        /// Write the index to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
 
-        if !cache_dir.is_dir() {
-            std::fs::create_dir_all(&cache_dir)?;
-        }
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum IndexFormat {
+    Json,
+    Yaml,
+    Msgpack,
+}
 
-        Ok(Cache { cache_dir })
-    }
+/// Output of commands printing structured data, the same data is serialized for every format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+    Yaml,
+}
 
-    pub fn get_temp_dir(&self) -> Result<PathBuf> {
-        let tmp_dir = self.root.join("tmp");
+#[derive(Debug, Subcommand)]
+enum RecipeCommand {
+    /// Download sources and print their sources block with the hashes filled in
+    Hash {
+        /// Partial recipe whose sources lack a hash, or a file with one URL per line
+        file: PathBuf,
+
+        /// Also compute the sha256 of each source
+        #[arg(long)]
+        sha256: bool,
+
+        /// Write the sources block to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Check recipe files and report every problem in them, not only the first
+    Lint {
+        /// Recipe files to check
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+}
 
-        if !tmp_dir.is_dir() {
-            std::fs::create_dir_all(&tmp_dir)?;
-        }
+#[derive(Debug, Subcommand)]
+enum RepoCommand {
+    /// List the repositories of the tetra root
+    List {
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+
+        /// Print the list as JSON, short for --format json
+        #[arg(long, conflicts_with = "format")]
+        json: bool,
+    },
+
+    /// List the packages of a repository whose recipe files changed recently
+    Changed {
+        /// Repository id
+        repo: String,
+
+        /// Cutoff as a duration before now, e.g. 7d, or a UTC date or timestamp such as
+        /// 2026-10-01 or 2026-10-01T12:00:00Z
+        #[arg(long, value_parser = parse_since)]
+        since: SystemTime,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum CacheCommand {
+    /// Add a locally built file to the cache so it is not downloaded
+    Add {
+        /// File to add
+        file: PathBuf,
+
+        /// Blake3 hash the file is expected to have
+        #[arg(long)]
+        hash: Option<String>,
+
+        /// Move the file into the cache instead of copying it
+        #[arg(long = "move")]
+        move_in: bool,
+    },
+
+    /// Print where and when a cached blob was downloaded from
+    Provenance {
+        /// Blake3 hash of the cached blob
+        hash: String,
+    },
+
+    /// Check cached blobs against their hashes, without removing corrupt ones
+    Verify {
+        /// Only check a random share of the blobs, e.g. 5%, and estimate the error rate
+        #[arg(long, value_parser = parse_percent, conflicts_with = "full")]
+        sample: Option<f64>,
+
+        /// Check every blob, this is the default
+        #[arg(long)]
+        full: bool,
+    },
+}
 
-        Ok(tmp_dir)
+/// Parses a percentage such as `5%` or `5` into a share between 0 and 1
+fn parse_percent(s: &str) -> Result<f64, String> {
+    let percent = s
+        .trim()
+        .trim_end_matches('%')
+        .parse::<f64>()
+        .map_err(|e| e.to_string())?;
+
+    if percent > 0.0 && percent <= 100.0 {
+        Ok(percent / 100.0)
+    } else {
+        Err("must be more than 0% and at most 100%".to_string())
     }
+}
 
-    pub fn get_default_arch(&self) -> String {
-        let arch_file = self.root.join("arch");
-        std::fs::read_to_string(arch_file)
-            .unwrap_or("".to_string())
-            .trim()
-            .to_string()
+/// Parses a cutoff given as a duration before now or as a UTC date or timestamp
+fn parse_since(s: &str) -> Result<SystemTime, String> {
+    if let Some(secs) = parse_unix_time(s) {
+        return Ok(UNIX_EPOCH + Duration::from_secs(secs));
     }
+
+    let age = parse_duration(s)
+        .map_err(|_| "expected a duration such as 7d or a date such as 2026-10-01".to_string())?;
+
+    SystemTime::now()
+        .checked_sub(age)
+        .ok_or("duration reaches back too far".to_string())
 }
 
-#[derive(Debug)]
-struct Downloader<'a, T> {
-    source: &'a T,
-    tmp_file: TempFile,
-    name: &'a str,
+/// Exit code of a run cut short by Ctrl-C, 128 plus the number of SIGINT
+const EXIT_INTERRUPTED: i32 = 130;
+
+fn exit_interrupted() -> ! {
+    output::restore_terminal();
+    TempFile::release_all();
+
+    println!("Interrupted.");
+    std::process::exit(EXIT_INTERRUPTED);
 }
 
-impl<'a, T> Downloader<'a, T>
-where
-    T: Source,
-{
-    pub fn new(root: &TetraRoot, source: &'a T, name: &'a str) -> Result<Self> {
-        let tmp_file = TempFile::new(root, source.checksum()?)?;
-        Ok(Self {
-            source,
-            tmp_file,
-            name,
-        })
+fn main() {
+    let cli = Cli::parse();
+    init_color(cli.no_color);
+
+    // Messages would end up in the middle of a source streamed to stdout
+    if let Command::Fetch(args) = &cli.command
+        && args.stdout
+    {
+        set_messages_to_stderr(true);
     }
 
-    pub fn download(&self) -> Result<()> {
-        let pb = ProgressBar::no_length();
-        pb.enable_steady_tick(Duration::from_millis(100));
-        pb.set_style(
-            ProgressStyle::with_template("{wide_msg:!} {percent:>3}% [{bar:25}] {bytes:>11} / {total_bytes:<11} {binary_bytes_per_sec:>13} ETA {eta_precise:8} ")
-                .unwrap()
-                .progress_chars("=> "),
-        );
+    // Diagnostics are off unless enabled with e.g. TETRA_LOG=debug
+    env_logger::Builder::from_env("TETRA_LOG")
+        .format_timestamp(None)
+        .init();
+
+    let user_dirs = if cli.user || UserDirs::enabled_by_env() {
+        match UserDirs::locate() {
+            Ok(dirs) => Some(dirs),
+            Err(e) => {
+                print_error(&e.to_string());
+                return;
+            }
+        }
+    } else {
+        None
+    };
 
-        pb.set_message(format!("{}/{}", self.name, self.source.checksum()?));
+    let mut config = match Config::load(user_dirs.as_ref()) {
+        Ok(c) => c,
+        Err(e) => {
+            print_error(&format!(
+                "Failed to load configuration {:#?}: {e}",
+                Config::get_config_path(user_dirs.as_ref())
+            ));
+            return;
+        }
+    };
 
-        let mut out_file = File::create(&self.tmp_file.path)?;
+    config.require_https |= cli.require_https;
 
-        let mut handle = Easy::new();
-        handle.url(&self.source.url())?;
-        handle.progress(true)?;
+    if cli.mirror_host.is_some() {
+        config.mirror_host = cli.mirror_host;
+    }
 
-        let mut transfer = handle.transfer();
+    if let Some(strategy) = cli.mirror_strategy {
+        config.mirror_strategy = strategy;
+    }
+    output::set_quiet(cli.quiet);
+    output::set_show_secrets(cli.show_secrets);
 
-        transfer.progress_function(|total, current, _, _| {
-            if total > 0.0 {
-                pb.set_length(total as u64);
-                pb.set_position(current as u64);
-            }
+    if let Some(max) = cli.max_parallel.or(config.max_parallel) {
+        set_max_parallel(max);
+    }
 
-            true
-        })?;
+    set_ipfs_gateways(config.ipfs_gateways.clone());
+    set_url_env_vars(config.url_env_vars.clone());
+    set_redirect_policy(&config);
 
-        transfer.write_function(|data| {
-            out_file.write_all(data).unwrap();
-            Ok(data.len())
-        })?;
+    #[cfg(feature = "metrics")]
+    if let Some(addr) = &cli.metrics_listen {
+        match tetra_pkgmgr::util::serve_metrics(addr) {
+            Ok(addr) => log::info!("Serving metrics on http://{addr}/metrics"),
+            Err(e) => {
+                print_error(&e.to_string());
+                return;
+            }
+        }
+    }
 
-        transfer.perform()?;
+    // A running download notices the interruption, stops and cleans up after itself
+    let handler = ctrlc::set_handler(|| {
+        if !interrupt() {
+            exit_interrupted();
+        }
+    });
 
-        pb.finish();
-        Ok(())
+    if let Err(e) = handler {
+        print_warn(&format!("Failed to install the Ctrl-C handler, {e}"));
     }
 
-    pub fn send_to_cache(&self, cache: &Cache) -> Result<()> {
-        cache.cache_tmp_file(&self.tmp_file, self.source.checksum()?)?;
+    let mut tetra_root = match TetraRoot::new(cli.root.as_deref(), &config, user_dirs) {
+        Ok(r) => r,
+        Err(e) => {
+            print_error(&format!("Failed to determine tetra root: {e}"));
+            return;
+        }
+    };
+
+    tetra_root.arch = cli.arch;
 
-        Ok(())
+    let health_path = tetra_root.get_mirror_health_path();
+
+    if cli.reset_mirror_health
+        && let Err(e) = reset_mirror_health(&health_path)
+    {
+        print_error(&e.to_string());
+        return;
     }
-}
 
-#[derive(Debug)]
-struct TempFile {
-    pub path: PathBuf,
-}
+    if config.persist_mirror_health
+        && let Err(e) = persist_mirror_health(&health_path)
+    {
+        print_warn(&e.to_string());
+    }
 
-impl TempFile {
-    pub fn new(root: &TetraRoot, hash: blake3::Hash) -> Result<Self> {
-        let mut path = root.get_temp_dir()?;
-        path.push(hash.to_string());
+    match config.cache_key() {
+        Ok(key) => tetra_root.cache_key = key,
+        Err(e) => {
+            print_error(&format!("Failed to load cache key: {e}"));
+            return;
+        }
+    }
 
-        Ok(Self { path })
+    match config.cache_modes() {
+        Ok((file_mode, dir_mode)) => {
+            tetra_root.cache_file_mode = file_mode;
+            tetra_root.cache_dir_mode = dir_mode;
+        }
+        Err(e) => {
+            print_error(&format!("Invalid cache permissions: {e}"));
+            return;
+        }
     }
-}
 
-impl Drop for TempFile {
-    fn drop(&mut self) {
-        if !self.path.is_file() {
+    match config.repo_keys() {
+        Ok(keys) => tetra_root.repo_keys = keys,
+        Err(e) => {
+            print_error(&format!("Failed to load repository keys: {e}"));
             return;
         }
+    }
 
-        if let Err(e) = std::fs::remove_file(&self.path) {
-            println!(
-                "WARN: Failed to remove temporary file {}, {e}",
-                self.path.display()
-            );
+    let repo_dirs = if cli.repo_dir.is_empty() {
+        config.repo_dirs.clone()
+    } else {
+        cli.repo_dir
+    };
+
+    for repo_dir in repo_dirs {
+        match expand_path(&repo_dir.to_string_lossy()) {
+            Ok(dir) => tetra_root.repo_dirs.push(dir),
+            Err(e) => {
+                print_error(&format!("Invalid repository directory {repo_dir:#?}: {e}"));
+                return;
+            }
+        }
+    }
+
+    if let Some(cache_dir) = cli.cache_dir.or(config.cache_dir.clone()) {
+        match expand_path(&cache_dir.to_string_lossy()) {
+            Ok(dir) => tetra_root.cache_dir = Some(dir),
+            Err(e) => {
+                print_error(&format!("Invalid cache directory {cache_dir:#?}: {e}"));
+                return;
+            }
         }
     }
-}
 
-#[derive(Debug, Deserialize)]
-struct Repository {
-    pub name: String,
-    pub desc: String,
+    // An outdated layout is not an error, but commands may miss entries until it is
+    // upgraded. Serialized output is read by other programs, which are not warned.
+    if !matches!(cli.command, Command::Migrate { .. } | Command::Doctor)
+        && !cli.command.prints_serialized()
+    {
+        match tetra_root.layout_version() {
+            Ok(version) if version < LAYOUT_VERSION => print_warn_to_stderr(&format!(
+                "Tetra root layout is version {version}, run tetra migrate to upgrade it to version {LAYOUT_VERSION}"
+            )),
+            Ok(version) if version > LAYOUT_VERSION => print_warn_to_stderr(&format!(
+                "Tetra root layout is version {version}, newer than version {LAYOUT_VERSION} of this tetra"
+            )),
+            Ok(_) => {}
+            Err(e) => print_warn_to_stderr(&e.to_string()),
+        }
+    }
 
-    #[serde(skip)]
-    pub id: String,
+    match cli.command {
+        Command::Fetch(args) if args.stdout => {
+            if !fetch_to_stdout(&tetra_root, &config, args) {
+                std::process::exit(1);
+            }
+        }
+        Command::Fetch(args) => fetch(&tetra_root, &config, args, cli.yes),
+        Command::Info {
+            package_id,
+            exact,
+            format,
+        } => info(&tetra_root, package_id, exact, format),
+        Command::DumpRecipe {
+            package_id,
+            exact,
+            format,
+        } => dump_recipe(&tetra_root, &config, package_id, exact, format),
+        Command::Closure { package_id, format } => {
+            closure(&tetra_root, &config, package_id, format)
+        }
+        Command::Tree {
+            package_id,
+            depth,
+            duplicates,
+            format,
+        } => tree::run(&tetra_root, package_id, depth, duplicates, format),
+        Command::Diff {
+            old_id,
+            new_id,
+            exact,
+            format,
+        } => diff::run(&tetra_root, old_id, new_id, exact, format),
+        Command::Resolve { query } => resolve(&tetra_root, query),
+        Command::Provides { path, format } => provides(&tetra_root, &path, format),
+        Command::Clean(args) => clean(&tetra_root, args),
+        Command::Doctor => {
+            if !doctor::run(&tetra_root, &config) {
+                std::process::exit(1);
+            }
+        }
+        Command::Migrate { dry_run } => migrate(&tetra_root, dry_run),
+        #[cfg(unix)]
+        Command::Daemon { socket } => daemon::run(&tetra_root, &config, socket),
+        Command::CheckSources {
+            package_id,
+            exact,
+            source,
+        } => {
+            if !check_sources::run(&tetra_root, &config, package_id, exact, source) {
+                std::process::exit(1);
+            }
+        }
+        Command::Verify {
+            package_id,
+            exact,
+            source,
+        } => {
+            if !verify::run(&tetra_root, package_id, exact, source) {
+                std::process::exit(1);
+            }
+        }
+        Command::Index { command } => match command {
+            IndexCommand::Export {
+                repo,
+                format,
+                output,
+            } => index_export(&tetra_root, &repo, format, output.as_deref()),
+            IndexCommand::Recipes { path, output } => index_recipes(&path, output.as_deref()),
+        },
+        Command::Recipe { command } => match command {
+            RecipeCommand::Hash {
+                file,
+                sha256,
+                output,
+            } => {
+                if !recipe_hash::run(&tetra_root, &config, &file, sha256, output.as_deref()) {
+                    std::process::exit(1);
+                }
+            }
+            RecipeCommand::Lint { files } => {
+                if !recipe_lint(&files) {
+                    std::process::exit(1);
+                }
+            }
+        },
+        Command::Repo { command } => match command {
+            RepoCommand::List { format, json } => {
+                let format = if json { OutputFormat::Json } else { format };
+                repo_list(&tetra_root, format)
+            }
+            RepoCommand::Changed {
+                repo,
+                since,
+                format,
+            } => repo_changed(&tetra_root, &repo, since, format),
+        },
+        Command::Cache { command } => match command {
+            CacheCommand::Add {
+                file,
+                hash,
+                move_in,
+            } => cache_add(&tetra_root, &file, hash.as_deref(), move_in),
+            CacheCommand::Provenance { hash } => cache_provenance(&tetra_root, &hash),
+            CacheCommand::Verify { sample, full: _ } => {
+                if !cache_verify(&tetra_root, sample) {
+                    std::process::exit(1);
+                }
+            }
+        },
+    }
 
-    #[serde(skip)]
-    pub pkgs_dir: PathBuf,
+    if is_interrupted() {
+        exit_interrupted();
+    }
 }
 
-impl Repository {
-    pub fn from_path(path: &Path) -> Result<Self> {
-        let repo_meta = path.join("repo.yml");
+#[derive(Debug, Serialize)]
+struct Info {
+    package: String,
+    recipe: PathBuf,
+    name: String,
+    version: String,
+    license: String,
+    maintainer: String,
 
-        if repo_meta.is_file() {
-            let repo_s = std::fs::read_to_string(repo_meta)?;
-            let mut repo: Self = serde_yaml::from_str(&repo_s)?;
+    /// Arch of the recipe, unset for an arch-less recipe
+    arch: Option<String>,
 
-            repo.id = path
-                .file_name()
-                .ok_or(anyhow!("Failed to unwrap repository path name"))?
-                .to_string_lossy()
-                .to_string();
+    /// Why the recipe of this arch was picked
+    arch_reason: String,
 
-            repo.pkgs_dir = path.join("pkgs");
+    /// Flavours the repository applied for the arch, as the package id named none
+    default_flavours: Vec<String>,
 
-            return Ok(repo);
-        }
+    /// Capabilities the recipe declares, package ids can ask for them with `+name`
+    capabilities: Vec<String>,
 
-        Err(anyhow!(
-            "Failed to load repository {path:#?}, no repository metadata found."
-        ))
-    }
+    /// Arches the arch-less recipe is shared by, empty when it applies to any
+    shared_arches: Vec<String>,
 
-    pub fn resolve_package_id(
-        &self,
-        package_id: &PackageId,
-        default_arch: &str,
-    ) -> Result<PathBuf> {
-        let mut recipe_path = PathBuf::from(&self.pkgs_dir);
+    /// Set for recipes without sources
+    metapackage: bool,
+    sources: Vec<InfoSource>,
 
-        recipe_path.push(
-            package_id
-                .name
-                .chars()
-                .nth(0)
-                .ok_or(anyhow!("Package name was empty"))?
-                .to_string(),
-        );
+    /// Aggregate hash of the sources, the value for the recipe's sources_hash
+    sources_hash: String,
 
-        recipe_path.push(&package_id.name);
+    /// Why the declared sources_hash does not match the sources
+    sources_hash_error: Option<String>,
 
-        if !recipe_path.is_dir() {
-            return Err(anyhow!(
-                "Package with name {} could not be found.",
-                &package_id.name
-            ));
+    /// Cached blobs of the package that no loaded recipe references anymore
+    unreferenced: Vec<UnreferencedSource>,
+}
+
+#[derive(Debug, Serialize)]
+struct InfoSource {
+    url: String,
+    hash: Option<String>,
+    cached: bool,
+    size: Option<u64>,
+    referenced_by: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct UnreferencedSource {
+    hash: String,
+    url: String,
+}
+
+fn info(tetra_root: &TetraRoot, package_id: String, exact: bool, format: OutputFormat) {
+    let id = PackageId::from_id_str(package_id);
+    let (arches, arch_source) = tetra_root.resolve_arch_preference();
+
+    let repos = match tetra_root.repos() {
+        Ok(r) => r,
+        Err(e) => {
+            print_error(&format!("Failed to locate repositories: {e}"));
+            return;
         }
+    };
+
+    let Some(repo) = repos.iter().find(|r| r.id == id.repo) else {
+        print_error(&format!("Cannot find repository with ID {}", id.repo));
+        return;
+    };
 
-        recipe_path.push(&package_id.version);
+    let recipe_path = match repo.resolve_package_id(&id, &arches, exact) {
+        Ok(p) => p,
+        Err(e) => {
+            print_error(&format!("Failed to resolve package ID: {e}"));
+            return;
+        }
+    };
 
-        if !recipe_path.is_dir() {
-            return Err(anyhow!(
-                "Package version {} does not exist.",
-                &package_id.version
-            ));
+    let recipe = match repo.load_recipe(&recipe_path) {
+        Ok(r) => r,
+        Err(e) => {
+            print_error(&format!("Failed to parse package recipe: {e}"));
+            return;
         }
+    };
 
-        for flavour in &package_id.flavours {
-            recipe_path.push(flavour);
+    let cache = match tetra_root.cache() {
+        Ok(c) => c.for_recipe(&recipe),
+        Err(e) => {
+            print_error(&format!("Failed to obtain cache object: {e}"));
+            return;
         }
+    };
 
-        if !recipe_path.is_dir() {
-            return Err(anyhow!(
-                "Specified package flavour combination does not exist."
+    let references = match SourceReferences::build(&repos) {
+        Ok(r) => r,
+        Err(e) => {
+            print_error(&format!(
+                "Failed to scan recipes for source references: {e}"
             ));
+            return;
         }
+    };
 
-        if let Some(arch) = &package_id.arch {
-            let mut path_with_arch = recipe_path.join(arch);
-            path_with_arch.push("recipe.yml");
+    let default_flavours = if exact {
+        None
+    } else {
+        repo.default_flavours(&id, &arches).ok().flatten()
+    };
 
-            if path_with_arch.is_file() {
-                return Ok(path_with_arch);
-            } else {
-                return Err(anyhow!(
-                    "Package architecure was set to {arch}, but package does not supply it."
-                ));
-            }
+    let metapackage = recipe.is_metapackage();
+    let mut sources = Vec::new();
+
+    for source in &recipe.sources {
+        let size = source.checksum().ok().and_then(|h| cache.blob_size(h));
+
+        let referenced_by = match &source.hash {
+            Some(hash) => references.get(hash).to_vec(),
+            None => Vec::new(),
+        };
+
+        sources.push(InfoSource {
+            url: output::display_url(&source.url),
+            hash: source.hash.clone(),
+            cached: size.is_some(),
+            size,
+            referenced_by,
+        });
+    }
+
+    // Blobs fetched for this package that no recipe points at anymore, e.g. after an update
+    let package_prefix = format!("{}/{}@", id.repo, id.name);
+    let unreferenced = match cache.provenances() {
+        Ok(p) => p
+            .into_iter()
+            .filter(|p| p.package.starts_with(&package_prefix))
+            .filter(|p| references.get(&p.hash).is_empty())
+            .map(|p| UnreferencedSource {
+                hash: p.hash,
+                url: output::display_url(&p.url),
+            })
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            print_error(&format!("Failed to read cache provenance: {e}"));
+            return;
         }
+    };
+
+    let sources_hash = recipe.written_sources_digest().to_string();
+    let sources_hash_error = recipe.check_sources_hash().err().map(|e| e.to_string());
+
+    let (arch, arch_reason) = describe_arch(&id, &arches, &arch_source, &recipe_path, exact);
+
+    let info = Info {
+        package: id.to_string(),
+        recipe: recipe_path,
+        name: recipe.name,
+        version: recipe.version,
+        license: recipe.license,
+        maintainer: recipe.maintainer,
+        arch,
+        arch_reason,
+        default_flavours: default_flavours.unwrap_or_default(),
+        capabilities: recipe.capabilities,
+        shared_arches: recipe.arches,
+        metapackage,
+        sources,
+        sources_hash,
+        sources_hash_error,
+        unreferenced,
+    };
+
+    if print_serialized(&info, format) {
+        return;
+    }
+
+    println!("Package: {}", info.package);
+    println!("Recipe: {:#?}", info.recipe);
+    println!("Name: {}", info.name);
+    println!("Version: {}", info.version);
+    println!("License: {}", info.license);
+    println!("Maintainer: {}", info.maintainer);
+    println!(
+        "Architecture: {} ({})",
+        info.arch.as_deref().unwrap_or("any"),
+        info.arch_reason
+    );
+
+    if !info.default_flavours.is_empty() {
+        println!("Default flavours: {}", info.default_flavours.join(":"));
+    }
+
+    if !info.capabilities.is_empty() {
+        println!("Capabilities: {}", info.capabilities.join(", "));
+    }
+
+    if !info.shared_arches.is_empty() {
+        println!("Shared by arches: {}", info.shared_arches.join(", "));
+    }
+
+    if info.metapackage {
+        println!("Sources: no sources (metapackage)");
+    } else {
+        println!("Sources:");
+    }
+
+    for source in &info.sources {
+        println!("    - URL: {}", source.url);
 
-        let mut path_with_default_arch = recipe_path.join(default_arch);
-        path_with_default_arch.push("recipe.yml");
-        if path_with_default_arch.is_file() {
-            return Ok(path_with_default_arch);
+        let Some(hash) = &source.hash else {
+            println!("      Hash: -");
+            continue;
+        };
+        println!("      Hash: {hash}");
+
+        match source.size {
+            Some(size) => println!("      Cached: yes, {}", format_bytes(size)),
+            None => println!("      Cached: no"),
         }
 
-        let path_with_recipe = recipe_path.join("recipe.yml");
-        if path_with_recipe.is_file() {
-            return Ok(path_with_recipe);
+        if source.referenced_by.len() <= 1 {
+            println!("      Referenced by: this recipe only");
+        } else {
+            println!(
+                "      Referenced by: {} recipes",
+                source.referenced_by.len()
+            );
+            for referrer in &source.referenced_by {
+                println!("        - {referrer}");
+            }
         }
+    }
 
-        Err(anyhow!("Package recipe could not be found."))
+    if !info.metapackage {
+        println!("Sources hash: {}", info.sources_hash);
     }
-}
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        println!("tetra <package id>");
-        return;
+    if let Some(error) = &info.sources_hash_error {
+        print_warn(error);
+    }
+
+    if !info.unreferenced.is_empty() {
+        println!("Unreferenced cached sources of {}:", id.name);
+        for source in &info.unreferenced {
+            println!("    - {} ({})", source.hash, source.url);
+        }
     }
+}
 
-    let package_id = args[1].clone();
+#[derive(Debug, Serialize)]
+struct Closure {
+    package: String,
+    packages: Vec<String>,
+    sources: Vec<ClosureSource>,
 
-    let tetra_root = TetraRoot::new();
-    println!("Tetra Root: {:#?}", tetra_root.root);
+    /// Bytes of all sources whose size is known
+    total_size: u64,
 
-    let default_arch = tetra_root.get_default_arch();
-    println!("Default architecture: {default_arch}");
+    /// Bytes of the sources that are not cached yet
+    download_size: u64,
 
-    let cache = match tetra_root.cache() {
-        Ok(c) => c,
+    /// Sources whose size could not be determined
+    unknown_sizes: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct ClosureSource {
+    url: String,
+    hash: Option<String>,
+    cached: bool,
+    size: Option<u64>,
+
+    /// Packages of the closure that use this source
+    packages: Vec<String>,
+}
+
+fn dump_recipe(
+    tetra_root: &TetraRoot,
+    config: &Config,
+    package_id: String,
+    exact: bool,
+    format: OutputFormat,
+) {
+    let id = PackageId::from_id_str(package_id);
+    let arches = tetra_root.get_arch_preference();
+
+    let mut repo = match tetra_root.repo(&id.repo) {
+        Ok(Some(r)) => r,
+        Ok(None) => {
+            print_error(&format!("Cannot find repository with ID {}", id.repo));
+            return;
+        }
         Err(e) => {
-            println!("Failed to obtain cache object: {e}");
+            print_error(&format!("Failed to locate repositories: {e}"));
             return;
         }
     };
-    println!("Cache directory: {:#?}", cache.cache_dir);
 
-    let id = PackageId::from_id_str(package_id);
+    // The sources are shown as fetch would download them
+    if let Some(host) = &config.mirror_host
+        && let Err(e) = repo.pin_mirror(host)
+    {
+        print_error(&format!("Failed to pin mirror {host}: {e}"));
+        return;
+    }
 
-    println!("\nRepo: {}", id.repo);
-    println!("Name: {}", id.name);
-    println!("Version: {}", id.version);
-    println!("Flavours:");
+    let recipe = repo
+        .resolve_package_id(&id, &arches, exact)
+        .map_err(|e| anyhow!("Failed to resolve package ID: {e}"))
+        .and_then(|path| {
+            repo.load_recipe(&path)
+                .map_err(|e| anyhow!("Failed to parse package recipe: {e}"))
+        })
+        .and_then(|mut recipe| {
+            display_source_urls(&mut recipe)?;
+            Ok(recipe)
+        });
+
+    match recipe {
+        Ok(recipe) => {
+            let format = match format {
+                OutputFormat::Text => OutputFormat::Yaml,
+                format => format,
+            };
 
-    for flavour in &id.flavours {
-        println!("    - {flavour}");
+            print_serialized(&recipe, format);
+        }
+        Err(e) => print_error(&e.to_string()),
     }
+}
 
-    println!("Arch: {:?}", id.arch);
+/// Replaces every URL of the sources of a recipe by the form it may be printed in
+fn display_source_urls(recipe: &mut Recipe) -> Result<()> {
+    for source in &mut recipe.sources {
+        source.url = output::display_request_url(&source.url)?;
 
-    let repos = match tetra_root.repos() {
-        Ok(r) => r,
-        Err(e) => {
-            println!("Failed to locate repositories: {e}");
-            return;
+        for mirror in &mut source.mirrors {
+            *mirror = output::display_request_url(mirror)?;
         }
-    };
 
-    for repo in &repos {
-        println!("\nId: {}", repo.id);
-        println!("Name: {}", repo.name);
-        println!("Description: {}", repo.desc);
-        println!("Packages Directory: {:#?}", repo.pkgs_dir);
+        for part in &mut source.parts {
+            part.url = output::display_request_url(&part.url)?;
+        }
+
+        if let Some(manifest) = &mut source.sha256sums {
+            manifest.url = output::display_request_url(&manifest.url)?;
+        }
     }
 
-    let repo = match repos.iter().find(|r| r.id == id.repo) {
-        Some(r) => r,
-        None => {
-            println!("\nCannot find repository with ID {}", id.repo);
+    Ok(())
+}
+
+fn closure(tetra_root: &TetraRoot, config: &Config, package_id: String, format: OutputFormat) {
+    let id = PackageId::from_id_str(package_id);
+    let package = id.to_string();
+    let arches = tetra_root.get_arch_preference();
+
+    let repos = match tetra_root.repos() {
+        Ok(r) => r,
+        Err(e) => {
+            print_error(&format!("Failed to locate repositories: {e}"));
             return;
         }
     };
 
-    println!("\nSelected repository {}", repo.id);
-
-    let recipe_path = match repo.resolve_package_id(&id, &default_arch) {
-        Ok(p) => p,
+    let cache = match tetra_root.cache() {
+        Ok(c) => c,
         Err(e) => {
-            println!("\nFailed to resolve package ID: {e}");
+            print_error(&format!("Failed to obtain cache object: {e}"));
             return;
         }
     };
 
-    println!("\nResolved recipe path: {recipe_path:#?}");
-
-    let recipe = match Recipe::from_path(&recipe_path) {
-        Ok(r) => r,
+    let entries = match resolve_closure(&repos, id, &arches) {
+        Ok(e) => e,
         Err(e) => {
-            println!("\nFailed to parse package recipe: {e}");
+            print_error(&format!("Failed to resolve dependency closure: {e}"));
             return;
         }
     };
 
-    println!("\nName: {}", &recipe.name);
-    println!("Version: {}", &recipe.version);
-    println!("License: {}", &recipe.license);
-    println!("Maintainer: {}", &recipe.maintainer);
-    println!("Sources:");
+    let mut sources: Vec<ClosureSource> = Vec::new();
+    let mut uncached: Vec<&RecipeSource> = Vec::new();
 
-    for source in &recipe.sources {
-        println!("    - URL: {}", source.url);
-        println!("      Hash: {}", source.hash);
+    for entry in &entries {
+        let entry_id = entry.id.to_string();
+        let cache = cache.for_recipe(&entry.recipe);
 
-        let cache_path = cache.get_cache_path(source.checksum().unwrap());
-        println!("      Cache Path: {cache_path:#?}");
+        for source in &entry.recipe.sources {
+            // Sources are the same blob if their hashes match, hashless ones by their URL
+            let existing = sources.iter_mut().find(|s| match (&s.hash, &source.hash) {
+                (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+                (None, None) => s.url == output::display_url(&source.url),
+                _ => false,
+            });
 
-        let validated = match cache.validate(source.checksum().unwrap()) {
-            Ok(r) => r,
-            Err(e) => {
-                println!("Cache validation failed: {e}");
-                return;
+            if let Some(existing) = existing {
+                if !existing.packages.contains(&entry_id) {
+                    existing.packages.push(entry_id.clone());
+                }
+                continue;
             }
-        };
 
-        if !validated {
-            let downloader = match Downloader::new(&tetra_root, source, &recipe.name) {
-                Ok(d) => d,
-                Err(e) => {
-                    println!("Error initializing downloader: {e}");
-                    return;
-                }
-            };
+            // Hashless mutable sources are found through the record of their last download
+            let hash = source.checksum().ok().or_else(|| {
+                cache
+                    .read_mutable_record(&source.url)
+                    .ok()
+                    .flatten()
+                    .and_then(|p| blake3::Hash::from_hex(&p.hash).ok())
+            });
+
+            let cached_size = hash.and_then(|h| cache.blob_size(h));
+            if cached_size.is_none() {
+                uncached.push(source);
+            }
+
+            sources.push(ClosureSource {
+                url: output::display_url(&source.url),
+                hash: source.hash.clone(),
+                cached: cached_size.is_some(),
+                size: cached_size,
+                packages: vec![entry_id.clone()],
+            });
+        }
+    }
+
+    // Sizes of sources that are not cached are asked from their servers concurrently
+    let limits = config.download_limits();
+    let remote_sizes = parallel_map(&uncached, None, |source| {
+        Downloader::probe(*source, &limits)
+            .ok()
+            .filter(|p| p.is_success())
+            .and_then(|p| p.size)
+    });
+
+    let mut remote_sizes = remote_sizes.into_iter();
+    for source in sources.iter_mut().filter(|s| !s.cached) {
+        source.size = remote_sizes.next().flatten();
+    }
+
+    let closure = Closure {
+        package,
+        packages: entries.iter().map(|e| e.id.to_string()).collect(),
+        total_size: sources.iter().filter_map(|s| s.size).sum(),
+        download_size: sources
+            .iter()
+            .filter(|s| !s.cached)
+            .filter_map(|s| s.size)
+            .sum(),
+        unknown_sizes: sources.iter().filter(|s| s.size.is_none()).count(),
+        sources,
+    };
+
+    if print_serialized(&closure, format) {
+        return;
+    }
+
+    println!("Packages:");
+    for package in &closure.packages {
+        println!("    - {package}");
+    }
+
+    if closure.sources.is_empty() {
+        println!("Sources: none, the closure only contains metapackages");
+    } else {
+        println!("Sources:");
+    }
+
+    for source in &closure.sources {
+        println!("    - URL: {}", source.url);
+        println!("      Hash: {}", source.hash.as_deref().unwrap_or("-"));
+
+        let size = source.size.map(format_bytes);
+        println!("      Size: {}", size.as_deref().unwrap_or("unknown"));
+        println!("      Cached: {}", if source.cached { "yes" } else { "no" });
+        println!("      Used by: {}", source.packages.join(", "));
+    }
+
+    let mut total = format!(
+        "Total: {} in {} source(s), {} to download",
+        format_bytes(closure.total_size),
+        closure.sources.len(),
+        format_bytes(closure.download_size)
+    );
+    if closure.unknown_sizes > 0 {
+        total.push_str(&format!(", {} of unknown size", closure.unknown_sizes));
+    }
+    println!("{total}");
+}
+
+fn resolve(tetra_root: &TetraRoot, query: String) {
+    let query = PackageQuery::from_query_str(query);
+
+    let repo = match tetra_root.repo(&query.repo) {
+        Ok(Some(r)) => r,
+        Ok(None) => {
+            print_error(&format!("Cannot find repository with ID {}", query.repo));
+            return;
+        }
+        Err(e) => {
+            print_error(&format!("Failed to locate repositories: {e}"));
+            return;
+        }
+    };
+
+    let resolved = match repo.resolve_all(&query) {
+        Ok(r) => r,
+        Err(e) => {
+            print_error(&format!("Failed to resolve package query: {e}"));
+            return;
+        }
+    };
+
+    if resolved.is_empty() {
+        println!("No recipes match the given package id");
+        return;
+    }
+
+    for recipe in resolved {
+        println!("{} {:#?}", recipe.id, recipe.path);
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct FileProvider {
+    package: String,
+    recipe: PathBuf,
+}
+
+fn provides(tetra_root: &TetraRoot, path: &Path, format: OutputFormat) {
+    if !path.is_absolute() {
+        print_error(&format!(
+            "Path {} is not absolute, packages declare absolute paths",
+            path.display()
+        ));
+        return;
+    }
+
+    let repos = match tetra_root.repos() {
+        Ok(r) => r,
+        Err(e) => {
+            print_error(&format!("Failed to locate repositories: {e}"));
+            return;
+        }
+    };
+
+    let mut providers = Vec::new();
+
+    for repo in &repos {
+        match repo.find_file_providers(path) {
+            Ok(found) => providers.extend(found.into_iter().map(|r| FileProvider {
+                package: r.id.to_string(),
+                recipe: r.path,
+            })),
+            Err(e) => {
+                print_error(&format!("Failed to search repository {}: {e}", repo.id));
+                return;
+            }
+        }
+    }
+
+    if print_serialized(&providers, format) {
+        return;
+    }
+
+    if providers.is_empty() {
+        println!("No package declares that it installs {}", path.display());
+        return;
+    }
+
+    for provider in &providers {
+        println!("{} {:#?}", provider.package, provider.recipe);
+    }
+}
+
+fn clean(tetra_root: &TetraRoot, args: CleanArgs) {
+    let clean_cache = args.cache || args.all;
+    let clean_temp = args.temp || args.all || !clean_cache;
+
+    let temp_dir = tetra_root.get_temp_dir_path();
+    let mut removed = Vec::new();
+
+    if clean_temp {
+        match TempFile::sweep(&temp_dir, args.dry_run) {
+            Ok(paths) => removed.extend(paths),
+            Err(e) => {
+                print_error(&format!(
+                    "Failed to clean temp directory {temp_dir:#?}: {e}"
+                ));
+                return;
+            }
+        }
+    }
+
+    if clean_cache {
+        let busy = match TempFile::in_use(&temp_dir) {
+            Ok(b) => b,
+            Err(e) => {
+                print_error(&format!("Failed to read temp directory {temp_dir:#?}: {e}"));
+                return;
+            }
+        };
+
+        let gc = tetra_root
+            .cache()
+            .and_then(|cache| cache.gc(&busy, args.dry_run));
+        match gc {
+            Ok(paths) => removed.extend(paths),
+            Err(e) => {
+                print_error(&format!("Failed to clean cache: {e}"));
+                return;
+            }
+        }
+    }
+
+    let action = if args.dry_run {
+        "Would remove"
+    } else {
+        "Removed"
+    };
+
+    for path in &removed {
+        println!("{action} {}", path.display());
+    }
+
+    println!("{action} {} file(s)", removed.len());
+}
+
+fn migrate(tetra_root: &TetraRoot, dry_run: bool) {
+    let migration = match tetra_root.migrate(dry_run) {
+        Ok(m) => m,
+        Err(e) => {
+            print_error(&format!("Failed to migrate tetra root: {e}"));
+            return;
+        }
+    };
+
+    match (migration.from == LAYOUT_VERSION, dry_run) {
+        (true, _) => println!("Layout is up to date (version {LAYOUT_VERSION})"),
+        (false, true) => println!(
+            "Would migrate layout version {} to {LAYOUT_VERSION}",
+            migration.from
+        ),
+        (false, false) => println!(
+            "Migrated layout version {} to {LAYOUT_VERSION}",
+            migration.from
+        ),
+    }
+}
+
+/// Writes the index a repository maintainer signs, e.g. with
+/// `openssl pkeyutl -sign -rawin -inkey key.pem -in recipes.index | xxd -p -c0 > recipes.index.sig`
+fn index_recipes(path: &Path, output: Option<&Path>) {
+    let index = match SignedIndex::generate(path) {
+        Ok(i) => i,
+        Err(e) => {
+            print_error(&format!("Failed to index recipes of {path:#?}: {e}"));
+            return;
+        }
+    };
+
+    let written = match output {
+        Some(output) => std::fs::write(output, &index),
+        None => std::io::stdout().write_all(index.as_bytes()),
+    };
+
+    if let Err(e) = written {
+        print_error(&format!("Failed to write index: {e}"));
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RepoListEntry {
+    id: String,
+    name: String,
+    desc: String,
+    packages: usize,
+    versions: usize,
+    variants: usize,
+    path: PathBuf,
+}
+
+fn repo_list(tetra_root: &TetraRoot, format: OutputFormat) {
+    let repos = match tetra_root.repos() {
+        Ok(r) => r,
+        Err(e) => {
+            print_error(&format!("Failed to locate repositories: {e}"));
+            return;
+        }
+    };
+
+    let mut entries = Vec::new();
+
+    for repo in repos {
+        let summary = match repo.summary() {
+            Ok(s) => s,
+            Err(e) => {
+                print_error(&format!(
+                    "Failed to list packages of repository {}: {e}",
+                    repo.id
+                ));
+                return;
+            }
+        };
+
+        entries.push(RepoListEntry {
+            id: repo.id,
+            name: repo.name,
+            desc: repo.desc,
+            packages: summary.packages,
+            versions: summary.versions,
+            variants: summary.variants,
+            path: repo.path,
+        });
+    }
+
+    entries.sort_by(|a, b| a.id.cmp(&b.id));
+
+    if print_serialized(&entries, format) {
+        return;
+    }
+
+    if entries.is_empty() {
+        println!("No repositories found in {:?}", tetra_root.get_repo_dirs());
+        return;
+    }
+
+    let id_width = entries.iter().map(|e| e.id.len()).max().unwrap_or(0).max(2);
+    let name_width = entries
+        .iter()
+        .map(|e| e.name.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+
+    println!(
+        "{:id_width$}  {:name_width$}  {:>8}  {:>8}  {:>8}  DESCRIPTION",
+        "ID", "NAME", "PACKAGES", "VERSIONS", "VARIANTS"
+    );
+
+    for entry in &entries {
+        println!(
+            "{:id_width$}  {:name_width$}  {:>8}  {:>8}  {:>8}  {}",
+            entry.id, entry.name, entry.packages, entry.versions, entry.variants, entry.desc
+        );
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ChangedEntry {
+    package: String,
+    change: RecipeChange,
+
+    /// UTC time the recipe file was last modified
+    modified: String,
+    recipe: PathBuf,
+}
+
+fn repo_changed(tetra_root: &TetraRoot, repo_id: &str, since: SystemTime, format: OutputFormat) {
+    let repo = match tetra_root.repo(repo_id) {
+        Ok(Some(r)) => r,
+        Ok(None) => {
+            print_error(&format!("Cannot find repository with ID {repo_id}"));
+            return;
+        }
+        Err(e) => {
+            print_error(&format!("Failed to locate repositories: {e}"));
+            return;
+        }
+    };
+
+    let changed = match changed_since(&repo, since) {
+        Ok(c) => c,
+        Err(e) => {
+            print_error(&format!(
+                "Failed to list changes of repository {repo_id}: {e}"
+            ));
+            return;
+        }
+    };
+
+    let entries = changed
+        .into_iter()
+        .map(|c| ChangedEntry {
+            package: c.id.to_string(),
+            change: c.change,
+            modified: format_unix_time(
+                c.modified
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0),
+            ),
+            recipe: c.path,
+        })
+        .collect::<Vec<_>>();
+
+    if print_serialized(&entries, format) {
+        return;
+    }
+
+    if entries.is_empty() {
+        println!("No recipe of repository {repo_id} changed since the cutoff");
+        return;
+    }
+
+    for entry in &entries {
+        println!("{}  {} ({})", entry.package, entry.change, entry.modified);
+    }
+}
+
+/// Lints recipe files, returning false if any of them cannot be loaded
+fn recipe_lint(files: &[PathBuf]) -> bool {
+    let mut report = output::Report::default();
+
+    for file in files {
+        let issues = Recipe::lint(file, |path| Ok(std::fs::read_to_string(path)?));
+
+        if issues.is_empty() {
+            report.check(output::Status::Pass, &file.display().to_string(), None);
+        }
+
+        for issue in issues {
+            match issue {
+                LintIssue::Error(e) => report.check(
+                    output::Status::Fail,
+                    &format!("{}: {e}", file.display()),
+                    None,
+                ),
+                LintIssue::UnknownField(e) => report.check(
+                    output::Status::Warn,
+                    &format!("{}: {e}", file.display()),
+                    Some("tetra ignores this field, check its spelling"),
+                ),
+            }
+        }
+    }
+
+    !report.failed
+}
+
+fn index_export(tetra_root: &TetraRoot, repo_id: &str, format: IndexFormat, output: Option<&Path>) {
+    let repo = match tetra_root.repo(repo_id) {
+        Ok(Some(r)) => r,
+        Ok(None) => {
+            print_error(&format!("Cannot find repository with ID {repo_id}"));
+            return;
+        }
+        Err(e) => {
+            print_error(&format!("Failed to locate repositories: {e}"));
+            return;
+        }
+    };
+
+    let index = match RepositoryIndex::build(&repo) {
+        Ok(i) => i,
+        Err(e) => {
+            print_error(&format!("Failed to index repository {repo_id}: {e}"));
+            return;
+        }
+    };
+
+    let bytes = match format {
+        IndexFormat::Json => serde_json::to_vec_pretty(&index)
+            .map(|mut b| {
+                b.push(b'\n');
+                b
+            })
+            .map_err(anyhow::Error::from),
+        IndexFormat::Yaml => serde_yaml::to_string(&index)
+            .map(String::into_bytes)
+            .map_err(anyhow::Error::from),
+        IndexFormat::Msgpack => rmp_serde::to_vec_named(&index).map_err(anyhow::Error::from),
+    };
+
+    let bytes = match bytes {
+        Ok(b) => b,
+        Err(e) => {
+            print_error(&format!("Failed to serialize index: {e}"));
+            return;
+        }
+    };
+
+    let written = match output {
+        Some(path) => std::fs::write(path, &bytes),
+        None => std::io::stdout().write_all(&bytes),
+    };
+
+    if let Err(e) = written {
+        print_error(&format!("Failed to write index: {e}"));
+    }
+}
+
+fn cache_add(tetra_root: &TetraRoot, file: &Path, hash: Option<&str>, move_in: bool) {
+    let expected = match hash.map(blake3::Hash::from_hex).transpose() {
+        Ok(h) => h,
+        Err(e) => {
+            print_error(&format!("Invalid hash {}: {e}", hash.unwrap_or_default()));
+            return;
+        }
+    };
+
+    let cache = match tetra_root.cache() {
+        Ok(c) => c,
+        Err(e) => {
+            print_error(&format!("Failed to obtain cache object: {e}"));
+            return;
+        }
+    };
+
+    match cache.add(file, expected, move_in) {
+        Ok(hash) => {
+            println!("Hash: {hash}");
+            println!("Cache Path: {:#?}", cache.blob_path(hash));
+        }
+        Err(e) => print_error(&format!(
+            "Failed to add {} to the cache: {e}",
+            file.display()
+        )),
+    }
+}
+
+/// Hashes cached blobs, or a sample of them, returning false if any was corrupt
+fn cache_verify(tetra_root: &TetraRoot, sample: Option<f64>) -> bool {
+    let mut report = output::Report::default();
+
+    let cache = match tetra_root.cache() {
+        Ok(c) => c,
+        Err(e) => {
+            print_error(&format!("Failed to obtain cache object: {e}"));
+            return false;
+        }
+    };
+
+    let verification = match cache.verify_blobs(sample) {
+        Ok(v) => v,
+        Err(e) => {
+            print_error(&format!("Failed to verify cache: {e}"));
+            return false;
+        }
+    };
+
+    match sample {
+        Some(share) => println!(
+            "Checked {} of {} blob(s), a {:.1}% sample",
+            verification.checked,
+            verification.total,
+            share * 100.0
+        ),
+        None => println!("Checked {} blob(s)", verification.checked),
+    }
+
+    for path in &verification.corrupt {
+        report.check(
+            output::Status::Fail,
+            &format!("Corrupt blob {}", path.display()),
+            None,
+        );
+    }
+
+    if verification.unknown > 0 {
+        report.check(
+            output::Status::Warn,
+            &format!(
+                "{} blob(s) could not be checked, their namespaced provenance is missing",
+                verification.unknown
+            ),
+            None,
+        );
+    }
+
+    let verified = verification.checked - verification.unknown;
+
+    let estimate = match verification.corrupt.len() {
+        _ if sample.is_none() || verified == 0 => String::new(),
+        // With nothing found in n blobs, 3/n bounds the error rate at 95% confidence
+        0 => format!(
+            ", an estimated error rate below {:.1}%",
+            (300.0 / verified as f64).min(100.0)
+        ),
+        _ => format!(
+            ", an estimated {:.1}% of the cache or about {:.0} blob(s)",
+            verification.error_rate() * 100.0,
+            verification.error_rate() * verification.total as f64
+        ),
+    };
+
+    println!();
+    if report.failed {
+        println!(
+            "Result: {}, {} corrupt blob(s){estimate}",
+            paint("FAIL", Style::Error),
+            verification.corrupt.len()
+        );
+    } else {
+        println!("Result: {}{estimate}", paint("PASS", Style::Success));
+    }
+
+    if report.failed {
+        println!("Run tetra clean --cache to remove corrupt blobs");
+    }
+
+    !report.failed
+}
+
+fn cache_provenance(tetra_root: &TetraRoot, hash: &str) {
+    let hash = match blake3::Hash::from_hex(hash) {
+        Ok(h) => h,
+        Err(e) => {
+            print_error(&format!("Invalid hash {hash}: {e}"));
+            return;
+        }
+    };
+
+    let cache = match tetra_root.cache() {
+        Ok(c) => c,
+        Err(e) => {
+            print_error(&format!("Failed to obtain cache object: {e}"));
+            return;
+        }
+    };
+
+    let provenance = match cache.read_provenance(hash) {
+        Ok(Some(p)) => p,
+        Ok(None) => {
+            print_error(&format!("No provenance recorded for {hash}"));
+            return;
+        }
+        Err(e) => {
+            print_error(&format!("Failed to read provenance for {hash}: {e}"));
+            return;
+        }
+    };
+
+    println!("Hash: {}", provenance.hash);
+    println!("URL: {}", output::display_url(&provenance.url));
+    if let Some(resolved_url) = &provenance.resolved_url {
+        println!("Served from: {resolved_url}");
+    }
+    println!("Package: {}", provenance.package);
+    println!(
+        "Fetched At: {} ({})",
+        provenance.fetched_at_utc(),
+        provenance.fetched_at
+    );
+    println!("HTTP Status: {}", provenance.status);
+    println!("ETag: {}", provenance.etag.as_deref().unwrap_or("-"));
+    println!(
+        "Last Modified: {}",
+        provenance.last_modified.as_deref().unwrap_or("-")
+    );
+}
+
+fn fetch(tetra_root: &TetraRoot, config: &Config, args: FetchArgs, assume_yes: bool) {
+    println!("Tetra Root: {:#?}", tetra_root.root);
+
+    let (arches, arch_source) = tetra_root.resolve_arch_preference();
+    match arches.len() {
+        1 => println!("Default architecture: {} (from {arch_source})", arches[0]),
+        _ => println!(
+            "Preferred architectures: {} (from {arch_source})",
+            arches.join(", ")
+        ),
+    }
+
+    let cache = match tetra_root.cache() {
+        Ok(c) => c,
+        Err(e) => {
+            print_error(&format!("Failed to obtain cache object: {e}"));
+            return;
+        }
+    };
+    println!("Cache directory: {:#?}", cache.cache_dir);
+
+    let id = PackageId::from_id_str(args.package_id.clone());
+
+    println!("\nRepo: {}", id.repo);
+    println!("Name: {}", id.name);
+    println!("Version: {}", id.version);
+    println!("Flavours:");
+
+    for flavour in &id.flavours {
+        println!("    - {flavour}");
+    }
+
+    println!("Arch: {:?}", id.arch);
+
+    // Only the repository of the package is loaded, the others are never read
+    let mut repo = match tetra_root.repo(&id.repo) {
+        Ok(Some(r)) => r,
+        Ok(None) => {
+            print_error(&format!("\nCannot find repository with ID {}", id.repo));
+            return;
+        }
+        Err(e) => {
+            print_error(&format!("Failed to locate repositories: {e}"));
+            return;
+        }
+    };
+
+    println!("\nSelected repository {}", repo.id);
+    println!("Name: {}", repo.name);
+    println!("Description: {}", repo.desc);
+    println!("Packages Directory: {:#?}", repo.pkgs_dir);
+
+    match (repo.is_verified(), repo.is_signed()) {
+        (true, _) => println!("Signature: verified"),
+        (false, true) => print_warn(&format!(
+            "Repository {} is signed, but repo_keys has no key for it, its recipes are not verified",
+            repo.id
+        )),
+        (false, false) => print_warn(&format!(
+            "Repository {} is not signed, its recipes are not verified",
+            repo.id
+        )),
+    }
+
+    if let Some(host) = &config.mirror_host {
+        let base_url = repo.base_url.clone();
+
+        match repo.pin_mirror(host) {
+            Ok(Some(pinned)) => {
+                println!(
+                    "Mirror: {} -> {}",
+                    output::display_url(&base_url.unwrap_or_default()),
+                    output::display_url(&pinned)
+                )
+            }
+            Ok(None) => println!("Mirror: repository has no base_url, sources are unchanged"),
+            Err(e) => {
+                print_error(&format!("Failed to pin mirror {host}: {e}"));
+                return;
+            }
+        }
+    }
+
+    let recipe_path = match repo.resolve_package_id(&id, &arches, args.exact) {
+        Ok(p) => p,
+        Err(e) => {
+            print_error(&format!("\nFailed to resolve package ID: {e}"));
+            return;
+        }
+    };
+
+    println!("\nResolved recipe path: {recipe_path:#?}");
+
+    let (arch, reason) = describe_arch(&id, &arches, &arch_source, &recipe_path, args.exact);
+    println!(
+        "Architecture: {} ({reason})",
+        arch.as_deref().unwrap_or("any")
+    );
+
+    if !args.exact
+        && let Ok(Some(flavours)) = repo.default_flavours(&id, &arches)
+    {
+        let arch = id.arch.as_ref().unwrap_or(&arches[0]);
+        println!("Default flavours for {arch}: {}", flavours.join(":"));
+    }
+
+    let recipe = match repo.load_recipe(&recipe_path) {
+        Ok(r) => r,
+        Err(e) => {
+            print_error(&format!("\nFailed to parse package recipe: {e}"));
+            return;
+        }
+    };
+
+    println!("\nName: {}", &recipe.name);
+    println!("Version: {}", &recipe.version);
+    println!("License: {}", &recipe.license);
+    println!("Maintainer: {}", &recipe.maintainer);
+
+    if let Err(e) = recipe.check_sources_hash() {
+        print_warn(&e.to_string());
+    }
+
+    let cache = cache.for_recipe(&recipe);
+    if cache.pin_to_recipe {
+        println!(
+            "Cache namespace: recipe {}",
+            recipe.written_sources_digest()
+        );
+    }
+
+    if recipe.is_metapackage() && args.source.is_none() && args.output.is_none() {
+        println!("Sources: no sources (metapackage), nothing to fetch");
+        return;
+    }
+
+    println!("Sources:");
+
+    let sources = match &args.source {
+        Some(selector) => match recipe.select_source(selector) {
+            Ok(s) => vec![s],
+            Err(e) => {
+                println!("{e}");
+                return;
+            }
+        },
+        None => recipe.sources.iter().collect::<Vec<_>>(),
+    };
+
+    if let Some(output) = &args.output {
+        let [source] = sources[..] else {
+            print_error(
+                "Fetching to an output path needs exactly one source, select it with --source",
+            );
+            return;
+        };
+
+        if let Err(e) =
+            fetch_to_output(tetra_root, config, assume_yes, source, &recipe.name, &id, output)
+        {
+            print_error(&format!(
+                "Failed to fetch {} to {}: {e}",
+                output::display_url(&source.url),
+                output.display()
+            ));
+        }
+
+        return;
+    }
+
+    let mut pending = Vec::new();
+
+    for source in sources {
+        println!("    - URL: {}", output::display_url(&source.url));
+
+        if let Some(name) = &source.name {
+            println!("      Name: {name}");
+        }
+
+        for mirror in &source.mirrors {
+            println!("      Mirror: {}", output::display_url(mirror));
+        }
+
+        println!("      Hash: {}", source.hash.as_deref().unwrap_or("-"));
+
+        if let Some(sha256) = &source.sha256 {
+            println!("      SHA256: {sha256}");
+        }
+
+        if let Some(manifest) = &source.sha256sums {
+            println!("      SHA256SUMS: {}", output::display_url(&manifest.url));
+        }
+
+        if source.optional {
+            println!("      Optional: true");
+        }
+
+        if source.mutable {
+            println!("      Mutable: true");
+
+            if args.explain_cache {
+                explain_mutable_lookup(&cache, source, &args);
+            }
+
+            match reusable_download(&cache, source, args.prefer_cache) {
+                Ok(Some(provenance)) => {
+                    println!(
+                        "      Cached: downloaded at {}, not revalidated",
+                        provenance.fetched_at_utc()
+                    );
+                    if let Ok(hash) = blake3::Hash::from_hex(&provenance.hash) {
+                        print_cache_entry(&cache, hash);
+                    }
+                }
+                Ok(None) => pending.push(source),
+                Err(e) => {
+                    print_warn(&format!("Failed to read the cached download, {e}"));
+                    pending.push(source);
+                }
+            }
+
+            continue;
+        }
+
+        let hash = match source.checksum() {
+            Ok(h) => h,
+            Err(e) => {
+                print_error(&format!("Invalid source checksum: {e}"));
+                return;
+            }
+        };
+
+        let cache_path = cache.blob_path(hash);
+        println!("      Cache Path: {cache_path:#?}");
+
+        let existed = cache_path.is_file();
+        let status = match cache.validate(hash) {
+            Ok(s) => s,
+            Err(e) => {
+                println!("Cache validation failed: {e}");
+                return;
+            }
+        };
+
+        if args.explain_cache {
+            explain_lookup(existed, status, &args);
+        }
+
+        if status == CacheStatus::Corrupt {
+            print_warn(&format!(
+                "Cached file {hash} was corrupt and has been removed, fetching it again"
+            ));
+        }
+
+        if !status.is_valid() || args.refresh || args.no_cache {
+            pending.push(source);
+        } else if let Ok(meta) = std::fs::metadata(&cache_path) {
+            println!("      Size: {}", format_bytes(meta.len()));
+        }
+    }
+
+    for source in &pending {
+        if let Err(e) = check_url_security(*source, config) {
+            println!("{e}");
+            return;
+        }
+    }
+
+    let refresh = args.refresh || args.no_cache;
+
+    match confirm_download_size(config, assume_yes, &cache, &pending, refresh) {
+        Ok(true) => {}
+        Ok(false) => {
+            println!("Aborted.");
+            return;
+        }
+        Err(e) => {
+            print_error(&format!("Failed to confirm download: {e}"));
+            return;
+        }
+    }
+
+    for source in pending {
+        if is_interrupted() {
+            exit_interrupted();
+        }
+
+        println!("\nFetching {}", output::display_url(&source.url));
+
+        if source.mutable && !args.no_cache {
+            if let Err(e) = fetch_mutable_source(
+                tetra_root,
+                config,
+                &cache,
+                source,
+                &recipe.name,
+                &id,
+                args.refresh,
+            ) {
+                if source.optional {
+                    skip_optional(source, &e);
+                    continue;
+                }
+
+                print_error(&format!("Failed to fetch mutable source: {e}"));
+                return;
+            }
+
+            continue;
+        }
 
-            if let Err(e) = downloader.download() {
+        let pb = progress_bar(&recipe.name, source);
+        let mut downloader = match Downloader::new(tetra_root, source, &id) {
+            Ok(d) => d
+                .with_limits(config.download_limits())
+                .with_mirror_strategy(config.mirror_strategy)
+                .with_progress(|current, total| update_progress(&pb, current, total)),
+            Err(e) if source.optional => {
+                skip_optional(source, &e);
+                continue;
+            }
+            Err(e) => {
+                println!("Error initializing downloader: {e}");
+                return;
+            }
+        };
+
+        let provenance = match finish_progress(&pb, downloader.download(None)) {
+            Ok(Download::Fetched(p)) => {
+                print_summary(&recipe.name, &downloader);
+                p
+            }
+            Ok(Download::NotModified) => continue,
+            Err(e) if source.optional => {
+                skip_optional(source, &e);
+                continue;
+            }
+            Err(e) => {
                 println!("Error while downloading: {e}");
                 return;
             }
+        };
 
-            if let Err(e) = downloader.send_to_cache(&cache) {
-                println!("Caching failed: {e}");
-            };
+        // The temp file is removed again once the downloader is dropped
+        if args.no_cache {
+            match downloader.verify(&provenance) {
+                Ok(()) => println!("      Verified {}, not cached", provenance.hash),
+                Err(e) if source.optional => skip_optional(source, &e),
+                Err(e) => {
+                    println!("Verification failed: {e}");
+                    return;
+                }
+            }
+            continue;
+        }
+
+        if let Err(e) = downloader.send_to_cache(&cache, &provenance) {
+            println!("Caching failed: {e}");
+            continue;
+        };
+
+        print_cache_entry(&cache, source.checksum().unwrap());
+    }
+}
+
+/// Reports an optional source that could not be fetched, which does not stop the fetch.
+/// A source that failed because of Ctrl-C is not skipped, the fetch ends there.
+fn skip_optional(source: &RecipeSource, e: &anyhow::Error) {
+    if is_interrupted() {
+        exit_interrupted();
+    }
+
+    print_warn(&format!(
+        "Skipping optional source {}, {e}",
+        output::display_url(&source.url)
+    ));
+}
+
+/// Prints where a blob was cached and its size, with its size on disk if compressed
+fn print_cache_entry(cache: &Cache, hash: blake3::Hash) {
+    let cache_path = cache.blob_path(hash);
+    let (Ok(meta), Some(size)) = (std::fs::metadata(&cache_path), cache.blob_size(hash)) else {
+        return;
+    };
+
+    println!("      Cache Path: {cache_path:#?}");
+
+    if meta.len() == size {
+        println!("      Size: {}", format_bytes(size));
+    } else {
+        println!(
+            "      Size: {} ({} on disk)",
+            format_bytes(size),
+            format_bytes(meta.len())
+        );
+    }
+}
+
+/// Asks for confirmation if the sources about to be downloaded exceed the configured
+/// size threshold, returning whether fetching should go ahead.
+fn confirm_download_size(
+    config: &Config,
+    assume_yes: bool,
+    cache: &Cache,
+    pending: &[&RecipeSource],
+    refresh: bool,
+) -> Result<bool> {
+    if assume_yes || pending.is_empty() {
+        return Ok(true);
+    }
+
+    let interactive = std::io::stdin().is_terminal();
+    if !interactive && config.non_interactive == NonInteractive::Proceed {
+        return Ok(true);
+    }
+
+    let mut probed = Vec::new();
+
+    for source in pending {
+        // A mutable source with an intact previous download will most likely not be modified
+        if source.mutable
+            && !refresh
+            && let Some(previous) = cache.read_mutable_record(&source.url)?
+            && cache
+                .validate(blake3::Hash::from_hex(&previous.hash)?)?
+                .is_valid()
+        {
+            continue;
+        }
+
+        probed.push(*source);
+    }
+
+    let limits = config.download_limits();
+    let sizes = parallel_map(&probed, None, |source| {
+        Downloader::probe(*source, &limits)
+            .ok()
+            .filter(|p| p.is_success())
+            .and_then(|p| p.size)
+    });
+
+    let mut total = 0;
+    let mut unknown = 0;
+
+    for size in sizes {
+        match size {
+            Some(size) => total += size,
+            None => unknown += 1,
+        }
+    }
+
+    if total <= config.confirm_threshold {
+        return Ok(true);
+    }
+
+    let mut prompt = format!("This will download {}", format_bytes(total));
+    if unknown > 0 {
+        prompt.push_str(&format!(" plus {unknown} source(s) of unknown size"));
+    }
+    prompt.push_str(", continue?");
+
+    if !interactive {
+        println!("{prompt} Not running interactively, pass --yes to proceed.");
+        return Ok(false);
+    }
+
+    confirm(&prompt)
+}
+
+/// Streams a single source of a package to stdout, verifying it as it is written and
+/// printing nothing else there. Returns false if it failed, and what was written must
+/// then be discarded.
+fn fetch_to_stdout(tetra_root: &TetraRoot, config: &Config, args: FetchArgs) -> bool {
+    let id = PackageId::from_id_str(args.package_id);
+    let arches = tetra_root.get_arch_preference();
+
+    let mut repo = match tetra_root.repo(&id.repo) {
+        Ok(Some(r)) => r,
+        Ok(None) => {
+            print_error(&format!("Cannot find repository with ID {}", id.repo));
+            return false;
+        }
+        Err(e) => {
+            print_error(&format!("Failed to locate repositories: {e}"));
+            return false;
+        }
+    };
+
+    if let Some(host) = &config.mirror_host
+        && let Err(e) = repo.pin_mirror(host)
+    {
+        print_error(&format!("Failed to pin mirror {host}: {e}"));
+        return false;
+    }
+
+    let recipe = match repo
+        .resolve_package_id(&id, &arches, args.exact)
+        .and_then(|path| repo.load_recipe(&path))
+    {
+        Ok(r) => r,
+        Err(e) => {
+            print_error(&format!("Failed to load recipe of {id}: {e}"));
+            return false;
+        }
+    };
+
+    if let Err(e) = recipe.check_sources_hash() {
+        print_warn(&e.to_string());
+    }
+
+    // Clap only accepts --stdout together with --source
+    let selector = args.source.unwrap_or_default();
+    let source = match recipe.select_source(&selector) {
+        Ok(s) => s,
+        Err(e) => {
+            print_error(&e.to_string());
+            return false;
+        }
+    };
+
+    if let Err(e) = check_url_security(source, config) {
+        print_error(&e.to_string());
+        return false;
+    }
+
+    let pb = progress_bar(&recipe.name, source);
+    let streamed = Downloader::new(tetra_root, source, &id).and_then(|downloader| {
+        downloader
+            .with_limits(config.download_limits())
+            .with_mirror_strategy(config.mirror_strategy)
+            .with_progress(|current, total| update_progress(&pb, current, total))
+            .download_to_writer(std::io::stdout().lock())
+    });
+
+    match finish_progress(&pb, streamed) {
+        Ok(_) => true,
+        Err(e) => {
+            print_error(&format!(
+                "Failed to stream {}: {e}",
+                output::display_url(&source.url)
+            ));
+            false
+        }
+    }
+}
+
+/// Downloads and verifies a single source to an explicit path, bypassing the cache
+fn fetch_to_output(
+    tetra_root: &TetraRoot,
+    config: &Config,
+    assume_yes: bool,
+    source: &RecipeSource,
+    name: &str,
+    id: &PackageId,
+    output: &Path,
+) -> Result<()> {
+    check_url_security(source, config)?;
+
+    if !confirm_download_size(config, assume_yes, &tetra_root.cache()?, &[source], false)? {
+        return Err(anyhow!("Aborted"));
+    }
+
+    let pb = progress_bar(name, source);
+    let mut downloader = Downloader::new(tetra_root, source, id)?
+        .with_limits(config.download_limits())
+        .with_mirror_strategy(config.mirror_strategy)
+        .with_progress(|current, total| update_progress(&pb, current, total));
+
+    let Download::Fetched(provenance) = finish_progress(&pb, downloader.download(None))? else {
+        return Err(anyhow!("Server did not return any content"));
+    };
+    print_summary(name, &downloader);
+
+    downloader.save_to(&provenance, output)?;
+    println!(
+        "Saved {} to {}",
+        output::display_url(&source.url),
+        output.display()
+    );
+
+    Ok(())
+}
+
+fn fetch_mutable_source(
+    tetra_root: &TetraRoot,
+    config: &Config,
+    cache: &Cache,
+    source: &RecipeSource,
+    name: &str,
+    id: &PackageId,
+    refresh: bool,
+) -> Result<()> {
+    // Only revalidate against the last download if its blob is still intact
+    let previous = match cache.read_mutable_record(&source.url)? {
+        Some(p) if !refresh => match cache.validate(blake3::Hash::from_hex(&p.hash)?)? {
+            CacheStatus::Valid => Some(p),
+            CacheStatus::Missing => None,
+            CacheStatus::Corrupt => {
+                print_warn(&format!(
+                    "Cached file {} was corrupt and has been removed, fetching it again",
+                    p.hash
+                ));
+                None
+            }
+        },
+        _ => None,
+    };
+
+    let pb = progress_bar(name, source);
+    let mut downloader = Downloader::new(tetra_root, source, id)?
+        .with_limits(config.download_limits())
+        .with_mirror_strategy(config.mirror_strategy)
+        .with_progress(|current, total| update_progress(&pb, current, total));
+
+    match finish_progress(&pb, downloader.download(previous.as_ref()))? {
+        Download::NotModified => {
+            if let Some(previous) = previous {
+                println!("      Not modified upstream, using cached {}", previous.hash);
+            }
+        }
+        Download::Fetched(provenance) => {
+            print_summary(name, &downloader);
+            downloader.send_to_cache(cache, &provenance)?;
+            cache.write_mutable_record(&provenance)?;
+
+            print_cache_entry(cache, blake3::Hash::from_hex(&provenance.hash)?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints how the cache was looked up for a source with a hash and what fetch does
+/// because of it
+fn explain_lookup(existed: bool, status: CacheStatus, args: &FetchArgs) {
+    let lookup = match (existed, status) {
+        (false, _) => "no cached file",
+        (true, CacheStatus::Valid) => "cached file matches the hash",
+        (true, _) => "cached file does not match the hash and was removed",
+    };
+
+    let decision = match status {
+        _ if args.no_cache => "download, --no-cache leaves the cache alone",
+        CacheStatus::Corrupt => "corrupt, downloading it again",
+        CacheStatus::Valid if args.refresh => "refresh, --refresh replaces the cached file",
+        CacheStatus::Valid => "hit, the cached file is used",
+        CacheStatus::Missing => "miss, downloading it",
+    };
+
+    println!("      Cache: {lookup}");
+    println!("      Decision: {decision}");
+}
+
+/// Prints how the cache was looked up for a mutable source and what fetch does because
+/// of it. The cache is only read, the fetch itself validates the cached file again.
+fn explain_mutable_lookup(cache: &Cache, source: &RecipeSource, args: &FetchArgs) {
+    let record = match cache.read_mutable_record(&source.url) {
+        Ok(r) => r,
+        Err(e) => {
+            println!("      Cache: record of the last download is unreadable, {e}");
+            None
+        }
+    };
+
+    let status = record
+        .as_ref()
+        .and_then(|r| blake3::Hash::from_hex(&r.hash).ok())
+        .and_then(|hash| cache.check(hash).ok());
+
+    if let Some(record) = &record {
+        let blob = match status {
+            Some(CacheStatus::Valid) => "the cached file matches its hash",
+            Some(CacheStatus::Corrupt) => "the cached file does not match its hash",
+            Some(CacheStatus::Missing) | None => "the cached file is gone",
+        };
+
+        println!(
+            "      Cache: last downloaded at {}, {blob}",
+            record.fetched_at_utc()
+        );
+    } else {
+        println!("      Cache: no record of an earlier download");
+    }
+
+    let decision = match (&record, status) {
+        _ if args.no_cache => "download, --no-cache leaves the cache alone",
+        _ if args.refresh => "refresh, --refresh ignores the earlier download",
+        (Some(record), Some(CacheStatus::Valid)) if args.prefer_cache.reuses(record.age()) => {
+            "hit, --prefer-cache uses the earlier download without asking the server"
+        }
+        (Some(_), Some(CacheStatus::Valid)) => {
+            "revalidate, the server is asked whether the earlier download changed"
+        }
+        _ => "miss, downloading it",
+    };
+
+    println!("      Decision: {decision}");
+}
+
+/// The cached download of a mutable source, if the cache policy lets it be used
+/// without asking the server whether it changed
+fn reusable_download(
+    cache: &Cache,
+    source: &RecipeSource,
+    policy: PreferCache,
+) -> Result<Option<Provenance>> {
+    if policy == PreferCache::Never {
+        return Ok(None);
+    }
+
+    let Some(record) = cache.read_mutable_record(&source.url)? else {
+        return Ok(None);
+    };
+
+    if !policy.reuses(record.age()) {
+        return Ok(None);
+    }
+
+    let hash = blake3::Hash::from_hex(&record.hash)?;
+    Ok(cache.validate(hash)?.is_valid().then_some(record))
+}
+
+fn progress_bar(name: &str, source: &RecipeSource) -> ProgressBar {
+    if output::is_quiet() {
+        return ProgressBar::hidden();
+    }
+
+    let pb = ProgressBar::no_length();
+    pb.enable_steady_tick(Duration::from_millis(100));
+    pb.set_style(
+        ProgressStyle::with_template("{wide_msg:!} {percent:>3}% [{bar:25}] {bytes:>11} / {total_bytes:<11} {binary_bytes_per_sec:>13} ETA {eta_precise:8} ")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+
+    let label = match source.checksum() {
+        Ok(hash) => hash.to_string(),
+        Err(_) => output::display_url(&source.url),
+    };
+    pb.set_message(format!("{name}/{label}"));
+
+    pb
+}
+
+fn update_progress(pb: &ProgressBar, current: u64, total: Option<u64>) {
+    if let Some(total) = total {
+        pb.set_length(total);
+        pb.set_position(current);
+    }
+}
+
+/// Finishes the progress bar on success and leaves it in place on failure
+fn finish_progress<T>(pb: &ProgressBar, result: Result<T>) -> Result<T> {
+    match &result {
+        Ok(_) => pb.finish(),
+        Err(_) => pb.abandon(),
+    }
+    result
+}
+
+/// Prints a line that stays in the scrollback once the progress bar is gone
+fn print_summary(name: &str, downloader: &Downloader<RecipeSource>) {
+    if output::is_quiet() {
+        return;
+    }
+
+    if let Some(stats) = downloader.stats() {
+        println!(
+            "{} {name} ({} in {:.1}s, {}/s)",
+            paint("Fetched", Style::Success),
+            format_bytes(stats.bytes),
+            stats.elapsed.as_secs_f64(),
+            format_bytes(stats.bytes_per_sec())
+        );
+    }
+
+    if let Some(resolved_url) = downloader.resolved_url() {
+        println!("      Served from: {}", output::display_url(resolved_url));
+    }
+}
+
+/// The arch a resolved recipe is for, `None` for an arch-less recipe, and why that
+/// recipe was picked
+fn describe_arch(
+    id: &PackageId,
+    arches: &[String],
+    source: &ArchSource,
+    recipe_path: &Path,
+    exact: bool,
+) -> (Option<String>, String) {
+    let arch = Repository::recipe_arch(recipe_path);
+
+    let reason = match (&id.arch, &arch) {
+        (Some(named), None) => {
+            return (
+                Some(named.clone()),
+                "named by the package id, arch-less recipe shared by it".to_string(),
+            );
         }
+        (Some(_), _) => "named by the package id".to_string(),
+        (None, Some(arch)) => match arches.iter().position(|a| a == arch) {
+            Some(0) => format!("preferred architecture, from {source}"),
+            Some(rank) => format!(
+                "preference {} from {source}, no recipe for {}",
+                rank + 1,
+                arches[..rank].join(", ")
+            ),
+            None => "not in the preference list".to_string(),
+        },
+        (None, None) if exact => "arch-less recipe, arch fallbacks are disabled".to_string(),
+        (None, None) => format!("arch-less recipe, no recipe for {}", arches.join(", ")),
+    };
+
+    (arch, reason)
+}
+
+/// Prints a value as JSON or YAML, returning false for text output which the caller renders
+fn print_serialized<T: Serialize>(value: &T, format: OutputFormat) -> bool {
+    let serialized = match format {
+        OutputFormat::Text => return false,
+        OutputFormat::Json => serde_json::to_string_pretty(value).map_err(anyhow::Error::from),
+        OutputFormat::Yaml => serde_yaml::to_string(value).map_err(anyhow::Error::from),
+    };
+
+    match serialized {
+        Ok(s) => println!("{}", s.trim_end()),
+        Err(e) => print_error(&format!("Failed to serialize output: {e}")),
     }
+
+    true
 }
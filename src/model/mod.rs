@@ -2,6 +2,6 @@ mod package_id;
 mod recipe;
 mod source;
 
-pub use package_id::PackageId;
-pub use recipe::{Recipe, RecipeSource};
-pub use source::{Checksum, Source};
+pub use package_id::{CapabilityTerm, KNOWN_ARCHES, PackageId, PackageQuery, is_known_arch};
+pub use recipe::{ChecksumManifest, LintIssue, Recipe, RecipeSource, SourcePart};
+pub use source::{Checksum, PartSource, Source};
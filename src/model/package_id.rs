@@ -1,48 +1,262 @@
-#[derive(Debug)]
+use std::fmt;
+
+#[derive(Debug, Clone)]
 pub struct PackageId {
     pub repo: String,
     pub name: String,
     pub version: String,
     pub flavours: Vec<String>,
     pub arch: Option<String>,
+
+    /// Capabilities the flavour combination must have or lack, e.g. `:+wayland+!x11`
+    pub capabilities: Vec<CapabilityTerm>,
+}
+
+/// A capability a recipe variant is asked to have, or with `!` to lack
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapabilityTerm {
+    pub name: String,
+    pub wanted: bool,
+}
+
+impl CapabilityTerm {
+    fn from_term_str(s: &str) -> Self {
+        match s.strip_prefix('!') {
+            Some(name) => Self {
+                name: name.to_string(),
+                wanted: false,
+            },
+            None => Self {
+                name: s.to_string(),
+                wanted: true,
+            },
+        }
+    }
+
+    pub fn matches(&self, capabilities: &[String]) -> bool {
+        capabilities.contains(&self.name) == self.wanted
+    }
+}
+
+impl fmt::Display for CapabilityTerm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.wanted {
+            write!(f, "+{}", self.name)
+        } else {
+            write!(f, "+!{}", self.name)
+        }
+    }
+}
+
+/// Architectures recognised when walking a recipe tree, any other directory
+/// below a package version is treated as a flavour.
+pub const KNOWN_ARCHES: &[&str] = &[
+    "x86_64",
+    "i686",
+    "aarch64",
+    "armv7",
+    "riscv64",
+    "ppc64le",
+    "s390x",
+    "loongarch64",
+];
+
+pub fn is_known_arch(name: &str) -> bool {
+    KNOWN_ARCHES.contains(&name)
 }
 
 impl PackageId {
+    /// Parses a dependency id, which lives in `repo` unless it names a repository
+    pub fn from_dep_str(s: &str, repo: &str) -> Self {
+        let mut id = Self::from_id_str(s.to_string());
+
+        if !s.contains('/') {
+            id.repo = repo.to_string();
+        }
+
+        id
+    }
+
     pub fn from_id_str(s: String) -> Self {
+        let query = PackageQuery::from_query_str(s);
+
+        Self {
+            repo: query.repo,
+            name: query.name,
+            version: query.version.unwrap_or("latest".to_string()),
+            flavours: query.flavours.unwrap_or_default(),
+            arch: query.arch,
+            capabilities: query.capabilities,
+        }
+    }
+}
+
+/// A package id where the version, flavours and arch may be left out to match
+/// any value.
+#[derive(Debug)]
+pub struct PackageQuery {
+    pub repo: String,
+    pub name: String,
+    pub version: Option<String>,
+    pub flavours: Option<Vec<String>>,
+    pub arch: Option<String>,
+    pub capabilities: Vec<CapabilityTerm>,
+}
+
+impl PackageQuery {
+    /// Parses `repo/name@version:flavour+capability+!capability#arch`, where every part
+    /// but the name may be left out. Capabilities follow the last flavour, or a `:` of
+    /// their own without flavours, as names and versions may contain `+` themselves.
+    pub fn from_query_str(s: String) -> Self {
         let (rest, arch) = if let Some(pos) = s.rfind('#') {
             (s[..pos].to_string(), Some(s[pos + 1..].to_string()))
         } else {
             (s, None)
         };
 
+        let flavour_start = rest.rfind(':').map(|pos| pos + 1);
+        let plus = flavour_start.and_then(|start| Some(start + rest[start..].find('+')?));
+
+        let (rest, capabilities) = if let (Some(start), Some(pos)) = (flavour_start, plus) {
+            let capabilities = rest[pos + 1..]
+                .split('+')
+                .filter(|term| !term.is_empty())
+                .map(CapabilityTerm::from_term_str)
+                .collect();
+
+            // Capabilities without flavours leave no flavour behind
+            let end = if pos == start { start - 1 } else { pos };
+            (rest[..end].to_string(), capabilities)
+        } else {
+            (rest, Vec::new())
+        };
+
         let (repo, rest) = if let Some(pos) = rest.find('/') {
             (rest[..pos].to_string(), rest[pos + 1..].to_string())
         } else {
             ("default".to_string(), rest)
         };
 
-        let (name, rest) = if let Some(pos) = rest.find('@') {
-            (rest[..pos].to_string(), rest[pos + 1..].to_string())
+        let (name, version, flavours) = if let Some(pos) = rest.find('@') {
+            let mut parts = rest[pos + 1..].split(':');
+            let version = parts.next().map(|s| s.to_string());
+            let flavours = parts.map(|s| s.to_string()).collect::<Vec<_>>();
+            (rest[..pos].to_string(), version, flavours)
         } else if let Some(pos) = rest.find(':') {
             // This handles the case where flavours are present, but no version
-            (
-                rest[..pos].to_string(),
-                format!("latest:{}", &rest[pos + 1..]),
-            )
+            let flavours = rest[pos + 1..].split(':').map(|s| s.to_string()).collect();
+            (rest[..pos].to_string(), None, flavours)
         } else {
-            (rest, "latest".to_string())
+            (rest, None, Vec::new())
         };
 
-        let mut parts = rest.split(':');
-        let version = parts.next().unwrap_or("latest").to_string();
-        let flavours = parts.map(|s| s.to_string()).collect::<Vec<_>>();
-
         Self {
             repo,
             name,
             version,
-            flavours,
+            flavours: (!flavours.is_empty()).then_some(flavours),
             arch,
+            capabilities,
+        }
+    }
+
+    /// Whether a flavour combination could still lead to a match once more flavours are added
+    pub fn matches_flavour_prefix(&self, flavours: &[String]) -> bool {
+        match &self.flavours {
+            Some(wanted) => wanted.starts_with(flavours),
+            None => true,
+        }
+    }
+
+    pub fn matches_flavours(&self, flavours: &[String]) -> bool {
+        match &self.flavours {
+            Some(wanted) => wanted == flavours,
+            None => true,
+        }
+    }
+
+    /// Whether a variant with these capabilities has every wanted one and none of the others
+    pub fn matches_capabilities(&self, capabilities: &[String]) -> bool {
+        self.capabilities.iter().all(|c| c.matches(capabilities))
+    }
+
+    pub fn matches_arch(&self, arch: Option<&str>) -> bool {
+        match &self.arch {
+            Some(wanted) => arch == Some(wanted.as_str()),
+            None => true,
+        }
+    }
+}
+
+impl fmt::Display for PackageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}@{}", self.repo, self.name, self.version)?;
+
+        for flavour in &self.flavours {
+            write!(f, ":{flavour}")?;
+        }
+
+        if self.flavours.is_empty() && !self.capabilities.is_empty() {
+            write!(f, ":")?;
+        }
+
+        for capability in &self.capabilities {
+            write!(f, "{capability}")?;
+        }
+
+        if let Some(arch) = &self.arch {
+            write!(f, "#{arch}")?;
         }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plus_in_names_and_versions_is_kept() {
+        let id = PackageId::from_id_str("default/libstdc++@1.0".to_string());
+        assert_eq!(id.name, "libstdc++");
+        assert_eq!(id.version, "1.0");
+        assert!(id.capabilities.is_empty());
+
+        let id = PackageId::from_id_str("foo@1.2+dfsg".to_string());
+        assert_eq!(id.name, "foo");
+        assert_eq!(id.version, "1.2+dfsg");
+        assert!(id.capabilities.is_empty());
+    }
+
+    #[test]
+    fn capabilities_follow_the_flavours() {
+        let id = PackageId::from_id_str("name@1.0:flav+cap+!other#x86_64".to_string());
+        assert_eq!(id.name, "name");
+        assert_eq!(id.version, "1.0");
+        assert_eq!(id.flavours, ["flav"]);
+        assert_eq!(id.arch.as_deref(), Some("x86_64"));
+        assert_eq!(
+            id.capabilities,
+            [
+                CapabilityTerm::from_term_str("cap"),
+                CapabilityTerm::from_term_str("!other")
+            ]
+        );
+        assert!(!id.capabilities[1].wanted);
+        assert_eq!(id.to_string(), "default/name@1.0:flav+cap+!other#x86_64");
+    }
+
+    #[test]
+    fn capabilities_without_flavours() {
+        let id = PackageId::from_id_str("foo@1.2+dfsg:+wayland".to_string());
+        assert_eq!(id.version, "1.2+dfsg");
+        assert!(id.flavours.is_empty());
+        assert_eq!(id.capabilities, [CapabilityTerm::from_term_str("wayland")]);
+
+        let reparsed = PackageId::from_id_str(id.to_string());
+        assert_eq!(reparsed.version, "1.2+dfsg");
+        assert!(reparsed.flavours.is_empty());
+        assert_eq!(reparsed.capabilities, id.capabilities);
     }
 }
@@ -1,18 +1,99 @@
-use anyhow::Result;
-use serde::Deserialize;
-use std::path::Path;
+use anyhow::{Result, anyhow};
+use serde::{
+    Deserialize, Serialize,
+    de::{self, Visitor},
+};
+use serde_yaml::{Mapping, Value};
+use std::path::{Path, PathBuf};
+use url::Url;
 
-use crate::model::{Checksum, Source};
+use crate::{
+    model::{Checksum, PartSource, Source},
+    util::{interpolate_env, normalize_path, redact_url},
+};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct RecipeSource {
     pub url: String,
+
+    /// Other URLs serving the same content, tried according to the mirror strategy.
+    /// Relative paths are joined onto the repository base URL like the source URL.
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+
+    /// Name build steps use to refer to this source, unnamed sources are referred to by index
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Only mutable sources may omit the hash, a present hash is always enforced
+    #[serde(default)]
+    pub hash: Option<String>,
+
+    /// Additional sha256 checksum, verified together with the blake3 hash
+    #[serde(default)]
+    pub sha256: Option<String>,
+
+    /// Rolling sources whose content is expected to change between syncs
+    #[serde(default)]
+    pub mutable: bool,
+
+    /// Fetch from exactly this URL, failing instead of following redirects
+    #[serde(default)]
+    pub exact_url: bool,
+
+    /// Best effort source, such as extra documentation. Failing to download or verify it
+    /// is only a warning and the other sources are still fetched.
+    #[serde(default)]
+    pub optional: bool,
+
+    /// Published sha256 sums file the download is verified against, in addition to the hash
+    #[serde(default)]
+    pub sha256sums: Option<ChecksumManifest>,
+
+    /// Volumes the content is split into upstream, downloaded and verified one by one
+    /// and joined in order into the content the hash is checked against. The source URL
+    /// then only names the joined file and is not requested.
+    #[serde(default)]
+    pub parts: Vec<SourcePart>,
+}
+
+/// A volume of a split source, such as `foo.tar.gz.001`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SourcePart {
+    /// URL of the volume, a relative URL is relative to the source URL
+    pub url: String,
     pub hash: String,
 }
 
+/// A checksum file published next to release artifacts, such as `SHA256SUMS`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChecksumManifest {
+    /// URL of the manifest, a relative URL is relative to the source URL
+    pub url: String,
+
+    /// Name the source is listed under, the last segment of the source URL by default
+    #[serde(default)]
+    pub file: Option<String>,
+}
+
+impl RecipeSource {
+    /// The name of this source, or its index in the recipe when it has none
+    pub fn reference(&self, index: usize) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => index.to_string(),
+        }
+    }
+}
+
 impl Checksum<blake3::Hash> for RecipeSource {
     fn checksum(&self) -> Result<blake3::Hash> {
-        Ok(blake3::Hash::from_hex(&self.hash)?)
+        let hash = self.hash.as_ref().ok_or(anyhow!(
+            "Source {} does not specify a hash",
+            redact_url(&self.url)
+        ))?;
+
+        Ok(blake3::Hash::from_hex(hash)?)
     }
 }
 
@@ -20,23 +101,593 @@ impl Source for RecipeSource {
     fn url(&self) -> String {
         self.url.clone()
     }
+
+    /// Fills in `${NAME}` references to environment variables, so credentials such
+    /// as tokens in signed URLs stay out of the recipe
+    fn request_url(&self) -> Result<String> {
+        interpolate_env(&self.url)
+            .map_err(|e| anyhow!("Cannot build URL of source {}, {e}", redact_url(&self.url)))
+    }
+
+    fn mirrors(&self) -> Vec<String> {
+        self.mirrors.clone()
+    }
+
+    fn mirror_request_url(&self, mirror: &str) -> Result<String> {
+        interpolate_env(mirror)
+            .map_err(|e| anyhow!("Cannot build URL of mirror {}, {e}", redact_url(mirror)))
+    }
+
+    fn is_mutable(&self) -> bool {
+        self.mutable
+    }
+
+    fn follows_redirects(&self) -> bool {
+        !self.exact_url
+    }
+
+    fn sha256_manifest(&self) -> Result<Option<(String, String)>> {
+        let Some(manifest) = &self.sha256sums else {
+            return Ok(None);
+        };
+
+        let url = interpolate_env(&manifest.url).map_err(|e| {
+            anyhow!(
+                "Cannot build sha256sums URL of source {}, {e}",
+                redact_url(&self.url)
+            )
+        })?;
+
+        let file = match &manifest.file {
+            Some(file) => file.clone(),
+            None => Url::parse(&self.url)
+                .ok()
+                .and_then(|u| u.path_segments()?.next_back().map(str::to_string))
+                .filter(|name| !name.is_empty())
+                .ok_or(anyhow!(
+                    "Source {} has no file name to look up in its sha256sums, set file",
+                    redact_url(&self.url)
+                ))?,
+        };
+
+        Ok(Some((url, file)))
+    }
+
+    fn sha256_manifest_url(&self) -> Option<String> {
+        self.sha256sums
+            .as_ref()
+            .map(|manifest| manifest.url.clone())
+    }
+
+    fn sha256(&self) -> Option<&str> {
+        self.sha256.as_deref()
+    }
+
+    fn parts(&self) -> Result<Vec<PartSource>> {
+        if !self.parts.is_empty() && (self.mutable || !self.mirrors.is_empty()) {
+            return Err(anyhow!(
+                "Split source {} cannot be mutable or have mirrors, its parts are fetched from their own URLs",
+                redact_url(&self.url)
+            ));
+        }
+
+        self.parts
+            .iter()
+            .map(|part| {
+                let shown = redact_url(&part.url);
+
+                Ok(PartSource {
+                    url: part.url.clone(),
+                    request_url: interpolate_env(&part.url)
+                        .map_err(|e| anyhow!("Cannot build URL of part {shown}, {e}"))?,
+                    hash: blake3::Hash::from_hex(&part.hash)
+                        .map_err(|e| anyhow!("Invalid hash of part {shown}, {e}"))?,
+                    follows_redirects: !self.exact_url,
+                })
+            })
+            .collect()
+    }
 }
 
-#[derive(Debug, Deserialize, Default)]
+/// A problem found by [`Recipe::lint`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LintIssue {
+    /// The recipe cannot be loaded because of it
+    Error(String),
+
+    /// A field tetra does not know and ignores, most likely a typo
+    UnknownField(String),
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
 pub struct Recipe {
     pub name: String,
     pub version: String,
     pub license: String,
     pub maintainer: String,
 
+    /// Sources to fetch, empty for a metapackage. The sources of a sidecar file named
+    /// by `sources_file`, relative to the recipe, follow the ones listed here.
     #[serde(default)]
     pub sources: Vec<RecipeSource>,
+
+    /// Package ids this package depends on, ids without a repository refer to the same repository
+    #[serde(default)]
+    pub depends: Vec<String>,
+
+    /// Names of packages, real or virtual, this package cannot be installed together with
+    #[serde(default)]
+    pub conflicts: Vec<String>,
+
+    /// Virtual package names this package satisfies dependencies on
+    #[serde(default)]
+    pub provides: Vec<String>,
+
+    /// Features of this flavour combination that package ids can ask for with
+    /// `+name`, in addition to the names of its flavours
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+
+    /// Absolute paths of the files the package installs, e.g. `/usr/bin/foo`, which
+    /// `tetra provides` looks up
+    #[serde(default)]
+    pub files: Vec<String>,
+
+    /// Arches an arch-less recipe is shared by, instead of a copy of it in the directory
+    /// of each arch. Ids naming one of them resolve to it unless that arch has a recipe
+    /// of its own, and it is not used for other arches. Empty for a recipe of any arch.
+    #[serde(default)]
+    pub arches: Vec<String>,
+
+    /// Former names of this package, as `name` or `repo/name`. Ids naming a package
+    /// that no longer exists resolve to this one instead, and so do dependencies on a
+    /// `repo/name` of another repository.
+    #[serde(default)]
+    pub replaces: Vec<String>,
+
+    /// Blake3 hash over the url and hash of every source, see [`Recipe::sources_digest`]
+    #[serde(default)]
+    pub sources_hash: Option<String>,
+
+    /// Digest of the sources as written, before relative URLs were resolved
+    #[serde(skip)]
+    written_sources_digest: Option<blake3::Hash>,
 }
 
 impl Recipe {
-    pub fn from_path(path: &Path) -> Result<Self> {
-        let recipe_str = std::fs::read_to_string(path)?;
-        let recipe: Self = serde_yaml::from_str(&recipe_str)?;
+    const MAX_EXTENDS_DEPTH: usize = 8;
+
+    /// Extensions of sidecar source files besides the recipe extensions, `lock` files
+    /// are YAML like recipes
+    pub const SIDECAR_EXTENSIONS: &[&str] = &["lock", "json"];
+
+    /// A recipe without sources is a metapackage, it only aggregates its dependencies
+    pub fn is_metapackage(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Fields every recipe has to set, after merging the recipes it extends
+    const REQUIRED_FIELDS: &[&str] = &["name", "version", "license", "maintainer"];
+
+    /// Checks a recipe and reports every problem found instead of only the first one,
+    /// checking each field and each source on its own. Only a recipe that cannot be
+    /// parsed as YAML, or whose extends chain cannot be loaded, yields a single error.
+    pub fn lint<F>(path: &Path, read: F) -> Vec<LintIssue>
+    where
+        F: Fn(&Path) -> Result<String>,
+    {
+        let merged = Self::load_extended(path, &read, &mut Vec::new()).and_then(|mut merged| {
+            Self::merge_sources_file(&mut merged, &read)?;
+            Ok(merged)
+        });
+
+        let fields = match merged {
+            Ok(Value::Mapping(fields)) => fields,
+            Ok(_) => return vec![LintIssue::Error("Recipe is not a mapping".to_string())],
+            Err(e) => return vec![LintIssue::Error(e.to_string())],
+        };
+
+        let mut issues = Vec::new();
+        let known = struct_fields::<Self>();
+
+        for field in Self::REQUIRED_FIELDS {
+            if !fields.contains_key(*field) {
+                issues.push(LintIssue::Error(format!("missing field `{field}`")));
+            }
+        }
+
+        for (key, value) in &fields {
+            let name = key.as_str().unwrap_or_default();
+
+            if !known.contains(&name) {
+                issues.push(LintIssue::UnknownField(format!("unknown field `{name}`")));
+                continue;
+            }
+
+            match (name, value) {
+                ("sources", Value::Sequence(sources)) => {
+                    for (index, source) in sources.iter().enumerate() {
+                        issues.extend(Self::lint_source(index, source));
+                    }
+                }
+                _ => {
+                    // Required fields other than the checked one are filled in, so any
+                    // error is about this field
+                    let mut probe = Mapping::new();
+                    for field in Self::REQUIRED_FIELDS {
+                        probe.insert((*field).into(), "".into());
+                    }
+                    probe.insert(key.clone(), value.clone());
+
+                    if let Err(e) = serde_yaml::from_value::<Self>(Value::Mapping(probe)) {
+                        issues.push(LintIssue::Error(format!("{name}: {e}")));
+                    }
+                }
+            }
+        }
+
+        // Checks across fields only run on a recipe whose fields are each valid
+        if !issues.iter().any(|i| matches!(i, LintIssue::Error(_)))
+            && let Err(e) = Self::load(path, read)
+        {
+            issues.push(LintIssue::Error(e.to_string()));
+        }
+
+        issues
+    }
+
+    fn lint_source(index: usize, source: &Value) -> Vec<LintIssue> {
+        let mut issues = Vec::new();
+
+        if let Value::Mapping(fields) = source {
+            let known = struct_fields::<RecipeSource>();
+
+            for key in fields.keys() {
+                let name = key.as_str().unwrap_or_default();
+                if !known.contains(&name) {
+                    issues.push(LintIssue::UnknownField(format!(
+                        "unknown field `{name}` in sources[{index}]"
+                    )));
+                }
+            }
+        }
+
+        if let Err(e) = serde_yaml::from_value::<RecipeSource>(source.clone()) {
+            issues.push(LintIssue::Error(format!("sources[{index}]: {e}")));
+        }
+
+        issues
+    }
+
+    /// Loads a recipe through the given reader, so recipes can also be read from
+    /// places other than the filesystem, such as packed repositories
+    pub fn load<F>(path: &Path, read: F) -> Result<Self>
+    where
+        F: Fn(&Path) -> Result<String>,
+    {
+        let mut merged = Self::load_extended(path, &read, &mut Vec::new())?;
+        Self::merge_sources_file(&mut merged, &read)?;
+
+        let mut recipe: Self = serde_yaml::from_value(merged)?;
+        recipe.check_source_names()?;
+        recipe.written_sources_digest = Some(recipe.sources_digest());
         Ok(recipe)
     }
+
+    /// Joins source URLs that are relative paths onto the repository base URL and
+    /// relative sha256sums and part URLs onto their source URL, absolute URLs are left
+    /// as they are
+    pub fn resolve_source_urls(&mut self, base_url: Option<&str>) -> Result<()> {
+        for source in &mut self.sources {
+            source.url = join_base_url(&source.url, base_url)?;
+
+            for mirror in &mut source.mirrors {
+                *mirror = join_base_url(mirror, base_url)?;
+            }
+        }
+
+        for source in &mut self.sources {
+            for part in &mut source.parts {
+                if Url::parse(&part.url).is_err() {
+                    part.url = Url::parse(&source.url)?.join(&part.url)?.to_string();
+                }
+            }
+
+            let Some(manifest) = &mut source.sha256sums else {
+                continue;
+            };
+
+            if Url::parse(&manifest.url).is_err() {
+                manifest.url = Url::parse(&source.url)?.join(&manifest.url)?.to_string();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Aggregate hash of the source section, blake3 over a `<url> <hash>` line for each
+    /// source, sorted. Sources without a hash contribute an empty hash.
+    pub fn sources_digest(&self) -> blake3::Hash {
+        let mut lines = self
+            .sources
+            .iter()
+            .map(|s| format!("{} {}\n", s.url, s.hash.as_deref().unwrap_or("")))
+            .collect::<Vec<_>>();
+        lines.sort();
+
+        let mut hasher = blake3::Hasher::new();
+        for line in lines {
+            hasher.update(line.as_bytes());
+        }
+        hasher.finalize()
+    }
+
+    /// The aggregate hash of the sources as written in the recipe, which is what
+    /// `sources_hash` is compared against, as resolved URLs depend on the base URL
+    pub fn written_sources_digest(&self) -> blake3::Hash {
+        self.written_sources_digest
+            .unwrap_or_else(|| self.sources_digest())
+    }
+
+    /// Checks the sources against the declared `sources_hash`, catching sources that
+    /// were added or swapped without updating it. Recipes without one always pass.
+    pub fn check_sources_hash(&self) -> Result<()> {
+        let Some(expected) = &self.sources_hash else {
+            return Ok(());
+        };
+
+        let expected = blake3::Hash::from_hex(expected)
+            .map_err(|e| anyhow!("Invalid sources_hash {expected}, {e}"))?;
+        let computed = self.written_sources_digest();
+
+        if expected != computed {
+            return Err(anyhow!(
+                "sources_hash {expected} does not match the sources of the recipe, which hash to {computed}"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Looks up a source by its name or index
+    pub fn source_by_ref(&self, reference: &str) -> Option<&RecipeSource> {
+        match reference.parse::<usize>() {
+            Ok(index) => self.sources.get(index),
+            Err(_) => self
+                .sources
+                .iter()
+                .find(|s| s.name.as_deref() == Some(reference)),
+        }
+    }
+
+    /// Picks a single source by its index, its name, its exact URL or a unique part of
+    /// its URL. A selector matching several URLs is an error listing them.
+    pub fn select_source(&self, selector: &str) -> Result<&RecipeSource> {
+        if let Some(source) = self.source_by_ref(selector) {
+            return Ok(source);
+        }
+
+        if let Ok(index) = selector.parse::<usize>() {
+            return Err(anyhow!(
+                "Source index {index} is out of range, recipe has {} source(s)",
+                self.sources.len()
+            ));
+        }
+
+        if let Some(source) = self.sources.iter().find(|s| s.url == selector) {
+            return Ok(source);
+        }
+
+        let matches = self
+            .sources
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.url.contains(selector))
+            .collect::<Vec<_>>();
+
+        match matches[..] {
+            [(_, source)] => Ok(source),
+            [] => Err(anyhow!("Recipe has no source with name or URL {selector}")),
+            _ => Err(anyhow!(
+                "Source selector {selector} is ambiguous, it matches {}",
+                matches
+                    .iter()
+                    .map(|(index, s)| format!("{} ({})", s.reference(*index), redact_url(&s.url)))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+
+    /// Source names double as directory names and must not collide with index references
+    fn check_source_names(&self) -> Result<()> {
+        let mut seen = Vec::new();
+
+        for name in self.sources.iter().filter_map(|s| s.name.as_deref()) {
+            if name.is_empty()
+                || name == "."
+                || name == ".."
+                || name.contains('/')
+                || name.parse::<usize>().is_ok()
+            {
+                return Err(anyhow!("Invalid source name {name:?}"));
+            }
+
+            if seen.contains(&name) {
+                return Err(anyhow!("Source name {name:?} is used more than once"));
+            }
+
+            seen.push(name);
+        }
+
+        Ok(())
+    }
+
+    /// Loads a recipe document merged with the chain of recipes it `extends`.
+    /// The path to extend is relative to the extending recipe, and each top-level
+    /// field set by the extending recipe replaces the one of its parent.
+    fn load_extended<F>(path: &Path, read: &F, chain: &mut Vec<PathBuf>) -> Result<Value>
+    where
+        F: Fn(&Path) -> Result<String>,
+    {
+        let path = normalize_path(path);
+
+        if chain.contains(&path) {
+            return Err(anyhow!("Recipe {path:#?} is part of an extends cycle"));
+        }
+
+        if chain.len() >= Self::MAX_EXTENDS_DEPTH {
+            return Err(anyhow!(
+                "Recipe {path:#?} exceeds the maximum extends depth of {}",
+                Self::MAX_EXTENDS_DEPTH
+            ));
+        }
+
+        chain.push(path.clone());
+
+        let recipe_str = read(&path).map_err(|e| anyhow!("Failed to load recipe {path:#?}, {e}"))?;
+        let mut recipe: Value = serde_yaml::from_str(&recipe_str)?;
+
+        let Value::Mapping(fields) = &mut recipe else {
+            return Err(anyhow!("Recipe {path:#?} is not a mapping"));
+        };
+
+        // The sidecar is relative to the recipe naming it, not to the one extending it
+        if let Some(file) = fields.get_mut("sources_file") {
+            let relative = file.as_str().ok_or(anyhow!(
+                "Recipe {path:#?} has a non-path sources_file field"
+            ))?;
+
+            let sidecar = path.parent().unwrap_or(Path::new("")).join(relative);
+            *file = normalize_path(&sidecar)
+                .to_string_lossy()
+                .into_owned()
+                .into();
+        }
+
+        let Some(extends) = fields.remove("extends") else {
+            return Ok(recipe);
+        };
+
+        let extends = extends
+            .as_str()
+            .ok_or(anyhow!("Recipe {path:#?} has a non-path extends field"))?;
+
+        let parent_path = path.parent().unwrap_or(Path::new("")).join(extends);
+        let mut parent = Self::load_extended(&parent_path, read, chain)?;
+
+        if let Value::Mapping(parent_fields) = &mut parent {
+            for (key, value) in std::mem::take(fields) {
+                parent_fields.insert(key, value);
+            }
+        }
+
+        Ok(parent)
+    }
+
+    /// Appends the sources of the sidecar file a merged recipe names in `sources_file`.
+    /// The file holds a list of sources, or a mapping with a `sources` list, as YAML or
+    /// as JSON if its extension is `json`.
+    fn merge_sources_file<F>(recipe: &mut Value, read: &F) -> Result<()>
+    where
+        F: Fn(&Path) -> Result<String>,
+    {
+        let Value::Mapping(fields) = recipe else {
+            return Ok(());
+        };
+
+        let Some(file) = fields.remove("sources_file") else {
+            return Ok(());
+        };
+
+        let path = PathBuf::from(file.as_str().unwrap_or_default());
+        let contents =
+            read(&path).map_err(|e| anyhow!("Failed to load sources file {path:#?}, {e}"))?;
+
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let sidecar: Value = match extension {
+            "json" => serde_json::from_str(&contents)?,
+            "lock" | "yml" | "yaml" => serde_yaml::from_str(&contents)?,
+            _ => {
+                return Err(anyhow!(
+                    "Sources file {path:#?} is neither YAML nor JSON, expected a lock, yml, yaml or json extension"
+                ));
+            }
+        };
+
+        let sources = match sidecar {
+            Value::Sequence(sources) => sources,
+            Value::Mapping(mut sidecar) => match sidecar.remove("sources") {
+                Some(Value::Sequence(sources)) => sources,
+                _ => return Err(anyhow!("Sources file {path:#?} has no sources list")),
+            },
+            _ => return Err(anyhow!("Sources file {path:#?} has no sources list")),
+        };
+
+        match fields.get_mut("sources") {
+            Some(Value::Sequence(inline)) => inline.extend(sources),
+            Some(_) => return Err(anyhow!("Recipe sources must be a list")),
+            None => {
+                fields.insert("sources".into(), Value::Sequence(sources));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Names of the fields a derived `Deserialize` struct accepts. Serde hands them to the
+/// deserializer when the struct is deserialized, which is all this deserializer asks for.
+fn struct_fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> de::Deserializer<'de> for FieldNames<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("only the field names are read"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes
+            byte_buf option unit unit_struct newtype_struct seq tuple tuple_struct map enum
+            identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// Joins a relative source path onto the repository base URL, absolute URLs are kept
+fn join_base_url(url: &str, base_url: Option<&str>) -> Result<String> {
+    if Url::parse(url).is_ok() {
+        return Ok(url.to_string());
+    }
+
+    let base_url = base_url.ok_or(anyhow!(
+        "Source {} is a relative path, but the repository has no base_url",
+        redact_url(url)
+    ))?;
+
+    // Without a trailing slash the last segment of the base would be replaced
+    let base = if base_url.ends_with('/') {
+        Url::parse(base_url)
+    } else {
+        Url::parse(&format!("{base_url}/"))
+    }
+    .map_err(|e| anyhow!("Invalid repository base_url {}, {e}", redact_url(base_url)))?;
+
+    Ok(base.join(url)?.to_string())
 }
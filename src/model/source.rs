@@ -1,9 +1,110 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use std::path::Path;
+
+use crate::util::{redact_url, sha256_file};
 
 pub trait Checksum<T> {
     fn checksum(&self) -> Result<T>;
 }
 
 pub trait Source: Checksum<blake3::Hash> {
+    /// URL identifying the source, safe to print and to record in the cache
     fn url(&self) -> String;
+
+    /// URL the source is requested from. It may hold secrets and is never shown.
+    fn request_url(&self) -> Result<String> {
+        Ok(self.url())
+    }
+
+    /// URLs of other servers with the same content, safe to print
+    fn mirrors(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// URL a mirror is requested from, as [`Source::request_url`] is for the source URL
+    fn mirror_request_url(&self, mirror: &str) -> Result<String> {
+        Ok(mirror.to_string())
+    }
+
+    fn is_mutable(&self) -> bool {
+        false
+    }
+
+    /// Whether a download may follow redirects to another URL
+    fn follows_redirects(&self) -> bool {
+        true
+    }
+
+    /// Request URL of a published sha256 sums file covering the source, together with
+    /// the file name the source is listed under in it
+    fn sha256_manifest(&self) -> Result<Option<(String, String)>> {
+        Ok(None)
+    }
+
+    /// URL of the sha256 sums file as the source lists it, safe to print
+    fn sha256_manifest_url(&self) -> Option<String> {
+        None
+    }
+
+    /// Hex sha256 digest the content must have besides its blake3 hash, if any
+    fn sha256(&self) -> Option<&str> {
+        None
+    }
+
+    /// Checks the sha256 digest computed over downloaded content against [`Source::sha256`]
+    fn verify_sha256(&self, computed: &str) -> Result<()> {
+        match self.sha256() {
+            Some(expected) if !computed.eq_ignore_ascii_case(expected) => Err(anyhow!(
+                "Source {} sha256 {computed} does not match {expected}",
+                redact_url(&self.url())
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Volumes the content is split into, in the order they are joined. Empty for
+    /// content served whole, which is all of it unless a source says otherwise.
+    fn parts(&self) -> Result<Vec<PartSource>> {
+        Ok(Vec::new())
+    }
+
+    /// Checks downloaded content against checksums other than the blake3 hash
+    fn verify(&self, path: &Path) -> Result<()> {
+        match self.sha256() {
+            Some(_) => self.verify_sha256(&sha256_file(path)?),
+            None => Ok(()),
+        }
+    }
+}
+
+/// One volume of a split source, downloaded and verified like a source of its own
+#[derive(Debug, Clone)]
+pub struct PartSource {
+    /// URL of the volume, safe to print
+    pub url: String,
+
+    /// URL the volume is requested from, it may hold secrets
+    pub request_url: String,
+    pub hash: blake3::Hash,
+    pub follows_redirects: bool,
+}
+
+impl Checksum<blake3::Hash> for PartSource {
+    fn checksum(&self) -> Result<blake3::Hash> {
+        Ok(self.hash)
+    }
+}
+
+impl Source for PartSource {
+    fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    fn request_url(&self) -> Result<String> {
+        Ok(self.request_url.clone())
+    }
+
+    fn follows_redirects(&self) -> bool {
+        self.follows_redirects
+    }
 }
@@ -0,0 +1,82 @@
+use anyhow::{Result, anyhow};
+
+use crate::{
+    TetraRoot,
+    config::DownloadLimits,
+    model::{Checksum, PackageId, Source},
+    net::{Download, Downloader},
+    store::CacheBackend,
+    util::{interpolate_env, parallel_map, redact_url},
+};
+
+/// A source URL whose hash is not known yet. It is downloaded like a mutable source,
+/// as there is no hash to pin its content to.
+#[derive(Debug, Clone)]
+pub struct UnhashedSource {
+    pub url: String,
+}
+
+impl Checksum<blake3::Hash> for UnhashedSource {
+    fn checksum(&self) -> Result<blake3::Hash> {
+        Err(anyhow!("Source {} has no hash yet", redact_url(&self.url)))
+    }
+}
+
+impl Source for UnhashedSource {
+    fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    fn request_url(&self) -> Result<String> {
+        interpolate_env(&self.url)
+            .map_err(|e| anyhow!("Cannot build URL of source {}, {e}", redact_url(&self.url)))
+    }
+
+    fn is_mutable(&self) -> bool {
+        true
+    }
+}
+
+/// Hashes of a source downloaded by [`checksum_all`]
+#[derive(Debug, Clone)]
+pub struct SourceChecksum {
+    pub url: String,
+    pub hash: blake3::Hash,
+
+    /// Set if the sha256 was asked for
+    pub sha256: Option<String>,
+}
+
+/// Downloads every source into the cache and hashes it, and computes its sha256 if
+/// `sha256` is set. The downloads run in parallel, one failing does not stop the others.
+pub fn checksum_all(
+    tetra_root: &TetraRoot,
+    cache: &dyn CacheBackend,
+    sources: &[UnhashedSource],
+    package_id: &PackageId,
+    limits: &DownloadLimits,
+    sha256: bool,
+) -> Vec<Result<SourceChecksum>> {
+    parallel_map(sources, None, |source| {
+        let mut downloader = Downloader::new(tetra_root, source, package_id)?.with_limits(*limits);
+
+        let Download::Fetched(provenance) = downloader.download(None)? else {
+            return Err(anyhow!("Server answered not modified to a plain request"));
+        };
+
+        downloader.send_to_cache(cache, &provenance)?;
+
+        let hash = blake3::Hash::from_hex(&provenance.hash)?;
+        let sha256 = if sha256 {
+            Some(cache.sha256_blob(hash)?)
+        } else {
+            None
+        };
+
+        Ok(SourceChecksum {
+            url: source.url.clone(),
+            hash,
+            sha256,
+        })
+    })
+}
@@ -0,0 +1,1437 @@
+use anyhow::{Result, anyhow};
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Seek, Write},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    TetraRoot,
+    config::{DownloadLimits, MirrorStrategy},
+    model::{PackageId, PartSource, Source},
+    net::{
+        BodySink, CurlClient, HttpClient, HttpError, HttpRequest, HttpResponse, Probe,
+        fetch_manifest_sha256, gateway_urls, mark_mirror_down, mark_mirror_up, order_mirrors,
+        probe_url,
+    },
+    store::{Cache, CacheBackend, Provenance, TempFile},
+    util::{
+        AbortableScope, Counter, Sha256Hasher, count, format_bytes, is_interrupted, move_file,
+        print_warn, redact_url, sha256_file,
+    },
+};
+
+#[derive(Debug)]
+pub enum Download {
+    Fetched(Provenance),
+
+    /// The server confirmed the previously downloaded content is still current
+    NotModified,
+}
+
+/// Transfer figures of a finished download
+#[derive(Debug, Clone, Copy)]
+pub struct DownloadStats {
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl DownloadStats {
+    pub fn bytes_per_sec(&self) -> u64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            (self.bytes as f64 / secs) as u64
+        } else {
+            self.bytes
+        }
+    }
+}
+
+/// Bytes between the synced checkpoints of a resumable download
+const CHECKPOINT_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Called with the number of bytes downloaded so far and the total size, if known
+pub type ProgressCallback<'a> = Box<dyn FnMut(u64, Option<u64>) + Send + 'a>;
+
+/// What a transfer left behind, before it is checked against the source
+struct Response {
+    status: u32,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    written: u64,
+    elapsed: Duration,
+
+    /// Content hash when it was computed while streaming
+    hash: Option<blake3::Hash>,
+
+    /// URL the content was served from, if the request was redirected or went to a mirror
+    resolved_url: Option<String>,
+}
+
+/// Outcome of a single request made for a download
+enum Attempt {
+    Complete(Response),
+
+    /// The transfer broke off in a way that resuming it may fix
+    Interrupted(anyhow::Error),
+}
+
+/// Writes the body of a download into any writer, hashing it as it arrives
+struct StreamSink<'s, 'a, W> {
+    out: &'s mut W,
+    hasher: &'s mut blake3::Hasher,
+
+    /// Only computed when the source is checked against a sha256 digest
+    sha256: Option<&'s mut Sha256Hasher>,
+    received: &'s mut u64,
+    progress: &'s mut Option<ProgressCallback<'a>>,
+    max_size: Option<u64>,
+
+    /// Bytes that were written before the request was made
+    offset: u64,
+    exceeded: bool,
+    write_error: Option<std::io::Error>,
+}
+
+impl<W: Write> BodySink for StreamSink<'_, '_, W> {
+    fn write(&mut self, data: &[u8]) -> bool {
+        if let Some(max_size) = self.max_size
+            && self.hasher.count() + data.len() as u64 > max_size
+        {
+            self.exceeded = true;
+            return false;
+        }
+
+        if let Err(e) = self.out.write_all(data) {
+            self.write_error = Some(e);
+            return false;
+        }
+
+        self.hasher.update(data);
+        if let Some(sha256) = self.sha256.as_mut() {
+            sha256.update(data);
+        }
+
+        *self.received += data.len() as u64;
+        count(Counter::BytesFetched, data.len() as u64);
+
+        true
+    }
+
+    fn progress(&mut self, received: u64, total: Option<u64>) -> bool {
+        // The client only counts what this request receives, not what was written before
+        if let Some(callback) = self.progress.as_mut() {
+            callback(self.offset + received, total.map(|t| self.offset + t));
+        }
+
+        !is_interrupted()
+    }
+}
+
+/// Writes the body of a download into the temporary file, with synced checkpoints it
+/// can be resumed from
+struct FileSink<'s, 'a> {
+    stream: StreamSink<'s, 'a, File>,
+    tmp_file: &'s TempFile,
+    checkpoint: u64,
+}
+
+impl BodySink for FileSink<'_, '_> {
+    fn write(&mut self, data: &[u8]) -> bool {
+        if !self.stream.write(data) {
+            return false;
+        }
+
+        // Synced checkpoints let a crashed run resume without trusting unflushed data
+        let written = self.stream.hasher.count();
+
+        if written - self.checkpoint >= CHECKPOINT_BYTES {
+            self.checkpoint = written;
+
+            if let Err(e) = self.stream.out.sync_data() {
+                self.stream.write_error = Some(e);
+                return false;
+            }
+
+            let _ = self.tmp_file.save_resume_offset(self.checkpoint);
+        }
+
+        true
+    }
+
+    fn progress(&mut self, received: u64, total: Option<u64>) -> bool {
+        self.stream.progress(received, total)
+    }
+}
+
+/// What a download streamed into a writer has passed on so far, across retries and mirrors
+#[derive(Default)]
+struct Streamed {
+    hasher: blake3::Hasher,
+    sha256: Option<Sha256Hasher>,
+    written: u64,
+}
+
+pub struct Downloader<'a, T> {
+    root: &'a TetraRoot,
+    source: &'a T,
+    tmp_file: TempFile,
+    package_id: &'a PackageId,
+    limits: DownloadLimits,
+    mirror_strategy: MirrorStrategy,
+    progress: Option<ProgressCallback<'a>>,
+    stats: Option<DownloadStats>,
+    client: Arc<dyn HttpClient + Send + Sync + 'a>,
+
+    /// Content hash of the downloaded file, once it has been checked
+    hash: Option<blake3::Hash>,
+
+    /// URL the download was redirected to or served from, if not the source URL
+    resolved_url: Option<String>,
+}
+
+impl<T> fmt::Debug for Downloader<'_, T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Downloader")
+            .field("source", &self.source)
+            .field("tmp_file", &self.tmp_file)
+            .field("package_id", &self.package_id)
+            .field("limits", &self.limits)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a, T> Downloader<'a, T>
+where
+    T: Source,
+{
+    pub fn new(root: &'a TetraRoot, source: &'a T, package_id: &'a PackageId) -> Result<Self> {
+        // Mutable sources may not have a hash yet, their URL is stable instead
+        let tmp_key = if source.is_mutable() {
+            blake3::hash(source.url().as_bytes())
+        } else {
+            source.checksum()?
+        };
+
+        // Only immutable content is resumed, mutable content may change in between. The
+        // parts of a split source are resumed instead of their joined content.
+        let resumable = if source.is_mutable() || !source.parts()?.is_empty() {
+            None
+        } else {
+            TempFile::resumable(root, tmp_key)?
+        };
+
+        let tmp_file = match resumable {
+            Some(f) => f,
+            None => TempFile::new(root, tmp_key)?,
+        };
+        Ok(Self {
+            root,
+            source,
+            tmp_file,
+            package_id,
+            limits: DownloadLimits::default(),
+            mirror_strategy: MirrorStrategy::default(),
+            progress: None,
+            stats: None,
+            client: Arc::new(CurlClient),
+            hash: None,
+            resolved_url: None,
+        })
+    }
+
+    /// Checks that a source is available and how large it is, without downloading it
+    /// or allocating a temp file for it
+    pub fn probe(source: &T, limits: &DownloadLimits) -> Result<Probe> {
+        let url = gateway_urls(&source.request_url()?)?.swap_remove(0);
+        probe_url(&url, limits, source.follows_redirects())
+    }
+
+    pub fn with_limits(mut self, limits: DownloadLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Chooses which of the source URL and its mirrors is tried first
+    pub fn with_mirror_strategy(mut self, strategy: MirrorStrategy) -> Self {
+        self.mirror_strategy = strategy;
+        self
+    }
+
+    /// Makes the requests with another HTTP backend than curl, e.g. a client that
+    /// simulates failing servers. The async download does not use it.
+    pub fn with_client<C>(mut self, client: C) -> Self
+    where
+        C: HttpClient + Send + Sync + 'a,
+    {
+        self.client = Arc::new(client);
+        self
+    }
+
+    /// Reports download progress to a callback, e.g. to render a progress bar
+    pub fn with_progress<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(u64, Option<u64>) + Send + 'a,
+    {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+
+    /// Figures of the last download that returned content
+    pub fn stats(&self) -> Option<DownloadStats> {
+        self.stats
+    }
+
+    /// URL the finished download was redirected to or served from, if that is not the
+    /// source URL
+    pub fn resolved_url(&self) -> Option<&str> {
+        self.resolved_url.as_deref()
+    }
+
+    /// Downloads the source into the temporary file. If a previous provenance is
+    /// given, the request is made conditional on the content having changed since.
+    /// Interrupted transfers are resumed from where they stopped, up to the configured
+    /// number of retries, and so is a partial file left behind by an earlier run. A
+    /// source with mirrors falls back to the next one if a download fails.
+    pub fn download(&mut self, previous: Option<&Provenance>) -> Result<Download> {
+        count(Counter::DownloadsStarted, 1);
+        let result = self.fetch(previous);
+        count_outcome(&result);
+        result
+    }
+
+    fn fetch(&mut self, previous: Option<&Provenance>) -> Result<Download> {
+        let _scope = AbortableScope::enter();
+
+        let parts = self.source.parts()?;
+        if !parts.is_empty() {
+            return self.fetch_parts(&parts);
+        }
+
+        let urls = self.request_urls()?;
+        let (last, first) = urls
+            .split_last()
+            .ok_or(anyhow!("Source has no URL to download from"))?;
+
+        for url in first {
+            match self.fetch_from(url, previous) {
+                Err(e) if !is_interrupted() && !is_disk_full(&e) => print_warn(&format!(
+                    "Failed to download from {}, {e}, trying the next mirror",
+                    redact_url(url)
+                )),
+                result => return result,
+            }
+        }
+
+        self.fetch_from(last, previous)
+    }
+
+    /// Downloads the volumes of a split source one after the other and joins them into
+    /// the temporary file. Each volume is verified against its own hash, and retried and
+    /// resumed on its own. The joined content is then verified like any download.
+    fn fetch_parts(&mut self, parts: &[PartSource]) -> Result<Download> {
+        let started = Instant::now();
+        let mut out_file = File::create(&self.tmp_file.path)?;
+        let mut written = 0;
+        let mut status = 0;
+
+        for (index, part) in parts.iter().enumerate() {
+            let joined = written;
+            let progress = &mut self.progress;
+
+            let mut downloader = Downloader::new(self.root, part, self.package_id)?
+                .with_limits(self.limits)
+                .with_mirror_strategy(self.mirror_strategy)
+                .with_progress(move |received, _| {
+                    if let Some(callback) = progress.as_mut() {
+                        callback(joined + received, None);
+                    }
+                });
+            downloader.client = self.client.clone();
+
+            let fetched = downloader.fetch(None).map_err(|e| {
+                anyhow!(
+                    "Failed to download part {} of {}, {e}",
+                    index + 1,
+                    parts.len()
+                )
+            })?;
+
+            let Download::Fetched(provenance) = fetched else {
+                return Err(anyhow!("Server answered not modified to a plain request"));
+            };
+            status = provenance.status;
+
+            // The part file is removed with its downloader once it has been joined
+            let mut part_file = File::open(&downloader.tmp_file.path)?;
+            let copied = std::io::copy(&mut part_file, &mut out_file);
+            drop(downloader);
+
+            written += copied.map_err(|e| write_error(&self.tmp_file.path, e))?;
+
+            if self
+                .limits
+                .max_size
+                .is_some_and(|max_size| written > max_size)
+            {
+                return Err(self.size_exceeded());
+            }
+        }
+
+        let response = Response {
+            status,
+            etag: None,
+            last_modified: None,
+            written,
+            elapsed: started.elapsed(),
+            hash: None,
+            resolved_url: None,
+        };
+
+        self.finish(response, false)
+    }
+
+    /// URLs the source can be requested from, its own and those of its mirrors, in the
+    /// order of the mirror strategy. IPFS URLs are tried on every gateway.
+    fn request_urls(&self) -> Result<Vec<String>> {
+        let mut urls = gateway_urls(&self.source.request_url()?)?;
+
+        for mirror in self.source.mirrors() {
+            urls.extend(gateway_urls(&self.source.mirror_request_url(&mirror)?)?);
+        }
+
+        Ok(order_mirrors(urls, self.mirror_strategy, &self.limits))
+    }
+
+    /// URL recorded as what content requested from `url` was served from, if that is
+    /// not the source URL. A mirror is recorded as the recipe lists it, so values filled
+    /// in from the environment are not kept.
+    fn served_from(&self, url: &str) -> Result<Option<String>> {
+        if url == self.source.request_url()? {
+            return Ok(None);
+        }
+
+        for mirror in self.source.mirrors() {
+            if self.source.mirror_request_url(&mirror)? == url {
+                return Ok(Some(mirror));
+            }
+        }
+
+        Ok(Some(url.to_string()))
+    }
+
+    fn fetch_from(&mut self, url: &str, previous: Option<&Provenance>) -> Result<Download> {
+        let offset = self.tmp_file.resume_offset();
+
+        let mut out_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.tmp_file.path)?;
+        out_file.set_len(offset)?;
+
+        // The prefix left by an earlier run is hashed once, the rest as it arrives
+        let mut hasher = blake3::Hasher::new();
+        hasher.update_reader(&mut out_file)?;
+
+        let started = Instant::now();
+        let mut received = 0;
+        let mut retries = 0;
+
+        loop {
+            let attempt = self.transfer(url, &mut out_file, &mut hasher, &mut received, previous);
+
+            match track_health(url, attempt)? {
+                Attempt::Complete(mut response) => {
+                    // Content served by a mirror is recorded like a redirect to it
+                    if response.resolved_url.is_none() {
+                        response.resolved_url = self.served_from(url)?;
+                    }
+
+                    response.written = received;
+                    response.elapsed = started.elapsed();
+                    return self.finish(response, previous.is_some());
+                }
+                Attempt::Interrupted(_) if retries < self.limits.retries => {
+                    retries += 1;
+
+                    // Give a flaky connection or server some time before asking again
+                    std::thread::sleep(Duration::from_secs(retries.into()));
+                }
+                Attempt::Interrupted(e) => {
+                    mark_mirror_down(url);
+                    return Err(anyhow!("{e}, gave up after {retries} retries"));
+                }
+            }
+        }
+    }
+
+    /// Makes a single request for the part of the source that is not in the file yet
+    fn transfer(
+        &mut self,
+        request_url: &str,
+        out_file: &mut File,
+        hasher: &mut blake3::Hasher,
+        received: &mut u64,
+        previous: Option<&Provenance>,
+    ) -> Result<Attempt> {
+        // Mutable content may have changed since the last attempt, it is always fetched whole
+        if !self.tmp_file.resumable && hasher.count() > 0 {
+            out_file.set_len(0)?;
+            out_file.rewind()?;
+            hasher.reset();
+        }
+
+        let offset = hasher.count();
+        let mut headers = Vec::new();
+
+        if let Some(previous) = previous {
+            if let Some(etag) = &previous.etag {
+                headers.push(format!("If-None-Match: {etag}"));
+            }
+
+            if let Some(last_modified) = &previous.last_modified {
+                headers.push(format!("If-Modified-Since: {last_modified}"));
+            }
+        }
+
+        let request = HttpRequest {
+            url: request_url,
+            headers,
+            resume_from: offset,
+            follow_redirects: self.source.follows_redirects(),
+            limits: &self.limits,
+        };
+
+        let mut sink = FileSink {
+            stream: StreamSink {
+                out: &mut *out_file,
+                hasher: &mut *hasher,
+                sha256: None,
+                received: &mut *received,
+                progress: &mut self.progress,
+                max_size: self.limits.max_size,
+                offset,
+                exceeded: false,
+                write_error: None,
+            },
+            tmp_file: &self.tmp_file,
+            checkpoint: offset,
+        };
+
+        let result = self.client.get(&request, &mut sink);
+        let StreamSink {
+            exceeded,
+            write_error,
+            ..
+        } = sink.stream;
+
+        let synced = out_file.sync_data();
+
+        if let Some(e) = write_error.or(synced.err()) {
+            return Err(self.write_failed(out_file, hasher.count(), e));
+        }
+
+        self.tmp_file.save_resume_offset(hasher.count())?;
+
+        if is_interrupted() {
+            return Err(anyhow!("Download interrupted"));
+        }
+
+        if exceeded {
+            return Err(self.size_exceeded());
+        }
+
+        // The server cannot continue where the file ends, the next attempt starts over
+        if offset > 0 && is_range_rejected(&result) {
+            out_file.set_len(0)?;
+            out_file.rewind()?;
+            hasher.reset();
+            self.tmp_file.save_resume_offset(0)?;
+
+            return Ok(Attempt::Interrupted(anyhow!(
+                "Server cannot resume the download at byte {offset}"
+            )));
+        }
+
+        complete(result, hasher, *received)
+    }
+
+    /// Downloads the source into `writer` instead of the temporary file, hashing and
+    /// verifying the bytes as they pass through. They are written before the download
+    /// can be verified, so whatever the writer received must be discarded if this fails.
+    /// An interrupted transfer of an immutable source is resumed where the writer
+    /// stopped, and mirrors are only tried while nothing was written. The content is
+    /// checked against its hash, sha256 and sha256 sums, but not by a [`Source::verify`]
+    /// override, which needs a file.
+    pub fn download_to_writer<W: Write>(&mut self, mut writer: W) -> Result<Provenance> {
+        count(Counter::DownloadsStarted, 1);
+        let result = self.stream(&mut writer);
+        count_outcome(&result);
+        result
+    }
+
+    fn stream<W: Write>(&mut self, writer: &mut W) -> Result<Provenance> {
+        let _scope = AbortableScope::enter();
+        self.require_whole()?;
+        let urls = self.request_urls()?;
+        let (last, first) = urls
+            .split_last()
+            .ok_or(anyhow!("Source has no URL to download from"))?;
+
+        let needs_sha256 =
+            self.source.sha256().is_some() || self.source.sha256_manifest()?.is_some();
+        let mut streamed = Streamed {
+            sha256: needs_sha256.then(Sha256Hasher::new),
+            ..Streamed::default()
+        };
+
+        for url in first {
+            match self.stream_from(url, writer, &mut streamed) {
+                Err(e) if streamed.written == 0 && !is_interrupted() => print_warn(&format!(
+                    "Failed to download from {}, {e}, trying the next mirror",
+                    redact_url(url)
+                )),
+                result => return result,
+            }
+        }
+
+        self.stream_from(last, writer, &mut streamed)
+    }
+
+    fn stream_from<W: Write>(
+        &mut self,
+        url: &str,
+        writer: &mut W,
+        streamed: &mut Streamed,
+    ) -> Result<Provenance> {
+        let started = Instant::now();
+        let mut retries = 0;
+
+        loop {
+            match track_health(url, self.stream_transfer(url, writer, streamed))? {
+                Attempt::Complete(mut response) => {
+                    if response.resolved_url.is_none() {
+                        response.resolved_url = self.served_from(url)?;
+                    }
+
+                    response.elapsed = started.elapsed();
+                    return self.finish_stream(response, streamed);
+                }
+                // Mutable content could have changed, it cannot continue what was written
+                Attempt::Interrupted(_)
+                    if retries < self.limits.retries
+                        && (!self.source.is_mutable() || streamed.written == 0) =>
+                {
+                    retries += 1;
+                    std::thread::sleep(Duration::from_secs(retries.into()));
+                }
+                Attempt::Interrupted(e) => {
+                    mark_mirror_down(url);
+                    return Err(anyhow!("{e}, gave up after {retries} retries"));
+                }
+            }
+        }
+    }
+
+    /// Makes a single request for the part of the source that was not written yet
+    fn stream_transfer<W: Write>(
+        &mut self,
+        request_url: &str,
+        writer: &mut W,
+        streamed: &mut Streamed,
+    ) -> Result<Attempt> {
+        let offset = streamed.hasher.count();
+
+        let request = HttpRequest {
+            url: request_url,
+            headers: Vec::new(),
+            resume_from: offset,
+            follow_redirects: self.source.follows_redirects(),
+            limits: &self.limits,
+        };
+
+        let mut sink = StreamSink {
+            out: &mut *writer,
+            hasher: &mut streamed.hasher,
+            sha256: streamed.sha256.as_mut(),
+            received: &mut streamed.written,
+            progress: &mut self.progress,
+            max_size: self.limits.max_size,
+            offset,
+            exceeded: false,
+            write_error: None,
+        };
+
+        let result = self.client.get(&request, &mut sink);
+        let StreamSink {
+            exceeded,
+            write_error,
+            ..
+        } = sink;
+
+        if let Some(e) = write_error.or(writer.flush().err()) {
+            let message = format!("Failed to write the download, {e}");
+            return Err(anyhow::Error::new(e).context(message));
+        }
+
+        if is_interrupted() {
+            return Err(anyhow!("Download interrupted"));
+        }
+
+        if exceeded {
+            return Err(self.size_exceeded());
+        }
+
+        if offset > 0 && is_range_rejected(&result) {
+            return Err(anyhow!(
+                "Server cannot resume the download at byte {offset}, and the bytes written cannot be taken back"
+            ));
+        }
+
+        complete(result, &streamed.hasher, streamed.written)
+    }
+
+    /// Verifies a download streamed into a writer and returns its provenance
+    fn finish_stream(&mut self, response: Response, streamed: &mut Streamed) -> Result<Provenance> {
+        let hash = streamed.hasher.finalize();
+
+        let verified = self.verify_hash(hash).and_then(|()| {
+            let Some(sha256) = streamed.sha256.take() else {
+                return Ok(());
+            };
+
+            let computed = sha256.finalize();
+            self.source.verify_sha256(&computed)?;
+            self.verify_manifest(|| Ok(computed))
+        });
+
+        if let Err(e) = verified {
+            count(Counter::ValidationFailures, 1);
+            return Err(e);
+        }
+
+        Ok(self.record(response, hash))
+    }
+
+    /// Async variant of [`Downloader::download`], streaming the response into the
+    /// temporary file without blocking the executor. It always fetches the whole source,
+    /// from the first URL of the mirror strategy without falling back to the others.
+    #[cfg(feature = "async")]
+    pub async fn download_async(&mut self, previous: Option<&Provenance>) -> Result<Download> {
+        count(Counter::DownloadsStarted, 1);
+        let result = self.fetch_async(previous).await;
+        count_outcome(&result);
+        result
+    }
+
+    #[cfg(feature = "async")]
+    async fn fetch_async(&mut self, previous: Option<&Provenance>) -> Result<Download> {
+        use crate::net::check_redirect;
+        use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+        use tokio::io::AsyncWriteExt;
+
+        let _scope = AbortableScope::enter();
+        self.require_whole()?;
+
+        let max_redirects = self.limits.max_redirects as usize;
+
+        // Every hop is checked against the https requirement before it is followed
+        let redirect = if self.source.follows_redirects() && max_redirects > 0 {
+            reqwest::redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() > max_redirects {
+                    return attempt
+                        .error(anyhow!("Gave up after following {max_redirects} redirects"));
+                }
+
+                match check_redirect(attempt.url().as_str()) {
+                    Ok(()) => attempt.follow(),
+                    Err(e) => attempt.error(e),
+                }
+            })
+        } else {
+            reqwest::redirect::Policy::none()
+        };
+
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(self.limits.connect_timeout)
+            .redirect(redirect);
+
+        if let Some(timeout) = self.limits.transfer_timeout {
+            builder = builder.timeout(timeout);
+        }
+
+        let url = self.request_urls()?.swap_remove(0);
+        let client = builder.build()?;
+        let started = Instant::now();
+        let mut retries = 0;
+
+        let mut response = loop {
+            let mut request = client.get(&url);
+
+            if let Some(previous) = previous {
+                if let Some(etag) = &previous.etag {
+                    request = request.header(IF_NONE_MATCH, etag);
+                }
+
+                if let Some(last_modified) = &previous.last_modified {
+                    request = request.header(IF_MODIFIED_SINCE, last_modified);
+                }
+            }
+
+            // Errors would otherwise print the request URL, along with any secrets in it
+            let response = request.send().await.map_err(|e| e.without_url())?;
+
+            // Like an interrupted transfer, a server error is worth asking again
+            if response.status().is_server_error() && retries < self.limits.retries {
+                retries += 1;
+                tokio::time::sleep(Duration::from_secs(retries.into())).await;
+                continue;
+            }
+
+            break response;
+        };
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+        let total = response.content_length();
+
+        // Checked before the temp file is created, an error page is never taken as content
+        let status = response.status();
+        if status.is_server_error() {
+            return Err(anyhow!(
+                "Server responded with status {status}, gave up after {retries} retries"
+            ));
+        }
+
+        if status.is_client_error() {
+            return Err(anyhow!("Server responded with status {status}"));
+        }
+
+        if status.is_redirection() && status != reqwest::StatusCode::NOT_MODIFIED {
+            return Err(anyhow!(
+                "Server responded with status {status}, a redirect that was not followed"
+            ));
+        }
+
+        let resolved_url = if response.url().as_str() == url {
+            self.served_from(&url)?
+        } else {
+            Some(response.url().to_string())
+        };
+
+        let mut out_file = tokio::fs::File::create(&self.tmp_file.path).await?;
+        let mut hasher = blake3::Hasher::new();
+        let mut written: u64 = 0;
+
+        while let Some(chunk) = response.chunk().await.map_err(|e| e.without_url())? {
+            if is_interrupted() {
+                return Err(anyhow!("Download interrupted"));
+            }
+
+            written += chunk.len() as u64;
+            count(Counter::BytesFetched, chunk.len() as u64);
+
+            if let Some(max_size) = self.limits.max_size
+                && written > max_size
+            {
+                return Err(self.size_exceeded());
+            }
+
+            out_file
+                .write_all(&chunk)
+                .await
+                .map_err(|e| write_error(&self.tmp_file.path, e))?;
+            hasher.update(&chunk);
+
+            if let Some(callback) = self.progress.as_mut() {
+                callback(written, total);
+            }
+        }
+
+        out_file.flush().await?;
+
+        let response = Response {
+            status: response.status().as_u16() as u32,
+            etag,
+            last_modified,
+            written,
+            elapsed: started.elapsed(),
+            hash: Some(hasher.finalize()),
+            resolved_url,
+        };
+
+        self.finish(response, previous.is_some())
+    }
+
+    /// Error for a temporary file that could not be written. A full disk also drops the
+    /// partial download, keeping it to resume would hold on to the space that ran out.
+    fn write_failed(&mut self, out_file: &File, written: u64, e: std::io::Error) -> anyhow::Error {
+        match e.kind() {
+            ErrorKind::StorageFull => {
+                let _ = out_file.set_len(0);
+                self.tmp_file.discard_resume_state();
+            }
+            _ => {
+                let _ = self.tmp_file.save_resume_offset(written);
+            }
+        }
+
+        write_error(&self.tmp_file.path, e)
+    }
+
+    /// Fails for a split source, whose parts can only be joined by [`Downloader::download`]
+    fn require_whole(&self) -> Result<()> {
+        if !self.source.parts()?.is_empty() {
+            return Err(anyhow!(
+                "Source {} is split into parts, which are only joined in the temporary file",
+                redact_url(&self.source.url())
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn size_exceeded(&self) -> anyhow::Error {
+        anyhow!(
+            "Source exceeded the maximum size of {}, download aborted",
+            format_bytes(self.limits.max_size.unwrap_or(0))
+        )
+    }
+
+    /// Turns a completed transfer into its provenance, shared by the sync and async downloads
+    fn finish(&mut self, response: Response, conditional: bool) -> Result<Download> {
+        if response.status == 304 && conditional {
+            return Ok(Download::NotModified);
+        }
+
+        // A complete download is not worth keeping around for resuming, whatever the checks say
+        self.tmp_file.discard_resume_state();
+
+        let hash = match response.hash {
+            Some(hash) => hash,
+            None => Cache::hash_file(&self.tmp_file.path)?,
+        };
+
+        if let Err(e) = self.verify_content(hash) {
+            count(Counter::ValidationFailures, 1);
+            return Err(e);
+        }
+
+        self.hash = Some(hash);
+        Ok(Download::Fetched(self.record(response, hash)))
+    }
+
+    /// Keeps the figures of a verified transfer and returns its provenance
+    fn record(&mut self, response: Response, hash: blake3::Hash) -> Provenance {
+        self.resolved_url = response.resolved_url;
+
+        if let Some(resolved_url) = &self.resolved_url {
+            log::info!(
+                "Source {} was downloaded from {}",
+                redact_url(&self.source.url()),
+                redact_url(resolved_url)
+            );
+        }
+
+        self.stats = Some(DownloadStats {
+            bytes: response.written,
+            elapsed: response.elapsed,
+        });
+
+        Provenance {
+            hash: hash.to_string(),
+            url: self.source.url(),
+            resolved_url: self.resolved_url.as_deref().map(redact_url),
+            package: self.package_id.to_string(),
+            fetched_at: Provenance::now(),
+            status: response.status,
+            etag: response.etag,
+            last_modified: response.last_modified,
+        }
+    }
+
+    /// Checks the downloaded file, hashed as `hash`, against the hashes of its source
+    fn verify_content(&self, hash: blake3::Hash) -> Result<()> {
+        self.verify_hash(hash)?;
+        self.source.verify(&self.tmp_file.path)?;
+        self.verify_manifest(|| sha256_file(&self.tmp_file.path))
+    }
+
+    /// Checks the blake3 hash of downloaded content against that of its source
+    fn verify_hash(&self, hash: blake3::Hash) -> Result<()> {
+        if self.source.is_mutable() {
+            // A hash on a mutable source pins it, changed content is an error
+            if let Ok(expected) = self.source.checksum()
+                && expected != hash
+            {
+                return Err(anyhow!(
+                    "Mutable source changed upstream, expected {expected} but got {hash}"
+                ));
+            }
+        } else {
+            let expected = self.source.checksum()?;
+
+            if expected != hash {
+                return Err(anyhow!(
+                    "Downloaded file checksum {hash} does not match {expected}"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks downloaded content against the digest listed for it in the sha256 sums
+    /// file the source refers to, if any. The sha256 of the content is only computed
+    /// when there is a digest to compare it with.
+    fn verify_manifest(&self, sha256: impl FnOnce() -> Result<String>) -> Result<()> {
+        let Some((url, file)) = self.source.sha256_manifest()? else {
+            return Ok(());
+        };
+
+        let expected = fetch_manifest_sha256(&*self.client, &url, &file, &self.limits)?;
+        let computed = sha256()?;
+
+        if computed != expected {
+            return Err(anyhow!(
+                "Source {} sha256 {computed} does not match {expected} listed in its sha256sums",
+                redact_url(&self.source.url())
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Checks the downloaded file against the hash recorded in its provenance
+    pub fn verify(&self, provenance: &Provenance) -> Result<()> {
+        let expected = blake3::Hash::from_hex(&provenance.hash)?;
+        let computed = match self.hash {
+            Some(hash) => hash,
+            None => Cache::hash_file(&self.tmp_file.path)?,
+        };
+
+        if expected != computed {
+            return Err(anyhow!(
+                "Downloaded file checksum {computed} does not match {expected}"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Verifies the downloaded file and moves it to a path outside of the cache.
+    /// Nothing is written to the destination if verification fails.
+    pub fn save_to(&self, provenance: &Provenance, dest: &Path) -> Result<()> {
+        self.verify(provenance)?;
+        move_file(&self.tmp_file.path, dest)
+    }
+
+    pub fn send_to_cache(&self, cache: &dyn CacheBackend, provenance: &Provenance) -> Result<()> {
+        let hash = blake3::Hash::from_hex(&provenance.hash)?;
+
+        // Content hashed while streaming needs no second pass before it is cached
+        if self.hash == Some(hash) {
+            cache.cache_hashed_tmp_file(&self.tmp_file, hash)?;
+        } else {
+            cache.cache_tmp_file(&self.tmp_file, hash)?;
+        }
+
+        cache.write_provenance(provenance)?;
+
+        Ok(())
+    }
+}
+
+/// Classifies the answer to a request whose body went into a sink
+fn complete(
+    result: Result<HttpResponse, HttpError>,
+    hasher: &blake3::Hasher,
+    written: u64,
+) -> Result<Attempt> {
+    let response = match result {
+        Ok(response) => response,
+        Err(HttpError::Transient(e)) => return Ok(Attempt::Interrupted(e)),
+        Err(e) => return Err(e.into()),
+    };
+
+    let status = response.status;
+
+    if status >= 500 {
+        return Ok(Attempt::Interrupted(anyhow!(
+            "Server responded with status {status}"
+        )));
+    }
+
+    if status >= 400 {
+        return Err(anyhow!("Server responded with status {status}"));
+    }
+
+    // Not modified is an answer to the conditional request, any other redirect was not followed
+    if (300..400).contains(&status) && status != 304 {
+        let target = response
+            .redirect
+            .as_deref()
+            .map(redact_url)
+            .unwrap_or_default();
+        return Err(anyhow!(
+            "Server responded with status {status} redirecting to {target}, but redirects are not followed"
+        ));
+    }
+
+    Ok(Attempt::Complete(Response {
+        status,
+        etag: response.etag,
+        last_modified: response.last_modified,
+        written,
+        elapsed: Duration::ZERO,
+        hash: Some(hasher.finalize()),
+        resolved_url: response.resolved_url,
+    }))
+}
+
+/// Keeps the health record of the server a request went to, a server that answered is
+/// no longer skipped and one that could not be reached is for a while
+fn track_health(url: &str, attempt: Result<Attempt>) -> Result<Attempt> {
+    match &attempt {
+        Ok(Attempt::Complete(_)) => mark_mirror_up(url),
+        Err(e) if matches!(e.downcast_ref::<HttpError>(), Some(HttpError::Failed(_))) => {
+            mark_mirror_down(url)
+        }
+        _ => {}
+    }
+
+    attempt
+}
+
+/// Whether the server refused to serve the body from the requested byte
+fn is_range_rejected(result: &Result<HttpResponse, HttpError>) -> bool {
+    match result {
+        Err(e) => matches!(e, HttpError::RangeRejected),
+        Ok(response) => response.status == 416,
+    }
+}
+
+fn count_outcome<D>(result: &Result<D>) {
+    match result {
+        Ok(_) => count(Counter::DownloadsSucceeded, 1),
+        Err(_) => count(Counter::DownloadsFailed, 1),
+    }
+}
+
+/// Describes a failed write to a temporary file, naming the filesystem if it is full
+fn write_error(path: &Path, e: std::io::Error) -> anyhow::Error {
+    let message = match e.kind() {
+        ErrorKind::StorageFull => format!(
+            "Out of disk space on {}",
+            path.parent().unwrap_or(path).display()
+        ),
+        _ => format!("Failed to write {}, {e}", path.display()),
+    };
+
+    anyhow::Error::new(e).context(message)
+}
+
+/// Whether a download failed because the disk is full, which no mirror can help with
+fn is_disk_full(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == ErrorKind::StorageFull)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        store::{CacheStatus, MemoryCache},
+    };
+    use std::{collections::VecDeque, sync::Mutex};
+
+    const CONTENT: &[u8] = b"content served by the scripted client";
+
+    type Reply =
+        Box<dyn FnOnce(&HttpRequest, &mut dyn BodySink) -> Result<HttpResponse, HttpError> + Send>;
+
+    /// Answers requests with scripted replies in order, recording the byte each request
+    /// asked the body from
+    struct ScriptedClient {
+        replies: Mutex<VecDeque<Reply>>,
+        offsets: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl HttpClient for ScriptedClient {
+        fn get(
+            &self,
+            request: &HttpRequest,
+            sink: &mut dyn BodySink,
+        ) -> Result<HttpResponse, HttpError> {
+            self.offsets.lock().unwrap().push(request.resume_from);
+
+            let reply = self.replies.lock().unwrap().pop_front();
+            reply.expect("no reply is scripted for the request")(request, sink)
+        }
+    }
+
+    /// Serves the content from the requested byte
+    fn serve(status: u32) -> Reply {
+        serve_body(status, CONTENT)
+    }
+
+    /// Serves any body from the requested byte
+    fn serve_body(status: u32, body: &'static [u8]) -> Reply {
+        Box::new(move |request, sink| {
+            assert!(sink.write(&body[request.resume_from as usize..]));
+            Ok(HttpResponse {
+                status,
+                ..Default::default()
+            })
+        })
+    }
+
+    /// Serves the content from the requested byte up to `end`, then breaks off
+    fn truncate(end: usize) -> Reply {
+        Box::new(move |request, sink| {
+            assert!(sink.write(&CONTENT[request.resume_from as usize..end]));
+            Err(HttpError::Transient(anyhow!("Connection reset")))
+        })
+    }
+
+    /// Answers with a status and no content
+    fn respond(status: u32, redirect: Option<&str>) -> Reply {
+        let redirect = redirect.map(str::to_string);
+        Box::new(move |_, _| {
+            Ok(HttpResponse {
+                status,
+                redirect,
+                ..Default::default()
+            })
+        })
+    }
+
+    fn reject_range() -> Reply {
+        Box::new(|_, _| Err(HttpError::RangeRejected))
+    }
+
+    /// A root of its own for each test, removed when the test ends
+    struct Fixture {
+        root: TetraRoot,
+        source: PartSource,
+        package_id: PackageId,
+    }
+
+    impl Fixture {
+        fn new(name: &str, follows_redirects: bool) -> Self {
+            let dir = std::env::temp_dir()
+                .join(format!("tetra-downloader-{}-{name}", std::process::id()));
+            let url = format!("https://example.test/{name}");
+
+            Self {
+                root: TetraRoot::new(Some(&dir), &Config::default(), None).unwrap(),
+                source: PartSource {
+                    url: url.clone(),
+                    request_url: url,
+                    hash: blake3::hash(CONTENT),
+                    follows_redirects,
+                },
+                package_id: PackageId::from_id_str("test/pkg@1.0".to_string()),
+            }
+        }
+
+        /// A downloader of the source answered by the scripted replies, along with the
+        /// byte each of its requests starts at
+        fn downloader(
+            &self,
+            retries: u32,
+            replies: Vec<Reply>,
+        ) -> (Downloader<'_, PartSource>, Arc<Mutex<Vec<u64>>>) {
+            let offsets = Arc::new(Mutex::new(Vec::new()));
+            let client = ScriptedClient {
+                replies: Mutex::new(replies.into()),
+                offsets: offsets.clone(),
+            };
+            let limits = DownloadLimits {
+                retries,
+                ..DownloadLimits::default()
+            };
+
+            let downloader = Downloader::new(&self.root, &self.source, &self.package_id)
+                .unwrap()
+                .with_limits(limits)
+                .with_client(client);
+
+            (downloader, offsets)
+        }
+
+        /// Downloads the source with the scripted replies, returning the result and the
+        /// byte each request started at
+        fn download(&self, retries: u32, replies: Vec<Reply>) -> (Result<Download>, Vec<u64>) {
+            let (mut downloader, offsets) = self.downloader(retries, replies);
+            let result = downloader.download(None);
+
+            let offsets = offsets.lock().unwrap().clone();
+            (result, offsets)
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.root.root);
+        }
+    }
+
+    fn fetched(result: Result<Download>) -> Provenance {
+        match result.unwrap() {
+            Download::Fetched(provenance) => provenance,
+            Download::NotModified => panic!("download was not fetched"),
+        }
+    }
+
+    fn fetched_hash(result: Result<Download>) -> String {
+        fetched(result).hash
+    }
+
+    #[test]
+    fn client_errors_fail_without_retrying() {
+        let fixture = Fixture::new("client-error", true);
+        let (result, offsets) = fixture.download(1, vec![respond(404, None)]);
+
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("status 404"), "{error}");
+        assert_eq!(offsets, [0]);
+    }
+
+    #[test]
+    fn server_errors_are_retried() {
+        let fixture = Fixture::new("server-error", true);
+        let (result, offsets) = fixture.download(1, vec![respond(503, None), serve(200)]);
+
+        assert_eq!(fetched_hash(result), blake3::hash(CONTENT).to_string());
+        assert_eq!(offsets, [0, 0]);
+    }
+
+    #[test]
+    fn server_errors_fail_once_retries_run_out() {
+        let fixture = Fixture::new("server-error-final", true);
+        let (result, offsets) = fixture.download(0, vec![respond(503, None)]);
+
+        let error = result.unwrap_err().to_string();
+        assert!(
+            error.contains("status 503, gave up after 0 retries"),
+            "{error}"
+        );
+        assert_eq!(offsets, [0]);
+    }
+
+    #[test]
+    fn truncated_body_is_resumed() {
+        let fixture = Fixture::new("truncated", true);
+        let (result, offsets) = fixture.download(1, vec![truncate(10), serve(206)]);
+
+        assert_eq!(fetched_hash(result), blake3::hash(CONTENT).to_string());
+        assert_eq!(offsets, [0, 10]);
+    }
+
+    #[test]
+    fn rejected_resume_starts_over() {
+        for (name, rejection) in [
+            ("range-rejected", reject_range()),
+            ("range-416", respond(416, None)),
+        ] {
+            let fixture = Fixture::new(name, true);
+
+            // A download that broke off leaves a partial file for the next one to resume
+            let (result, _) = fixture.download(0, vec![truncate(10)]);
+            assert!(result.is_err());
+
+            let (result, offsets) = fixture.download(1, vec![rejection, serve(200)]);
+
+            assert_eq!(fetched_hash(result), blake3::hash(CONTENT).to_string());
+            assert_eq!(offsets, [10, 0], "{name}");
+        }
+    }
+
+    #[test]
+    fn unfollowed_redirect_fails() {
+        let fixture = Fixture::new("redirect", false);
+        let reply = respond(302, Some("https://elsewhere.test/redirect"));
+        let (result, offsets) = fixture.download(1, vec![reply]);
+
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("redirects are not followed"), "{error}");
+        assert!(error.contains("https://elsewhere.test/redirect"), "{error}");
+        assert_eq!(offsets, [0]);
+    }
+
+    #[test]
+    fn verified_download_is_cached() {
+        let fixture = Fixture::new("cached", true);
+        let cache = MemoryCache::new();
+        let hash = blake3::hash(CONTENT);
+
+        assert_eq!(cache.validate(hash).unwrap(), CacheStatus::Missing);
+
+        let (mut downloader, _) = fixture.downloader(0, vec![serve(200)]);
+        let provenance = fetched(downloader.download(None));
+        downloader.send_to_cache(&cache, &provenance).unwrap();
+
+        assert_eq!(cache.validate(hash).unwrap(), CacheStatus::Valid);
+        assert_eq!(cache.blob(hash).unwrap().as_deref(), Some(CONTENT));
+
+        let recorded = cache.read_provenance(hash).unwrap().unwrap();
+        assert_eq!(recorded.url, fixture.source.url);
+    }
+
+    #[test]
+    fn corrupt_blob_is_removed_and_fetched_again() {
+        let fixture = Fixture::new("corrupt", true);
+        let cache = MemoryCache::new();
+        let hash = blake3::hash(CONTENT);
+
+        let (mut downloader, _) = fixture.downloader(0, vec![serve(200)]);
+        let provenance = fetched(downloader.download(None));
+        cache.insert_as(hash, b"bit rot").unwrap();
+        cache.write_provenance(&provenance).unwrap();
+
+        assert_eq!(cache.validate(hash).unwrap(), CacheStatus::Corrupt);
+        assert_eq!(cache.blob(hash).unwrap(), None);
+        assert!(cache.read_provenance(hash).unwrap().is_none());
+
+        downloader.send_to_cache(&cache, &provenance).unwrap();
+        assert_eq!(cache.validate(hash).unwrap(), CacheStatus::Valid);
+    }
+
+    #[test]
+    fn bad_download_never_replaces_a_good_blob() {
+        let fixture = Fixture::new("tampered", true);
+        let cache = MemoryCache::new();
+        let hash = blake3::hash(CONTENT);
+
+        let (mut downloader, _) = fixture.downloader(0, vec![serve(200)]);
+        let provenance = fetched(downloader.download(None));
+        downloader.send_to_cache(&cache, &provenance).unwrap();
+        drop(downloader);
+
+        let (mut downloader, _) = fixture.downloader(0, vec![serve_body(200, b"tampered")]);
+        let error = downloader.download(None).unwrap_err().to_string();
+        assert!(error.contains("does not match"), "{error}");
+
+        // Even when asked to, the content that failed verification is not cached
+        assert!(downloader.send_to_cache(&cache, &provenance).is_err());
+        assert_eq!(cache.validate(hash).unwrap(), CacheStatus::Valid);
+        assert_eq!(cache.blob(hash).unwrap().as_deref(), Some(CONTENT));
+    }
+}
@@ -0,0 +1,261 @@
+use anyhow::anyhow;
+use curl::easy::{Easy, List};
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+};
+
+use crate::{config::DownloadLimits, net::check_redirect};
+
+/// A GET request made by a download
+#[derive(Debug, Clone)]
+pub struct HttpRequest<'a> {
+    pub url: &'a str,
+
+    /// Extra headers, as "Name: value" lines
+    pub headers: Vec<String>,
+
+    /// Byte the body is requested from, 0 for the whole content
+    pub resume_from: u64,
+
+    /// Redirects are followed within the limits if set
+    pub follow_redirects: bool,
+    pub limits: &'a DownloadLimits,
+}
+
+/// What the server answered a request with, its body went to the [`BodySink`]
+#[derive(Debug, Clone, Default)]
+pub struct HttpResponse {
+    /// Status of the last response, after any followed redirects
+    pub status: u32,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+
+    /// Where the server redirects to, if the redirect was not followed
+    pub redirect: Option<String>,
+
+    /// URL the content was served from, if the request was redirected
+    pub resolved_url: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum HttpError {
+    /// The server cannot serve the body from the requested byte
+    RangeRejected,
+
+    /// The connection broke off in a way that resuming may fix, e.g. a timeout or a
+    /// truncated body
+    Transient(anyhow::Error),
+
+    Failed(anyhow::Error),
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::RangeRejected => write!(f, "Server cannot serve the requested range"),
+            HttpError::Transient(e) | HttpError::Failed(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl From<curl::Error> for HttpError {
+    fn from(e: curl::Error) -> Self {
+        HttpError::Failed(e.into())
+    }
+}
+
+/// Receives the body of a successful response as it arrives
+pub trait BodySink {
+    /// Returning false aborts the transfer
+    fn write(&mut self, data: &[u8]) -> bool;
+
+    /// Called with the body bytes this request received so far and the body size, if
+    /// known. Returning false aborts the transfer.
+    fn progress(&mut self, _received: u64, _total: Option<u64>) -> bool {
+        true
+    }
+}
+
+/// The HTTP backend downloads are made with. Curl is used unless another client is
+/// given, e.g. one that simulates failing servers.
+pub trait HttpClient {
+    /// Makes a GET request and streams the body of a successful response into the sink.
+    /// Bodies of error and redirect responses are not passed on.
+    fn get(
+        &self,
+        request: &HttpRequest,
+        sink: &mut dyn BodySink,
+    ) -> Result<HttpResponse, HttpError>;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CurlClient;
+
+impl HttpClient for CurlClient {
+    fn get(
+        &self,
+        request: &HttpRequest,
+        sink: &mut dyn BodySink,
+    ) -> Result<HttpResponse, HttpError> {
+        let max_redirects = if request.follow_redirects {
+            request.limits.max_redirects
+        } else {
+            0
+        };
+
+        let mut url = request.url.to_string();
+        let mut redirects = 0;
+
+        // Redirects are followed here rather than by curl, so every target is checked
+        // against the https requirement before it is requested
+        loop {
+            let mut response = self.get_once(&url, request, &mut *sink)?;
+
+            let target = match response.redirect.take() {
+                Some(target) if (300..400).contains(&response.status) && response.status != 304 => {
+                    target
+                }
+                redirect => {
+                    response.redirect = redirect;
+                    response.resolved_url = (redirects > 0).then_some(url);
+                    return Ok(response);
+                }
+            };
+
+            if max_redirects == 0 {
+                response.redirect = Some(target);
+                return Ok(response);
+            }
+
+            if redirects >= max_redirects {
+                return Err(HttpError::Failed(anyhow!(
+                    "Gave up after following {max_redirects} redirects"
+                )));
+            }
+
+            check_redirect(&target).map_err(HttpError::Failed)?;
+            url = target;
+            redirects += 1;
+        }
+    }
+}
+
+impl CurlClient {
+    /// Makes a single request, without following a redirect
+    fn get_once(
+        &self,
+        url: &str,
+        request: &HttpRequest,
+        sink: &mut dyn BodySink,
+    ) -> Result<HttpResponse, HttpError> {
+        let limits = request.limits;
+
+        let mut handle = Easy::new();
+        handle.url(url)?;
+        handle.progress(true)?;
+        handle.connect_timeout(limits.connect_timeout)?;
+
+        if let Some(timeout) = limits.transfer_timeout {
+            handle.timeout(timeout)?;
+        }
+
+        if request.resume_from > 0 {
+            handle.resume_from(request.resume_from)?;
+        }
+
+        if !request.headers.is_empty() {
+            let mut headers = List::new();
+            for header in &request.headers {
+                headers.append(header)?;
+            }
+            handle.http_headers(headers)?;
+        }
+
+        let status = Cell::new(0);
+        let mut etag = None;
+        let mut last_modified = None;
+
+        // Curl calls one callback at a time, both of them feed the sink
+        let sink = RefCell::new(sink);
+
+        let mut transfer = handle.transfer();
+
+        transfer.progress_function(|total, current, _, _| {
+            let total = (total > 0.0).then_some(total as u64);
+
+            // Returning false makes curl abort the transfer
+            sink.borrow_mut().progress(current as u64, total)
+        })?;
+
+        transfer.write_function(|data| {
+            // The body of an error or redirect response is not content
+            if status.get() >= 300 {
+                return Ok(data.len());
+            }
+
+            // Returning a short write makes curl abort the transfer
+            Ok(if sink.borrow_mut().write(data) {
+                data.len()
+            } else {
+                0
+            })
+        })?;
+
+        transfer.header_function(|header| {
+            let header = String::from_utf8_lossy(header);
+
+            // A new status line starts the headers of a new response, e.g. after a redirect
+            if header.starts_with("HTTP/") {
+                let code = header.split_whitespace().nth(1);
+                status.set(code.and_then(|s| s.parse().ok()).unwrap_or(0));
+                etag = None;
+                last_modified = None;
+            }
+
+            if let Some((name, value)) = header.split_once(':') {
+                let value = value.trim().to_string();
+
+                if name.eq_ignore_ascii_case("etag") {
+                    etag = Some(value);
+                } else if name.eq_ignore_ascii_case("last-modified") {
+                    last_modified = Some(value);
+                }
+            }
+
+            true
+        })?;
+
+        let result = transfer.perform();
+        drop(transfer);
+
+        if let Err(e) = result {
+            if e.is_range_error() {
+                return Err(HttpError::RangeRejected);
+            }
+
+            // Failures of the connection itself are worth resuming, anything else is final
+            let transient = e.is_partial_file()
+                || e.is_operation_timedout()
+                || e.is_recv_error()
+                || e.is_send_error()
+                || e.is_got_nothing()
+                || e.is_couldnt_connect();
+
+            if transient {
+                return Err(HttpError::Transient(e.into()));
+            }
+            return Err(HttpError::Failed(e.into()));
+        }
+
+        Ok(HttpResponse {
+            status: handle.response_code()?,
+            etag,
+            last_modified,
+            redirect: handle.redirect_url()?.map(str::to_string),
+            resolved_url: None,
+        })
+    }
+}
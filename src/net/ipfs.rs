@@ -0,0 +1,48 @@
+use anyhow::{Result, anyhow};
+use std::sync::RwLock;
+
+use crate::util::redact_url;
+
+const IPFS_SCHEME: &str = "ipfs://";
+
+/// HTTP gateways `ipfs://` URLs are downloaded through, in order of preference
+static GATEWAYS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Sets the gateways `ipfs://` URLs are downloaded through, e.g. `https://ipfs.io`
+pub fn set_ipfs_gateways(gateways: Vec<String>) {
+    if let Ok(mut current) = GATEWAYS.write() {
+        *current = gateways;
+    }
+}
+
+pub fn is_ipfs_url(url: &str) -> bool {
+    url.starts_with(IPFS_SCHEME)
+}
+
+/// URLs the content of a URL is requested from. An `ipfs://<cid>/<path>` URL becomes
+/// `<gateway>/ipfs/<cid>/<path>` on every configured gateway, other URLs are kept.
+pub fn gateway_urls(url: &str) -> Result<Vec<String>> {
+    let Some(content_path) = url.strip_prefix(IPFS_SCHEME) else {
+        return Ok(vec![url.to_string()]);
+    };
+
+    if content_path.is_empty() {
+        return Err(anyhow!("IPFS URL {} names no content", redact_url(url)));
+    }
+
+    let gateways = GATEWAYS
+        .read()
+        .map_err(|_| anyhow!("IPFS gateway state is poisoned"))?;
+
+    if gateways.is_empty() {
+        return Err(anyhow!(
+            "Cannot download {} without an IPFS gateway, set ipfs_gateways",
+            redact_url(url)
+        ));
+    }
+
+    Ok(gateways
+        .iter()
+        .map(|gateway| format!("{}/ipfs/{content_path}", gateway.trim_end_matches('/')))
+        .collect())
+}
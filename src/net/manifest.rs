@@ -0,0 +1,57 @@
+use anyhow::{Result, anyhow};
+
+use crate::{
+    config::DownloadLimits,
+    net::{BodySink, HttpClient, HttpRequest},
+    util::{find_manifest_digest, redact_url},
+};
+
+/// Checksum manifests list a few files, anything larger is not one
+const MAX_MANIFEST_SIZE: usize = 1024 * 1024;
+
+/// Collects a manifest body, refusing anything too large to be one
+struct ManifestSink(Vec<u8>);
+
+impl BodySink for ManifestSink {
+    fn write(&mut self, data: &[u8]) -> bool {
+        if self.0.len() + data.len() > MAX_MANIFEST_SIZE {
+            return false;
+        }
+        self.0.extend_from_slice(data);
+        true
+    }
+}
+
+/// Downloads a published sha256 sums file and returns the digest it lists for `file`.
+/// Redirects are followed within the limits, e.g. to the mirror a release page points at.
+pub fn fetch_manifest_sha256(
+    client: &dyn HttpClient,
+    url: &str,
+    file: &str,
+    limits: &DownloadLimits,
+) -> Result<String> {
+    let shown = redact_url(url);
+    let mut body = ManifestSink(Vec::new());
+
+    let request = HttpRequest {
+        url,
+        headers: Vec::new(),
+        resume_from: 0,
+        follow_redirects: true,
+        limits,
+    };
+
+    let response = client
+        .get(&request, &mut body)
+        .map_err(|e| anyhow!("Failed to download sha256sums {shown}, {e}"))?;
+
+    let status = response.status;
+    if !(200..300).contains(&status) {
+        return Err(anyhow!(
+            "Failed to download sha256sums {shown}, server responded with {status}"
+        ));
+    }
+
+    let manifest = String::from_utf8_lossy(&body.0);
+    find_manifest_digest(&manifest, file).ok_or(anyhow!("sha256sums {shown} does not list {file}"))
+}
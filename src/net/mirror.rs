@@ -0,0 +1,239 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    hash::BuildHasher,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use url::Url;
+
+use crate::{
+    config::{DownloadLimits, MirrorStrategy},
+    net::probe_url,
+    util::{format_unix_time, parallel_map, print_warn, redact_url},
+};
+
+/// Latency of every server probed this session by origin, unset if it did not answer
+static LATENCIES: Mutex<BTreeMap<String, Option<Duration>>> = Mutex::new(BTreeMap::new());
+
+/// Servers that failed to answer by origin, they are skipped until their backoff ends
+static HEALTH: Mutex<BTreeMap<String, MirrorHealth>> = Mutex::new(BTreeMap::new());
+
+/// File the health of servers is kept in across runs, only in memory when not set
+static HEALTH_FILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Probing a server that does not answer is not worth the full connect timeout
+const PROBE_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time a server is skipped for after its first failure, doubled with each further one
+const BACKOFF: Duration = Duration::from_secs(60);
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MirrorHealth {
+    /// Failures in a row, without an answer in between
+    failures: u32,
+
+    /// Unix time until which the server is skipped
+    down_until: u64,
+}
+
+/// Keeps the health of servers in a file, so servers found down are also skipped by
+/// later runs until their backoff ends. Entries that already expired are dropped.
+pub fn persist_mirror_health(path: &Path) -> Result<()> {
+    let loaded = match std::fs::read_to_string(path) {
+        Ok(s) => serde_yaml::from_str::<BTreeMap<String, MirrorHealth>>(&s)
+            .map_err(|e| anyhow!("Invalid mirror health file {path:#?}, {e}"))?,
+        Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => return Err(anyhow!("Failed to read mirror health file {path:#?}, {e}")),
+    };
+
+    let now = unix_now();
+
+    if let Ok(mut health) = HEALTH.lock() {
+        health.extend(loaded.into_iter().filter(|(_, h)| h.down_until > now));
+    }
+
+    if let Ok(mut file) = HEALTH_FILE.lock() {
+        *file = Some(path.to_path_buf());
+    }
+
+    Ok(())
+}
+
+/// Forgets which servers were found down, including those in the persisted file
+pub fn reset_mirror_health(path: &Path) -> Result<()> {
+    if let Ok(mut health) = HEALTH.lock() {
+        health.clear();
+    }
+
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(anyhow!(
+            "Failed to remove mirror health file {path:#?}, {e}"
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Whether the server of a URL failed recently and is skipped until its backoff ends
+pub fn is_mirror_down(url: &str) -> bool {
+    let Some(origin) = origin(url) else {
+        return false;
+    };
+
+    HEALTH.lock().is_ok_and(|health| {
+        health
+            .get(&origin)
+            .is_some_and(|h| h.down_until > unix_now())
+    })
+}
+
+/// Records that the server of a URL did not answer, backing off for longer each time
+pub(crate) fn mark_mirror_down(url: &str) {
+    let Some(origin) = origin(url) else {
+        return;
+    };
+
+    let Ok(mut health) = HEALTH.lock() else {
+        return;
+    };
+
+    let failures = health.get(&origin).map_or(0, |h| h.failures) + 1;
+    let backoff = BACKOFF
+        .saturating_mul(2u32.saturating_pow(failures - 1))
+        .min(MAX_BACKOFF);
+    let down_until = unix_now() + backoff.as_secs();
+
+    log::info!(
+        "Mirror {} is down, skipping it until {}",
+        redact_url(url),
+        format_unix_time(down_until)
+    );
+
+    health.insert(
+        origin,
+        MirrorHealth {
+            failures,
+            down_until,
+        },
+    );
+    save_health(&health);
+}
+
+/// Records that the server of a URL answered, ending any backoff
+pub(crate) fn mark_mirror_up(url: &str) {
+    let Some(origin) = origin(url) else {
+        return;
+    };
+
+    if let Ok(mut health) = HEALTH.lock()
+        && health.remove(&origin).is_some()
+    {
+        save_health(&health);
+    }
+}
+
+fn save_health(health: &BTreeMap<String, MirrorHealth>) {
+    let Some(path) = HEALTH_FILE.lock().ok().and_then(|f| f.clone()) else {
+        return;
+    };
+
+    let saved = serde_yaml::to_string(health)
+        .map_err(|e| anyhow!("{e}"))
+        .and_then(|s| std::fs::write(&path, s).map_err(|e| anyhow!("{e}")));
+
+    if let Err(e) = saved {
+        print_warn(&format!("Failed to save mirror health to {path:#?}, {e}"));
+    }
+}
+
+fn origin(url: &str) -> Option<String> {
+    Some(Url::parse(url).ok()?.origin().ascii_serialization())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Orders the URLs a source can be downloaded from, which are tried in the returned
+/// order. Servers that are down are skipped, unless every one of them is. The fastest
+/// strategy probes each server once per session, servers that did not answer come last.
+pub fn order_mirrors(
+    mut urls: Vec<String>,
+    strategy: MirrorStrategy,
+    limits: &DownloadLimits,
+) -> Vec<String> {
+    if urls.len() < 2 {
+        return urls;
+    }
+
+    if urls.iter().any(|url| !is_mirror_down(url)) {
+        urls.retain(|url| {
+            let down = is_mirror_down(url);
+            if down {
+                log::info!("Skipping mirror {}, it is down", redact_url(url));
+            }
+            !down
+        });
+    }
+
+    match strategy {
+        MirrorStrategy::Ordered => {}
+        MirrorStrategy::Random => {
+            let state = std::hash::RandomState::new();
+            urls.sort_by_cached_key(|url| state.hash_one(url));
+        }
+        MirrorStrategy::Fastest => {
+            let latencies = parallel_map(&urls, None, |url| latency(url, limits));
+            let mut ranked = urls.into_iter().zip(latencies).collect::<Vec<_>>();
+
+            // A stable sort keeps the recipe order between servers that are equally fast
+            ranked.sort_by_key(|(_, latency)| latency.unwrap_or(Duration::MAX));
+            urls = ranked.into_iter().map(|(url, _)| url).collect();
+        }
+    }
+
+    urls
+}
+
+/// Time the server of a URL takes to answer a HEAD request for it, measured once per
+/// server and session
+fn latency(url: &str, limits: &DownloadLimits) -> Option<Duration> {
+    let origin = origin(url)?;
+
+    if let Some(latency) = LATENCIES.lock().ok()?.get(&origin) {
+        return *latency;
+    }
+
+    let limits = DownloadLimits {
+        connect_timeout: limits.connect_timeout.min(PROBE_CONNECT_TIMEOUT),
+        transfer_timeout: Some(PROBE_CONNECT_TIMEOUT * 2),
+        ..*limits
+    };
+
+    let started = Instant::now();
+    let latency = match probe_url(url, &limits, false) {
+        Ok(probe) if probe.status < 400 => Some(started.elapsed()),
+        Ok(_) => None,
+        Err(_) => {
+            mark_mirror_down(url);
+            None
+        }
+    };
+
+    match latency {
+        Some(latency) => log::debug!("Mirror {} answered in {latency:?}", redact_url(url)),
+        None => log::debug!("Mirror {} did not answer", redact_url(url)),
+    }
+
+    if let Ok(mut latencies) = LATENCIES.lock() {
+        latencies.insert(origin, latency);
+    }
+
+    latency
+}
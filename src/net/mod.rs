@@ -0,0 +1,18 @@
+mod checksum;
+mod downloader;
+mod http;
+mod ipfs;
+mod manifest;
+mod mirror;
+mod probe;
+mod security;
+
+pub use checksum::{SourceChecksum, UnhashedSource, checksum_all};
+pub use downloader::{Download, DownloadStats, Downloader, ProgressCallback};
+pub use http::{BodySink, CurlClient, HttpClient, HttpError, HttpRequest, HttpResponse};
+pub use ipfs::{gateway_urls, is_ipfs_url, set_ipfs_gateways};
+pub use manifest::fetch_manifest_sha256;
+pub use mirror::{is_mirror_down, order_mirrors, persist_mirror_health, reset_mirror_health};
+pub(crate) use mirror::{mark_mirror_down, mark_mirror_up};
+pub use probe::{Probe, probe_url};
+pub use security::{check_redirect, check_url_security, set_redirect_policy};
@@ -0,0 +1,163 @@
+use anyhow::{Result, anyhow};
+use curl::easy::{Easy, List};
+
+use crate::{config::DownloadLimits, net::check_redirect};
+
+/// What a server reports about a URL, found without downloading its content
+#[derive(Debug, Clone)]
+pub struct Probe {
+    pub status: u32,
+
+    /// Size of the content, if the server reported it
+    pub size: Option<u64>,
+
+    /// URL the request ended up at after following redirects
+    pub url: String,
+
+    /// Where the server redirects to, if the redirect was not followed
+    pub redirect: Option<String>,
+}
+
+impl Probe {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// Asks the server about a URL with a HEAD request. Servers that reject HEAD are
+/// asked again with a GET for the first byte only. Redirects are followed within the
+/// limits if `follow_redirects` is set.
+pub fn probe_url(url: &str, limits: &DownloadLimits, follow_redirects: bool) -> Result<Probe> {
+    let max_redirects = if follow_redirects {
+        limits.max_redirects
+    } else {
+        0
+    };
+
+    let mut url = url.to_string();
+    let mut redirects = 0;
+
+    // Redirects are followed here rather than by curl, so every target is checked
+    // against the https requirement before it is requested
+    loop {
+        let mut probe = probe_once(&url, limits)?;
+
+        let target = match probe.redirect.take() {
+            Some(target) if (300..400).contains(&probe.status) && probe.status != 304 => target,
+            redirect => {
+                probe.redirect = redirect;
+                return Ok(probe);
+            }
+        };
+
+        if max_redirects == 0 {
+            probe.redirect = Some(target);
+            return Ok(probe);
+        }
+
+        if redirects >= max_redirects {
+            return Err(anyhow!("Gave up after following {max_redirects} redirects"));
+        }
+
+        check_redirect(&target)?;
+        url = target;
+        redirects += 1;
+    }
+}
+
+/// Probes a single URL, without following a redirect
+fn probe_once(url: &str, limits: &DownloadLimits) -> Result<Probe> {
+    let probe = head(url, limits)?;
+
+    if matches!(probe.status, 403 | 405 | 501) {
+        return first_byte(url, limits);
+    }
+
+    Ok(probe)
+}
+
+fn request(url: &str, limits: &DownloadLimits) -> Result<Easy> {
+    let mut handle = Easy::new();
+    handle.url(url)?;
+    handle.connect_timeout(limits.connect_timeout)?;
+
+    if let Some(timeout) = limits.transfer_timeout {
+        handle.timeout(timeout)?;
+    }
+
+    Ok(handle)
+}
+
+fn head(url: &str, limits: &DownloadLimits) -> Result<Probe> {
+    let mut handle = request(url, limits)?;
+    handle.nobody(true)?;
+    handle.perform()?;
+
+    let length = handle.content_length_download()?;
+
+    Ok(Probe {
+        status: handle.response_code()?,
+        size: (length >= 0.0).then_some(length as u64),
+        url: url.to_string(),
+        redirect: handle.redirect_url()?.map(str::to_string),
+    })
+}
+
+fn first_byte(url: &str, limits: &DownloadLimits) -> Result<Probe> {
+    let mut handle = request(url, limits)?;
+
+    let mut headers = List::new();
+    headers.append("Range: bytes=0-0")?;
+    handle.http_headers(headers)?;
+
+    let mut content_range = None;
+    let mut received = 0;
+
+    let performed = {
+        let mut transfer = handle.transfer();
+        transfer.header_function(|header| {
+            let header = String::from_utf8_lossy(header);
+
+            if let Some((name, value)) = header.split_once(':')
+                && name.eq_ignore_ascii_case("content-range")
+            {
+                content_range = Some(value.trim().to_string());
+            }
+            true
+        })?;
+        transfer.write_function(|data| {
+            received += data.len();
+
+            // A server ignoring the range sends everything, the headers are enough
+            Ok(if received > 1 { 0 } else { data.len() })
+        })?;
+        transfer.perform()
+    };
+
+    // Stopping a transfer that ignored the range is not a failure of the probe
+    if let Err(e) = performed
+        && !e.is_write_error()
+    {
+        return Err(e.into());
+    }
+
+    let status = handle.response_code()?;
+
+    // The total size follows the slash in "bytes 0-0/12345"
+    let size = match status {
+        206 => content_range
+            .and_then(|range| range.rsplit_once('/').map(|(_, total)| total.to_string()))
+            .and_then(|total| total.parse().ok()),
+        _ => {
+            let length = handle.content_length_download()?;
+            (length >= 0.0).then_some(length as u64)
+        }
+    };
+
+    Ok(Probe {
+        status,
+        size,
+        url: url.to_string(),
+        redirect: handle.redirect_url()?.map(str::to_string),
+    })
+}
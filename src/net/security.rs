@@ -0,0 +1,132 @@
+use anyhow::{Result, anyhow};
+use std::sync::RwLock;
+use url::Url;
+
+use crate::{
+    config::Config,
+    model::Source,
+    net::{gateway_urls, is_ipfs_url},
+    util::{print_warn, redact_url},
+};
+
+/// Warns about sources fetched without TLS, or refuses them if https is required.
+/// Hosts listed as insecure hosts in the configuration are trusted either way.
+/// The URL the source is requested from is checked, but only its public URL is printed.
+/// Mirrors, parts and a sha256 sums file the source is verified against are checked the
+/// same way.
+pub fn check_url_security<S: Source>(source: &S, config: &Config) -> Result<()> {
+    let config = &UrlPolicy::from(config);
+    check_url(&source.url(), &source.request_url()?, config)?;
+
+    for mirror in source.mirrors() {
+        check_url(&mirror, &source.mirror_request_url(&mirror)?, config)?;
+    }
+
+    for part in source.parts()? {
+        check_url(&part.url, &part.request_url, config)?;
+    }
+
+    if let Some((manifest_url, _)) = source.sha256_manifest()? {
+        let shown = source.sha256_manifest_url().unwrap_or(manifest_url.clone());
+        check_url(&shown, &manifest_url, config)?;
+    }
+
+    Ok(())
+}
+
+/// What URLs may be requested over, from the configuration
+#[derive(Debug)]
+struct UrlPolicy {
+    require_https: bool,
+    insecure_hosts: Vec<String>,
+}
+
+impl UrlPolicy {
+    /// URLs that are downloaded without a warning, over https or from a trusted host
+    fn trusts(&self, url: &Url) -> bool {
+        url.scheme() == "https"
+            || url
+                .host_str()
+                .is_some_and(|host| self.insecure_hosts.iter().any(|h| h == host))
+    }
+}
+
+impl From<&Config> for UrlPolicy {
+    fn from(config: &Config) -> Self {
+        Self {
+            require_https: config.require_https,
+            insecure_hosts: config.insecure_hosts.clone(),
+        }
+    }
+}
+
+/// Policy redirects are checked against, they are followed long after the source URL
+/// was checked
+static REDIRECT_POLICY: RwLock<UrlPolicy> = RwLock::new(UrlPolicy {
+    require_https: false,
+    insecure_hosts: Vec::new(),
+});
+
+/// Sets the https requirement and trusted hosts the targets of redirects are checked against
+pub fn set_redirect_policy(config: &Config) {
+    if let Ok(mut policy) = REDIRECT_POLICY.write() {
+        *policy = UrlPolicy::from(config);
+    }
+}
+
+/// Checks a URL a download is redirected to, so a redirect cannot lead around the https
+/// requirement. Only http and https targets are followed.
+pub fn check_redirect(target: &str) -> Result<()> {
+    let url = redact_url(target);
+    let parsed = Url::parse(target).map_err(|e| anyhow!("Invalid redirect to {url}, {e}"))?;
+
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(anyhow!(
+            "Refusing to follow the redirect to {url}, only http and https are followed"
+        ));
+    }
+
+    let policy = REDIRECT_POLICY
+        .read()
+        .map_err(|_| anyhow!("Redirect policy state is poisoned"))?;
+
+    if policy.require_https && !policy.trusts(&parsed) {
+        return Err(anyhow!(
+            "Refusing to follow the redirect to {url} over insecure {}, https is required",
+            parsed.scheme()
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_url(shown: &str, request_url: &str, config: &UrlPolicy) -> Result<()> {
+    // IPFS content is only ever fetched through the gateways, which are what is checked
+    if is_ipfs_url(request_url) {
+        for gateway_url in gateway_urls(request_url)? {
+            check_url(&gateway_url, &gateway_url, config)?;
+        }
+        return Ok(());
+    }
+
+    let url = redact_url(shown);
+    let parsed = Url::parse(request_url).map_err(|e| anyhow!("Invalid source URL {url}, {e}"))?;
+
+    if config.trusts(&parsed) {
+        return Ok(());
+    }
+
+    if config.require_https {
+        return Err(anyhow!(
+            "Refusing to download {url} over insecure {}, https is required",
+            parsed.scheme()
+        ));
+    }
+
+    print_warn(&format!(
+        "Source {url} is downloaded over insecure {}",
+        parsed.scheme()
+    ));
+
+    Ok(())
+}
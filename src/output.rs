@@ -0,0 +1,218 @@
+use std::{
+    io::{IsTerminal, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::{Result, anyhow};
+
+use tetra_pkgmgr::{
+    TetraRoot,
+    model::{PackageId, Recipe, RecipeSource},
+    store::Cache,
+    util::{Style, interpolate_env, paint, redact_url},
+};
+
+/// Outcome of a single check of `tetra doctor` or `tetra verify`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// Prints check results as they come in and remembers whether any failed
+#[derive(Debug, Default)]
+pub struct Report {
+    pub failed: bool,
+}
+
+impl Report {
+    pub fn check(&mut self, status: Status, message: &str, hint: Option<&str>) {
+        let label = match status {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        };
+
+        let style = match status {
+            Status::Pass => Style::Success,
+            Status::Warn => Style::Warn,
+            Status::Fail => Style::Error,
+        };
+
+        println!("[{}] {message}", paint(label, style));
+
+        if status != Status::Pass
+            && let Some(hint) = hint
+        {
+            println!("       {}", paint(format!("hint: {hint}"), Style::Dim));
+        }
+
+        self.failed |= status == Status::Fail;
+    }
+}
+
+/// A package whose sources `tetra verify` or `tetra check-sources` go through
+#[derive(Debug)]
+pub struct CheckedPackage {
+    pub recipe: Recipe,
+    pub cache: Cache,
+
+    /// Index of the only source to check, if one was selected
+    selected: Option<usize>,
+}
+
+impl CheckedPackage {
+    /// Resolves a package, its selected source and its cache, reporting what fails as a
+    /// failed check, and prints the package header
+    pub fn load(
+        report: &mut Report,
+        tetra_root: &TetraRoot,
+        package_id: String,
+        exact: bool,
+        selector: Option<String>,
+    ) -> Option<Self> {
+        let id = PackageId::from_id_str(package_id);
+        let arches = tetra_root.get_arch_preference();
+
+        let repo = match tetra_root.repo(&id.repo) {
+            Ok(Some(r)) => r,
+            Ok(None) => {
+                report.check(
+                    Status::Fail,
+                    &format!("Cannot find repository with ID {}", id.repo),
+                    None,
+                );
+                return None;
+            }
+            Err(e) => {
+                report.check(
+                    Status::Fail,
+                    &format!("Failed to locate repositories, {e}"),
+                    None,
+                );
+                return None;
+            }
+        };
+
+        let recipe = match repo
+            .resolve_package_id(&id, &arches, exact)
+            .and_then(|path| repo.load_recipe(&path))
+        {
+            Ok(r) => r,
+            Err(e) => {
+                report.check(
+                    Status::Fail,
+                    &format!("Failed to load recipe of {id}, {e}"),
+                    None,
+                );
+                return None;
+            }
+        };
+
+        let selected = match selector.as_deref().map(|s| recipe.select_source(s)) {
+            Some(Ok(source)) => recipe.sources.iter().position(|s| std::ptr::eq(s, source)),
+            Some(Err(e)) => {
+                report.check(Status::Fail, &e.to_string(), None);
+                return None;
+            }
+            None => None,
+        };
+
+        let cache = match tetra_root.cache() {
+            Ok(c) => c.for_recipe(&recipe),
+            Err(e) => {
+                report.check(
+                    Status::Fail,
+                    &format!("Failed to obtain cache object, {e}"),
+                    None,
+                );
+                return None;
+            }
+        };
+
+        println!("Package: {id}");
+
+        if let Err(e) = recipe.check_sources_hash() {
+            report.check(
+                Status::Warn,
+                &e.to_string(),
+                Some("A source was added or changed without updating sources_hash"),
+            );
+        }
+
+        if recipe.is_metapackage() {
+            println!("Sources: no sources (metapackage)");
+        }
+
+        Some(Self {
+            recipe,
+            cache,
+            selected,
+        })
+    }
+
+    /// The sources to check, each printed as a heading when it is reached
+    pub fn sources(&self) -> impl Iterator<Item = &RecipeSource> {
+        self.recipe
+            .sources
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| self.selected.is_none_or(|s| s == *index))
+            .map(|(index, source)| {
+                println!();
+                println!(
+                    "Source {}: {}",
+                    source.reference(index),
+                    display_url(&source.url)
+                );
+                source
+            })
+    }
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static SHOW_SECRETS: AtomicBool = AtomicBool::new(false);
+
+/// Suppresses progress bars and download summaries for the rest of the run
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Shows the cursor again and ends the line a progress bar may have left unfinished
+pub fn restore_terminal() {
+    let mut stderr = std::io::stderr();
+
+    if stderr.is_terminal() {
+        let _ = writeln!(stderr, "\x1b[?25h");
+    }
+}
+
+/// Prints URLs in full, including credentials, for the rest of the run
+pub fn set_show_secrets(show: bool) {
+    SHOW_SECRETS.store(show, Ordering::Relaxed);
+}
+
+/// A URL as it may be printed, redacted unless secrets are shown
+pub fn display_url(url: &str) -> String {
+    if SHOW_SECRETS.load(Ordering::Relaxed) {
+        url.to_string()
+    } else {
+        redact_url(url)
+    }
+}
+
+/// A URL template as it is requested when secrets are shown, with its environment
+/// references filled in. Otherwise the template is redacted like [`display_url`] does,
+/// as the variables it refers to commonly hold credentials.
+pub fn display_request_url(url: &str) -> Result<String> {
+    if SHOW_SECRETS.load(Ordering::Relaxed) {
+        interpolate_env(url).map_err(|e| anyhow!("Cannot build URL {}, {e}", redact_url(url)))
+    } else {
+        Ok(redact_url(url))
+    }
+}
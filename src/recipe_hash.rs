@@ -0,0 +1,178 @@
+use anyhow::{Result, anyhow};
+use serde_yaml::{Mapping, Value};
+use std::{io::Write, path::Path};
+
+use tetra_pkgmgr::{
+    TetraRoot,
+    config::Config,
+    model::PackageId,
+    net::{UnhashedSource, check_url_security, checksum_all},
+    util::print_error,
+};
+
+use crate::output;
+
+/// Downloads the sources of a partial recipe, or of a file listing one URL per line,
+/// and prints its `sources` block with the computed hashes filled in. Sources that
+/// already have a hash are left as they are. Returns false if any source failed.
+pub fn run(
+    tetra_root: &TetraRoot,
+    config: &Config,
+    file: &Path,
+    sha256: bool,
+    output: Option<&Path>,
+) -> bool {
+    let is_recipe = file
+        .extension()
+        .is_some_and(|ext| ext == "yml" || ext == "yaml");
+
+    let loaded = if is_recipe {
+        load_recipe(file)
+    } else {
+        load_url_list(file)
+    };
+
+    let (package_id, mut sources) = match loaded {
+        Ok(l) => l,
+        Err(e) => {
+            print_error(&format!("Failed to read {file:#?}: {e}"));
+            return false;
+        }
+    };
+
+    let cache = match tetra_root.cache() {
+        Ok(c) => c,
+        Err(e) => {
+            print_error(&format!("Failed to obtain cache object: {e}"));
+            return false;
+        }
+    };
+
+    // Entries without a url, or with a hash already, are only passed through
+    let mut unhashed = Vec::new();
+    let mut failed = false;
+
+    for entry in &sources {
+        let Some(url) = entry.get("url").and_then(Value::as_str) else {
+            continue;
+        };
+
+        if entry.contains_key("hash") {
+            continue;
+        }
+
+        let source = UnhashedSource {
+            url: url.to_string(),
+        };
+
+        match check_url_security(&source, config) {
+            Ok(()) => unhashed.push(source),
+            Err(e) => {
+                print_error(&e.to_string());
+                failed = true;
+            }
+        }
+    }
+
+    let limits = config.download_limits();
+    let results = checksum_all(tetra_root, &cache, &unhashed, &package_id, &limits, sha256);
+
+    for (source, result) in unhashed.iter().zip(results) {
+        let checksum = match result {
+            Ok(c) => c,
+            Err(e) => {
+                print_error(&format!(
+                    "Failed to hash {}: {e}",
+                    output::display_url(&source.url)
+                ));
+                failed = true;
+                continue;
+            }
+        };
+
+        let entries = sources.iter_mut().filter(|e| {
+            !e.contains_key("hash") && e.get("url").and_then(Value::as_str) == Some(&source.url)
+        });
+
+        for entry in entries {
+            entry.insert("hash".into(), checksum.hash.to_string().into());
+
+            if let Some(sha256) = &checksum.sha256 {
+                entry.insert("sha256".into(), sha256.as_str().into());
+            }
+        }
+    }
+
+    let mut block = Mapping::new();
+    block.insert(
+        "sources".into(),
+        sources.into_iter().map(Value::Mapping).collect(),
+    );
+
+    let yaml = match serde_yaml::to_string(&block) {
+        Ok(y) => y,
+        Err(e) => {
+            print_error(&format!("Failed to serialize sources: {e}"));
+            return false;
+        }
+    };
+
+    let written = match output {
+        Some(path) => std::fs::write(path, &yaml),
+        None => std::io::stdout().write_all(yaml.as_bytes()),
+    };
+
+    if let Err(e) = written {
+        print_error(&format!("Failed to write sources: {e}"));
+        return false;
+    }
+
+    !failed
+}
+
+/// The sources of a partial recipe, provenance records them under its name and version
+fn load_recipe(file: &Path) -> Result<(PackageId, Vec<Mapping>)> {
+    let recipe: Value = serde_yaml::from_str(&std::fs::read_to_string(file)?)?;
+
+    let field = |name| recipe.get(name).and_then(Value::as_str);
+    let package_id = match (field("name"), field("version")) {
+        (Some(name), Some(version)) => PackageId::from_id_str(format!("{name}@{version}")),
+        (Some(name), None) => PackageId::from_id_str(name.to_string()),
+        _ => file_package_id(file),
+    };
+
+    let sources = recipe
+        .get("sources")
+        .and_then(Value::as_sequence)
+        .ok_or(anyhow!("Recipe has no sources"))?
+        .iter()
+        .map(|s| {
+            s.as_mapping()
+                .cloned()
+                .ok_or(anyhow!("Every source must be a mapping with a url"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok((package_id, sources))
+}
+
+/// One URL per line, blank lines and lines starting with `#` are skipped
+fn load_url_list(file: &Path) -> Result<(PackageId, Vec<Mapping>)> {
+    let sources = std::fs::read_to_string(file)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|url| {
+            let mut entry = Mapping::new();
+            entry.insert("url".into(), url.into());
+            entry
+        })
+        .collect();
+
+    Ok((file_package_id(file), sources))
+}
+
+fn file_package_id(file: &Path) -> PackageId {
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    PackageId::from_id_str(stem.to_string())
+}
@@ -0,0 +1,85 @@
+use anyhow::{Result, anyhow};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+use tar::EntryType;
+
+use crate::util::normalize_path;
+
+/// In-memory index of a packed repository. The archive is read once when the
+/// repository is loaded, afterwards entries are looked up by their path inside it.
+#[derive(Debug, Default)]
+pub struct ArchiveTree {
+    files: HashMap<PathBuf, Vec<u8>>,
+    dirs: HashSet<PathBuf>,
+}
+
+impl ArchiveTree {
+    pub const EXTENSION: &str = ".tar.zst";
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let decoder = zstd::Decoder::new(File::open(path)?)?;
+        let mut archive = tar::Archive::new(decoder);
+
+        let mut tree = Self::default();
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let entry_path = normalize_path(&entry.path()?);
+
+            match entry.header().entry_type() {
+                EntryType::Directory => tree.add_dir(&entry_path),
+                EntryType::Regular => {
+                    let mut contents = Vec::new();
+                    entry.read_to_end(&mut contents)?;
+
+                    if let Some(parent) = entry_path.parent() {
+                        tree.add_dir(parent);
+                    }
+
+                    tree.files.insert(entry_path, contents);
+                }
+                // Links and special files have no meaning in a packed repository
+                _ => {}
+            }
+        }
+
+        Ok(tree)
+    }
+
+    fn add_dir(&mut self, path: &Path) {
+        for ancestor in path.ancestors() {
+            self.dirs.insert(ancestor.to_path_buf());
+        }
+    }
+
+    pub fn is_dir(&self, path: &Path) -> bool {
+        self.dirs.contains(path)
+    }
+
+    pub fn is_file(&self, path: &Path) -> bool {
+        self.files.contains_key(path)
+    }
+
+    /// Names of the directories directly below `path`
+    pub fn child_dirs(&self, path: &Path) -> Vec<String> {
+        self.dirs
+            .iter()
+            .filter(|dir| dir.parent() == Some(path))
+            .filter_map(|dir| dir.file_name())
+            .map(|name| name.to_string_lossy().to_string())
+            .collect()
+    }
+
+    pub fn read_to_string(&self, path: &Path) -> Result<String> {
+        let contents = self
+            .files
+            .get(path)
+            .ok_or(anyhow!("Archive entry {path:#?} does not exist"))?;
+
+        Ok(String::from_utf8(contents.clone())?)
+    }
+}
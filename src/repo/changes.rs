@@ -0,0 +1,114 @@
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt, path::PathBuf, time::SystemTime};
+
+use crate::{model::PackageId, repo::Repository};
+
+/// How a recipe changed, judged by the modification times of the recipe files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RecipeChange {
+    /// The package directory was created after the cutoff, so the package is new
+    NewPackage,
+
+    /// Every recipe of the version changed while other versions did not
+    NewVersion,
+
+    /// A variant was added to a version that had others, or its recipe was edited. Also
+    /// reported when every recipe of a package changed but its directory is older than
+    /// the cutoff, or the filesystem does not record when it was created.
+    Modified,
+}
+
+impl fmt::Display for RecipeChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NewPackage => write!(f, "new package"),
+            Self::NewVersion => write!(f, "new version"),
+            Self::Modified => write!(f, "modified"),
+        }
+    }
+}
+
+/// A recipe whose file was modified after the cutoff given to [`changed_since`]
+#[derive(Debug)]
+pub struct ChangedRecipe {
+    pub id: PackageId,
+    pub path: PathBuf,
+    pub modified: SystemTime,
+    pub change: RecipeChange,
+}
+
+/// Recipes of a repository whose files were modified after `cutoff`. Only modification
+/// times are compared, so within a version an added recipe cannot be told apart from an
+/// edited one. A package only counts as new if its directory was created after `cutoff`,
+/// as an edit touching every recipe looks the same otherwise. Packed repositories have no
+/// times to compare.
+pub fn changed_since(repo: &Repository, cutoff: SystemTime) -> Result<Vec<ChangedRecipe>> {
+    if Repository::is_archive_path(&repo.path) {
+        return Err(anyhow!(
+            "Packed repository {} has no file modification times",
+            repo.id
+        ));
+    }
+
+    let mut recipes = Vec::new();
+
+    for resolved in repo.recipes()? {
+        let modified = std::fs::metadata(&resolved.path)?.modified()?;
+        recipes.push((resolved, modified));
+    }
+
+    // Whether every recipe of a package, and of a version, changed
+    let mut packages = BTreeMap::<&str, bool>::new();
+    let mut versions = BTreeMap::<(&str, &str), bool>::new();
+
+    for (resolved, modified) in &recipes {
+        let changed = *modified > cutoff;
+        let id = &resolved.id;
+
+        *packages.entry(&id.name).or_insert(true) &= changed;
+        *versions.entry((&id.name, &id.version)).or_insert(true) &= changed;
+    }
+
+    // Creation times are not kept by every filesystem, without one nothing proves it new
+    let new_packages = packages
+        .iter()
+        .filter(|(_, all_changed)| **all_changed)
+        .filter(|(name, _)| {
+            std::fs::metadata(repo.package_path(name))
+                .and_then(|meta| meta.created())
+                .is_ok_and(|created| created > cutoff)
+        })
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>();
+
+    let changes = recipes
+        .iter()
+        .map(|(resolved, _)| {
+            let id = &resolved.id;
+
+            match (
+                packages[id.name.as_str()],
+                versions[&(&*id.name, &*id.version)],
+            ) {
+                (true, _) if new_packages.contains(&id.name.as_str()) => RecipeChange::NewPackage,
+                (true, _) => RecipeChange::Modified,
+                (false, true) => RecipeChange::NewVersion,
+                (false, false) => RecipeChange::Modified,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    Ok(recipes
+        .into_iter()
+        .zip(changes)
+        .filter(|((_, modified), _)| *modified > cutoff)
+        .map(|((resolved, modified), change)| ChangedRecipe {
+            id: resolved.id,
+            path: resolved.path,
+            modified,
+            change,
+        })
+        .collect())
+}
@@ -0,0 +1,217 @@
+use anyhow::{Result, anyhow};
+use std::path::PathBuf;
+
+use crate::{
+    model::{PackageId, Recipe},
+    repo::Repository,
+};
+
+/// A package in the dependency closure of another
+#[derive(Debug)]
+pub struct ClosureEntry {
+    pub id: PackageId,
+    pub path: PathBuf,
+    pub recipe: Recipe,
+
+    /// Indices in the closure of the packages satisfying the dependencies of the recipe,
+    /// in the order it lists them
+    pub dependencies: Vec<usize>,
+}
+
+/// A recipe that provides virtual package names
+#[derive(Debug)]
+struct Provider<'a> {
+    repo: &'a Repository,
+    id: PackageId,
+    path: PathBuf,
+    provides: Vec<String>,
+}
+
+/// Resolves a package and everything it transitively depends on, in the order they
+/// were first reached. Each recipe appears once, dependency cycles are not an error.
+/// Metapackages are part of the closure and only contribute their dependencies.
+///
+/// A name no package in its repository has is the former name of a package that
+/// replaces it, in that or another repository, or else a virtual package, satisfied by
+/// any recipe that provides it. Packages that conflict with another package in the
+/// closure make the resolution fail.
+pub fn resolve_closure(
+    repos: &[Repository],
+    id: PackageId,
+    arches: &[String],
+) -> Result<Vec<ClosureEntry>> {
+    let mut closure: Vec<ClosureEntry> = Vec::new();
+    let mut providers: Option<Vec<Provider>> = None;
+    // Dependencies waiting to be resolved, with the index of the package requiring them
+    let mut queue = vec![(id, None::<usize>)];
+
+    while let Some((id, required_by)) = queue.pop() {
+        let repo = repos
+            .iter()
+            .find(|r| r.id == id.repo)
+            .ok_or(anyhow!("Cannot find repository with ID {}", id.repo))?;
+
+        let resolved = match repo.find_package(&id.name)? {
+            Some(_) => repo
+                .resolve_package_id(&id, arches, false)
+                .map(|path| (repo, id.clone(), path)),
+            None if let Some((repo, new_name)) = find_replacement(repos, repo, &id.name)? => {
+                let mut new_id = id.clone();
+                new_id.repo = repo.id.clone();
+                new_id.name = new_name;
+                repo.resolve_package_id(&new_id, arches, false)
+                    .map(|path| (repo, new_id, path))
+            }
+            None => {
+                let providers = match &mut providers {
+                    Some(providers) => providers,
+                    None => providers.insert(collect_providers(repos, arches)?),
+                };
+                select_provider(providers, &closure, &id)
+            }
+        };
+
+        let (repo, id, path) = resolved.map_err(|e| match required_by {
+            Some(parent) => anyhow!(
+                "Dependency {id} of {} could not be resolved, {e}",
+                closure[parent].id
+            ),
+            None => e,
+        })?;
+
+        let existing = closure
+            .iter()
+            .position(|e| e.id.repo == repo.id && e.path == path);
+
+        let index = existing.unwrap_or(closure.len());
+        if let Some(parent) = required_by {
+            closure[parent].dependencies.push(index);
+        }
+
+        if existing.is_some() {
+            continue;
+        }
+
+        let recipe = repo.load_recipe(&path)?;
+
+        // Reversed so dependencies are visited in the order the recipe lists them
+        for dep in recipe.depends.iter().rev() {
+            queue.push((PackageId::from_dep_str(dep, &repo.id), Some(index)));
+        }
+
+        closure.push(ClosureEntry {
+            id,
+            path,
+            recipe,
+            dependencies: Vec::new(),
+        });
+    }
+
+    check_conflicts(&closure)?;
+    Ok(closure)
+}
+
+/// The package that replaces `name` of `repo`, looked up in `repo` first and then in
+/// the other repositories in order
+fn find_replacement<'a>(
+    repos: &'a [Repository],
+    repo: &'a Repository,
+    name: &str,
+) -> Result<Option<(&'a Repository, String)>> {
+    let others = repos.iter().filter(|r| r.id != repo.id);
+
+    for candidate in std::iter::once(repo).chain(others) {
+        if let Some(new_name) = candidate.find_replacement(&repo.id, name)? {
+            return Ok(Some((candidate, new_name)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Every recipe for one of `arches` that provides a virtual package, in repository
+/// order. Recipes that fail to load cannot provide anything and are skipped.
+fn collect_providers<'a>(repos: &'a [Repository], arches: &[String]) -> Result<Vec<Provider<'a>>> {
+    let mut providers = Vec::new();
+
+    for repo in repos {
+        for resolved in repo.recipes()? {
+            if resolved
+                .id
+                .arch
+                .as_ref()
+                .is_some_and(|a| !arches.contains(a))
+            {
+                continue;
+            }
+
+            let Ok(recipe) = repo.load_recipe(&resolved.path) else {
+                continue;
+            };
+
+            if !recipe.provides.is_empty() {
+                providers.push(Provider {
+                    repo,
+                    id: resolved.id,
+                    path: resolved.path,
+                    provides: recipe.provides,
+                });
+            }
+        }
+    }
+
+    Ok(providers)
+}
+
+/// Picks the recipe satisfying a dependency on a virtual package. A provider already
+/// in the closure is preferred, otherwise the first one, looking in the repository
+/// the dependency names first.
+fn select_provider<'a>(
+    providers: &[Provider<'a>],
+    closure: &[ClosureEntry],
+    id: &PackageId,
+) -> Result<(&'a Repository, PackageId, PathBuf)> {
+    let candidates = providers
+        .iter()
+        .filter(|p| p.provides.contains(&id.name))
+        .collect::<Vec<_>>();
+
+    let provider = candidates
+        .iter()
+        .find(|p| {
+            closure
+                .iter()
+                .any(|e| e.id.repo == p.repo.id && e.path == p.path)
+        })
+        .or_else(|| candidates.iter().find(|p| p.repo.id == id.repo))
+        .or_else(|| candidates.first())
+        .ok_or(anyhow!(
+            "Package with name {} could not be found and no package provides it",
+            id.name
+        ))?;
+
+    Ok((provider.repo, provider.id.clone(), provider.path.clone()))
+}
+
+/// Fails if a package in the closure conflicts with another one, by its name or by
+/// a virtual name the other provides
+fn check_conflicts(closure: &[ClosureEntry]) -> Result<()> {
+    for (index, entry) in closure.iter().enumerate() {
+        for conflict in &entry.recipe.conflicts {
+            let other = closure.iter().enumerate().find(|(other_index, other)| {
+                *other_index != index
+                    && (other.id.name == *conflict || other.recipe.provides.contains(conflict))
+            });
+
+            if let Some((_, other)) = other {
+                return Err(anyhow!(
+                    "{} conflicts with {}, they cannot be installed together",
+                    entry.id,
+                    other.id
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
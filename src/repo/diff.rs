@@ -0,0 +1,294 @@
+use serde::Serialize;
+use url::Url;
+
+use crate::{
+    model::{Recipe, RecipeSource},
+    repo::VersionAvailability,
+};
+
+/// What changed between two recipes, usually two versions of the same package
+#[derive(Debug, Serialize)]
+pub struct RecipeDiff {
+    /// Changed metadata fields, such as the version or license
+    pub fields: Vec<FieldChange>,
+    pub depends: ListChange,
+    pub conflicts: ListChange,
+    pub provides: ListChange,
+
+    /// Sources that were added, removed or changed, matched by name or index
+    pub sources: Vec<SourceChange>,
+}
+
+/// A field that has a different value, `None` where it is not set
+#[derive(Debug, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ListChange {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SourceChange {
+    /// Name of the source, or its index when it has none
+    pub reference: String,
+    pub kind: ChangeKind,
+    pub fields: Vec<FieldChange>,
+
+    /// Changes a reviewer should take a closer look at, such as a new download host
+    pub warnings: Vec<String>,
+}
+
+/// A flavour combination and arch a version has a recipe for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Variant {
+    pub flavours: Vec<String>,
+
+    /// Arch of the recipe, `None` for an arch-less recipe
+    pub arch: Option<String>,
+}
+
+/// Recipes one version has and the other does not
+#[derive(Debug, Default, Serialize)]
+pub struct VariantChange {
+    pub added: Vec<Variant>,
+    pub removed: Vec<Variant>,
+}
+
+impl ListChange {
+    fn between(old: &[String], new: &[String]) -> Self {
+        Self {
+            added: new.iter().filter(|n| !old.contains(n)).cloned().collect(),
+            removed: old.iter().filter(|o| !new.contains(o)).cloned().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl RecipeDiff {
+    pub fn between(old: &Recipe, new: &Recipe) -> Self {
+        let mut fields = Vec::new();
+
+        for (field, old, new) in [
+            ("name", &old.name, &new.name),
+            ("version", &old.version, &new.version),
+            ("license", &old.license, &new.license),
+            ("maintainer", &old.maintainer, &new.maintainer),
+        ] {
+            push_change(&mut fields, field, Some(old.clone()), Some(new.clone()));
+        }
+
+        Self {
+            fields,
+            depends: ListChange::between(&old.depends, &new.depends),
+            conflicts: ListChange::between(&old.conflicts, &new.conflicts),
+            provides: ListChange::between(&old.provides, &new.provides),
+            sources: diff_sources(old, new),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+            && self.depends.is_empty()
+            && self.conflicts.is_empty()
+            && self.provides.is_empty()
+            && self.sources.is_empty()
+    }
+}
+
+impl VariantChange {
+    pub fn between(old: &VersionAvailability, new: &VersionAvailability) -> Self {
+        let old = variants(old);
+        let new = variants(new);
+
+        Self {
+            added: new.iter().filter(|n| !old.contains(n)).cloned().collect(),
+            removed: old.iter().filter(|o| !new.contains(o)).cloned().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+fn variants(version: &VersionAvailability) -> Vec<Variant> {
+    let mut variants = Vec::new();
+
+    for variant in &version.variants {
+        if variant.generic.is_some() {
+            variants.push(Variant {
+                flavours: variant.flavours.clone(),
+                arch: None,
+            });
+        }
+
+        for arch in variant.arches.keys() {
+            variants.push(Variant {
+                flavours: variant.flavours.clone(),
+                arch: Some(arch.clone()),
+            });
+        }
+    }
+
+    variants
+}
+
+fn push_change(
+    changes: &mut Vec<FieldChange>,
+    field: &str,
+    old: Option<String>,
+    new: Option<String>,
+) {
+    if old != new {
+        changes.push(FieldChange {
+            field: field.to_string(),
+            old,
+            new,
+        });
+    }
+}
+
+/// The fields of a source that are compared, in the order they are reported
+fn source_fields(source: Option<&RecipeSource>) -> [(&'static str, Option<String>); 6] {
+    [
+        ("url", source.map(|s| s.url.clone())),
+        ("hash", source.and_then(|s| s.hash.clone())),
+        ("sha256", source.and_then(|s| s.sha256.clone())),
+        (
+            "sha256sums",
+            source.and_then(|s| s.sha256sums.as_ref().map(|m| m.url.clone())),
+        ),
+        // Flags are only listed when set, so added sources do not list every default
+        (
+            "mutable",
+            source.filter(|s| s.mutable).map(|_| "true".to_string()),
+        ),
+        (
+            "exact_url",
+            source.filter(|s| s.exact_url).map(|_| "true".to_string()),
+        ),
+    ]
+}
+
+/// Sources are matched by their reference, so a renamed source shows as removed and added
+fn diff_sources(old: &Recipe, new: &Recipe) -> Vec<SourceChange> {
+    let mut references = Vec::new();
+
+    let all_sources = old
+        .sources
+        .iter()
+        .enumerate()
+        .chain(new.sources.iter().enumerate());
+
+    for (index, source) in all_sources {
+        let reference = source.reference(index);
+
+        if !references.contains(&reference) {
+            references.push(reference);
+        }
+    }
+
+    let mut changes = Vec::new();
+
+    for reference in references {
+        let old_source = old.source_by_ref(&reference);
+        let new_source = new.source_by_ref(&reference);
+
+        let kind = match (old_source, new_source) {
+            (None, _) => ChangeKind::Added,
+            (_, None) => ChangeKind::Removed,
+            _ => ChangeKind::Changed,
+        };
+
+        let mut fields = Vec::new();
+
+        for ((field, old_value), (_, new_value)) in source_fields(old_source)
+            .into_iter()
+            .zip(source_fields(new_source))
+        {
+            push_change(&mut fields, field, old_value, new_value);
+        }
+
+        if fields.is_empty() {
+            continue;
+        }
+
+        changes.push(SourceChange {
+            reference,
+            kind,
+            fields,
+            warnings: source_warnings(old_source, new_source),
+        });
+    }
+
+    changes
+}
+
+/// Flags source changes that weaken verification or point somewhere unexpected
+fn source_warnings(old: Option<&RecipeSource>, new: Option<&RecipeSource>) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    let Some(new) = new else {
+        return warnings;
+    };
+
+    if new.hash.is_none() {
+        warnings.push("Source is not pinned to a hash".to_string());
+    }
+
+    let Some(old) = old else {
+        return warnings;
+    };
+
+    if old.url == new.url && old.hash != new.hash && old.hash.is_some() && !new.mutable {
+        warnings.push("Hash changed but the URL did not, the content was replaced".to_string());
+    }
+
+    let old_url = Url::parse(&old.url).ok();
+    let new_url = Url::parse(&new.url).ok();
+
+    if let (Some(old_url), Some(new_url)) = (&old_url, &new_url) {
+        if old_url.host_str() != new_url.host_str() {
+            warnings.push(format!(
+                "Downloaded from a different host, {} instead of {}",
+                new_url.host_str().unwrap_or("-"),
+                old_url.host_str().unwrap_or("-")
+            ));
+        }
+
+        if old_url.scheme() == "https" && new_url.scheme() != "https" {
+            warnings.push("No longer downloaded over https".to_string());
+        }
+    }
+
+    if old.sha256.is_some() && new.sha256.is_none() {
+        warnings.push("The sha256 checksum was removed".to_string());
+    }
+
+    if old.sha256sums.is_some() && new.sha256sums.is_none() {
+        warnings.push("The sha256sums file was removed".to_string());
+    }
+
+    if !old.mutable && new.mutable {
+        warnings.push("Source became mutable".to_string());
+    }
+
+    warnings
+}
@@ -0,0 +1,113 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::repo::{Repository, ResolvedRecipe};
+
+/// Lightweight metadata of every recipe in a repository, for external search tools
+#[derive(Debug, Serialize)]
+pub struct RepositoryIndex {
+    pub repo: String,
+    pub name: String,
+    pub desc: String,
+    pub recipes: Vec<IndexEntry>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum IndexEntry {
+    Ok {
+        id: String,
+        name: String,
+        version: String,
+        license: String,
+        maintainer: String,
+        sources: Vec<String>,
+
+        /// Files the package declares that it installs
+        files: Vec<String>,
+    },
+
+    /// The recipe could not be loaded, it is kept so the index shows what is broken
+    Error { id: String, error: String },
+}
+
+impl RepositoryIndex {
+    /// Reads every recipe of the repository, no sources are downloaded
+    pub fn build(repo: &Repository) -> Result<Self> {
+        let mut recipes = Vec::new();
+
+        for resolved in repo.recipes()? {
+            recipes.push(Self::entry(repo, resolved));
+        }
+
+        Ok(Self {
+            repo: repo.id.clone(),
+            name: repo.name.clone(),
+            desc: repo.desc.clone(),
+            recipes,
+        })
+    }
+
+    fn entry(repo: &Repository, resolved: ResolvedRecipe) -> IndexEntry {
+        let id = resolved.id.to_string();
+
+        match repo.load_recipe(&resolved.path) {
+            Ok(recipe) => IndexEntry::Ok {
+                id,
+                name: recipe.name,
+                version: recipe.version,
+                license: recipe.license,
+                maintainer: recipe.maintainer,
+                sources: recipe.sources.into_iter().map(|s| s.url).collect(),
+                files: recipe.files,
+            },
+            Err(e) => IndexEntry::Error {
+                id,
+                error: e.to_string(),
+            },
+        }
+    }
+}
+
+/// Which recipes reference each source hash, across a set of repositories
+#[derive(Debug, Default)]
+pub struct SourceReferences {
+    by_hash: HashMap<String, Vec<String>>,
+}
+
+impl SourceReferences {
+    /// Reads every recipe of the repositories, recipes that fail to load are skipped
+    pub fn build(repos: &[Repository]) -> Result<Self> {
+        let mut references = Self::default();
+
+        for repo in repos {
+            for resolved in repo.recipes()? {
+                let Ok(recipe) = repo.load_recipe(&resolved.path) else {
+                    continue;
+                };
+
+                let id = resolved.id.to_string();
+
+                for hash in recipe.sources.iter().filter_map(|s| s.hash.as_ref()) {
+                    let referrers = references.by_hash.entry(hash.to_lowercase()).or_default();
+
+                    // A recipe listing the same source twice still counts once
+                    if referrers.last() != Some(&id) {
+                        referrers.push(id.clone());
+                    }
+                }
+            }
+        }
+
+        Ok(references)
+    }
+
+    /// Ids of the recipes referencing a source hash
+    pub fn get(&self, hash: &str) -> &[String] {
+        self.by_hash
+            .get(&hash.to_lowercase())
+            .map(|ids| ids.as_slice())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,20 @@
+mod archive;
+mod changes;
+mod closure;
+mod diff;
+mod index;
+mod repository;
+mod signed_index;
+
+pub use archive::ArchiveTree;
+pub use changes::{ChangedRecipe, RecipeChange, changed_since};
+pub use closure::{ClosureEntry, resolve_closure};
+pub use diff::{
+    ChangeKind, FieldChange, ListChange, RecipeDiff, SourceChange, Variant, VariantChange,
+};
+pub use index::{IndexEntry, RepositoryIndex, SourceReferences};
+pub use repository::{
+    PackageAvailability, Repository, RepositorySummary, ResolvedRecipe, VariantAvailability,
+    VersionAvailability,
+};
+pub use signed_index::SignedIndex;
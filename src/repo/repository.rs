@@ -0,0 +1,1027 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use url::Url;
+
+use crate::{
+    model::{PackageId, PackageQuery, Recipe, is_known_arch},
+    repo::{ArchiveTree, SignedIndex},
+    util::{normalize_path, print_warn, redact_url},
+};
+
+/// A recipe found by [`Repository::resolve_all`], with every id component filled in
+#[derive(Debug)]
+pub struct ResolvedRecipe {
+    pub id: PackageId,
+    pub path: PathBuf,
+}
+
+/// Every version, flavour combination and arch a repository has recipes for, as
+/// returned by [`Repository::find_package`]
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageAvailability {
+    pub name: String,
+    pub versions: Vec<VersionAvailability>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionAvailability {
+    pub version: String,
+
+    /// Flavour combinations with at least one recipe, the unflavoured one first
+    pub variants: Vec<VariantAvailability>,
+}
+
+/// The recipes of one flavour combination of a version
+#[derive(Debug, Clone, Serialize)]
+pub struct VariantAvailability {
+    pub flavours: Vec<String>,
+
+    /// Arch-less recipe, used on any arch without a recipe of its own
+    pub generic: Option<PathBuf>,
+
+    /// Recipes for specific arches, by arch name
+    pub arches: BTreeMap<String, PathBuf>,
+}
+
+impl PackageAvailability {
+    pub fn version(&self, version: &str) -> Option<&VersionAvailability> {
+        self.versions.iter().find(|v| v.version == version)
+    }
+
+    /// The highest version starting with `prefix` at a dot boundary, so `1.2` selects
+    /// the highest of `1.2.0` and `1.2.7` but never `1.20`
+    pub fn version_with_prefix(&self, prefix: &str) -> Option<&VersionAvailability> {
+        let dotted = format!("{}.", prefix.trim_end_matches('.'));
+
+        self.versions
+            .iter()
+            .filter(|v| v.version.starts_with(&dotted))
+            .max_by(|a, b| compare_versions(&a.version, &b.version))
+    }
+
+    /// Number of recipes across all versions and variants
+    pub fn recipe_count(&self) -> usize {
+        self.versions
+            .iter()
+            .flat_map(|v| &v.variants)
+            .map(|v| usize::from(v.generic.is_some()) + v.arches.len())
+            .sum()
+    }
+}
+
+impl VersionAvailability {
+    pub fn variant(&self, flavours: &[String]) -> Option<&VariantAvailability> {
+        self.variants.iter().find(|v| v.flavours == flavours)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RepositorySummary {
+    pub packages: usize,
+    pub versions: usize,
+
+    /// Recipes across all versions, flavours and arches
+    pub variants: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Repository {
+    pub name: String,
+    pub desc: String,
+
+    /// Base that relative source URLs of this repository's recipes are joined onto
+    #[serde(default)]
+    pub base_url: Option<String>,
+
+    /// File name of recipes without extension, `recipe` unless set
+    #[serde(default = "Repository::default_recipe_name")]
+    pub recipe_name: String,
+
+    /// Flavours applied on an arch when a package id names none, e.g. `aarch64: [neon]`
+    #[serde(default)]
+    pub arch_flavours: BTreeMap<String, Vec<String>>,
+
+    #[serde(skip)]
+    pub id: String,
+
+    #[serde(skip)]
+    pub pkgs_dir: PathBuf,
+
+    /// Directory or packed archive the repository was loaded from
+    #[serde(skip)]
+    pub path: PathBuf,
+
+    #[serde(skip)]
+    archive: Option<ArchiveTree>,
+
+    /// Hashes of the recipe files, set once the signature of the index was verified
+    #[serde(skip)]
+    signed_index: Option<SignedIndex>,
+
+    /// Names found to have no package, by when that was found, so walks over large
+    /// dependency graphs do not look for absent packages again
+    #[serde(skip)]
+    missing_packages: Mutex<BTreeMap<String, Instant>>,
+
+    /// Names found to have no package replacing them, which takes reading every recipe
+    #[serde(skip)]
+    missing_replacements: Mutex<BTreeMap<String, Instant>>,
+}
+
+impl Repository {
+    /// Recipe extensions in the order they are tried
+    pub const RECIPE_EXTENSIONS: &[&str] = &["yml", "yaml"];
+
+    /// How long a package that was not found is not looked for again. Packages added
+    /// meanwhile are found once it expired, or once the repository is loaded again.
+    const MISSING_TTL: Duration = Duration::from_secs(60);
+
+    fn default_recipe_name() -> String {
+        "recipe".to_string()
+    }
+
+    /// Path of the recipe in a directory, trying each supported extension
+    pub fn find_recipe(&self, dir: &Path) -> Option<PathBuf> {
+        Self::RECIPE_EXTENSIONS
+            .iter()
+            .map(|ext| dir.join(format!("{}.{ext}", self.recipe_name)))
+            .find(|path| self.is_file(path))
+    }
+
+    /// Arch of a recipe path returned by [`Repository::resolve_package_id`], `None`
+    /// for an arch-less recipe
+    pub fn recipe_arch(path: &Path) -> Option<String> {
+        let arch = path.parent()?.file_name()?.to_string_lossy();
+        is_known_arch(&arch).then(|| arch.to_string())
+    }
+
+    /// Path the recipe of a directory is expected at, for error messages
+    fn expected_recipe_path(&self, dir: &Path) -> PathBuf {
+        dir.join(format!(
+            "{}.{}",
+            self.recipe_name,
+            Self::RECIPE_EXTENSIONS[0]
+        ))
+    }
+
+    /// Whether a path points at a packed repository archive rather than a directory
+    pub fn is_archive_path(path: &Path) -> bool {
+        path.is_file() && path.to_string_lossy().ends_with(ArchiveTree::EXTENSION)
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        if Self::is_archive_path(path) {
+            return Self::from_archive(path);
+        }
+
+        let repo_meta = path.join("repo.yml");
+
+        if repo_meta.is_file() {
+            let repo_s = std::fs::read_to_string(repo_meta)?;
+            let mut repo: Self = serde_yaml::from_str(&repo_s)?;
+
+            repo.id = Self::path_id(path)?;
+
+            repo.pkgs_dir = path.join("pkgs");
+            repo.path = path.to_path_buf();
+
+            return Ok(repo);
+        }
+
+        Err(anyhow!(
+            "Failed to load repository {path:#?}, no repository metadata found."
+        ))
+    }
+
+    /// Id of the repository at `path`, its directory name or the archive name without
+    /// the archive extension
+    pub fn path_id(path: &Path) -> Result<String> {
+        let file_name = path
+            .file_name()
+            .ok_or(anyhow!("Failed to unwrap repository path name"))?
+            .to_string_lossy();
+
+        let id = Self::is_archive_path(path)
+            .then(|| file_name.strip_suffix(ArchiveTree::EXTENSION))
+            .flatten();
+
+        Ok(id.unwrap_or(&file_name).to_string())
+    }
+
+    /// Loads a read-only repository packed into a single archive. The archive holds
+    /// the same layout as a repository directory, with `repo.yml` at its root.
+    fn from_archive(path: &Path) -> Result<Self> {
+        let archive = ArchiveTree::from_path(path)
+            .map_err(|e| anyhow!("Failed to read repository archive {path:#?}, {e}"))?;
+
+        let repo_s = archive.read_to_string(Path::new("repo.yml")).map_err(|_| {
+            anyhow!("Failed to load repository {path:#?}, no repository metadata found.")
+        })?;
+        let mut repo: Self = serde_yaml::from_str(&repo_s)?;
+
+        repo.id = Self::path_id(path)?;
+
+        repo.pkgs_dir = path.join("pkgs");
+        repo.path = path.to_path_buf();
+        repo.archive = Some(archive);
+
+        Ok(repo)
+    }
+
+    /// Verifies the signed recipe index of the repository with its public key. Every
+    /// recipe file loaded afterwards must match its hash in the index, as must repo.yml.
+    pub fn verify_signature(&mut self, key: &[u8; 32]) -> Result<()> {
+        let index_path = self.path.join(SignedIndex::FILE_NAME);
+        let signature_path = self.path.join(SignedIndex::SIGNATURE_FILE_NAME);
+
+        if !self.is_file(&index_path) || !self.is_file(&signature_path) {
+            return Err(anyhow!(
+                "Repository {} has a key configured, but no signed {}",
+                self.id,
+                SignedIndex::FILE_NAME
+            ));
+        }
+
+        let index = SignedIndex::verify(
+            &self.read_to_string(&index_path)?,
+            &self.read_to_string(&signature_path)?,
+            key,
+        )
+        .map_err(|e| anyhow!("Failed to verify repository {}, {e}", self.id))?;
+
+        let repo_meta = self.read_to_string(&self.path.join("repo.yml"))?;
+        index
+            .check(Path::new("repo.yml"), repo_meta.as_bytes())
+            .map_err(|e| anyhow!("Failed to verify repository {}, {e}", self.id))?;
+
+        self.signed_index = Some(index);
+        Ok(())
+    }
+
+    /// Whether the recipes of the repository are checked against a verified signed index
+    pub fn is_verified(&self) -> bool {
+        self.signed_index.is_some()
+    }
+
+    /// Whether the repository ships a signed index, verified or not
+    pub fn is_signed(&self) -> bool {
+        self.is_file(&self.path.join(SignedIndex::SIGNATURE_FILE_NAME))
+    }
+
+    /// Reads a recipe file, refusing it if it does not match the verified signed index
+    fn read_recipe_file(&self, path: &Path) -> Result<String> {
+        let contents = self.read_to_string(path)?;
+
+        let Some(index) = &self.signed_index else {
+            return Ok(contents);
+        };
+
+        let path = normalize_path(path);
+        let relative = path.strip_prefix(normalize_path(&self.path)).map_err(|_| {
+            anyhow!(
+                "Recipe file {path:#?} is outside of signed repository {}",
+                self.id
+            )
+        })?;
+
+        index
+            .check(relative, contents.as_bytes())
+            .map_err(|e| anyhow!("Refusing recipe of repository {}, {e}", self.id))?;
+
+        Ok(contents)
+    }
+
+    /// Maps a path below a packed repository onto its path inside the archive
+    fn archive_entry<'a>(&'a self, path: &Path) -> Option<(&'a ArchiveTree, PathBuf)> {
+        let archive = self.archive.as_ref()?;
+        let entry = normalize_path(path).strip_prefix(&self.path).ok()?.to_path_buf();
+        Some((archive, entry))
+    }
+
+    pub fn is_dir(&self, path: &Path) -> bool {
+        if self.archive.is_none() {
+            return path.is_dir();
+        }
+
+        self.archive_entry(path)
+            .is_some_and(|(archive, entry)| archive.is_dir(&entry))
+    }
+
+    pub fn is_file(&self, path: &Path) -> bool {
+        if self.archive.is_none() {
+            return path.is_file();
+        }
+
+        self.archive_entry(path)
+            .is_some_and(|(archive, entry)| archive.is_file(&entry))
+    }
+
+    pub fn read_to_string(&self, path: &Path) -> Result<String> {
+        if self.archive.is_none() {
+            return Ok(std::fs::read_to_string(path)?);
+        }
+
+        let (archive, entry) = self.archive_entry(path).ok_or(anyhow!(
+            "{path:#?} is outside of packed repository {}",
+            self.id
+        ))?;
+
+        archive.read_to_string(&entry)
+    }
+
+    /// Names of the directories directly below `path`, sorted
+    pub fn child_dirs(&self, path: &Path) -> Result<Vec<String>> {
+        let mut names = match self.archive_entry(path) {
+            Some((archive, entry)) => archive.child_dirs(&entry),
+            None if self.archive.is_some() => Vec::new(),
+            None => {
+                let mut names = Vec::new();
+                for entry in std::fs::read_dir(path)? {
+                    let entry = entry?;
+                    if entry.file_type()?.is_dir() {
+                        names.push(entry.file_name().to_string_lossy().to_string());
+                    }
+                }
+                names
+            }
+        };
+
+        names.sort();
+        Ok(names)
+    }
+
+    /// Names of all packages, found in the first-letter directories below `pkgs`
+    pub fn package_names(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+
+        if !self.is_dir(&self.pkgs_dir) {
+            return Ok(names);
+        }
+
+        for prefix in self.child_dirs(&self.pkgs_dir)? {
+            for name in self.child_dirs(&self.pkgs_dir.join(&prefix))? {
+                // Packages outside of their first-letter directory cannot be resolved
+                if name.starts_with(prefix.as_str()) {
+                    names.push(name);
+                }
+            }
+        }
+
+        Ok(names)
+    }
+
+    /// Counts packages, versions and recipe variants from the directory layout
+    /// alone, without reading any recipe
+    pub fn summary(&self) -> Result<RepositorySummary> {
+        let mut summary = RepositorySummary::default();
+
+        for name in self.package_names()? {
+            let Some(package) = self.find_package(&name)? else {
+                continue;
+            };
+
+            summary.packages += 1;
+            summary.versions += package.versions.len();
+            summary.variants += package.recipe_count();
+        }
+
+        Ok(summary)
+    }
+
+    /// Every recipe of the repository, across all packages, versions and variants
+    pub fn recipes(&self) -> Result<Vec<ResolvedRecipe>> {
+        let mut recipes = Vec::new();
+
+        for name in self.package_names()? {
+            let query = PackageQuery {
+                repo: self.id.clone(),
+                name,
+                version: None,
+                flavours: None,
+                arch: None,
+                capabilities: Vec::new(),
+            };
+
+            recipes.extend(self.resolve_all(&query)?);
+        }
+
+        Ok(recipes)
+    }
+
+    /// Directory of a package, inside the directory named after its first letter
+    pub(crate) fn package_path(&self, name: &str) -> PathBuf {
+        let prefix = name.chars().next().map(String::from).unwrap_or_default();
+        self.pkgs_dir.join(prefix).join(name)
+    }
+
+    /// Lists the versions, flavour combinations and arches a package is available
+    /// in, or `None` if the repository has no package of that name
+    pub fn find_package(&self, name: &str) -> Result<Option<PackageAvailability>> {
+        let package_path = self.package_path(name);
+
+        if name.is_empty() || Self::is_known_missing(&self.missing_packages, name) {
+            return Ok(None);
+        }
+
+        if !self.is_dir(&package_path) {
+            Self::remember_missing(&self.missing_packages, name);
+            return Ok(None);
+        }
+
+        let mut versions = Vec::new();
+
+        for version in self.child_dirs(&package_path)? {
+            let mut variants = Vec::new();
+            self.collect_variants(&package_path.join(&version), Vec::new(), &mut variants)?;
+
+            versions.push(VersionAvailability { version, variants });
+        }
+
+        Ok(Some(PackageAvailability {
+            name: name.to_string(),
+            versions,
+        }))
+    }
+
+    /// Collects the variant of a flavour directory, then those of its flavour subdirectories
+    fn collect_variants(
+        &self,
+        dir: &Path,
+        flavours: Vec<String>,
+        variants: &mut Vec<VariantAvailability>,
+    ) -> Result<()> {
+        let mut variant = VariantAvailability {
+            flavours,
+            generic: self.find_recipe(dir),
+            arches: BTreeMap::new(),
+        };
+
+        let mut flavour_dirs = Vec::new();
+
+        for child in self.child_dirs(dir)? {
+            let child_path = dir.join(&child);
+
+            if !is_known_arch(&child) {
+                flavour_dirs.push(child);
+            } else if let Some(recipe_path) = self.find_recipe(&child_path) {
+                variant.arches.insert(child, recipe_path);
+            }
+        }
+
+        let flavours = variant.flavours.clone();
+
+        if variant.generic.is_some() || !variant.arches.is_empty() {
+            variants.push(variant);
+        }
+
+        for flavour in flavour_dirs {
+            let mut child_flavours = flavours.clone();
+            child_flavours.push(flavour.clone());
+            self.collect_variants(&dir.join(flavour), child_flavours, variants)?;
+        }
+
+        Ok(())
+    }
+
+    /// Every recipe that declares it installs the file at `path`. Every recipe has to be
+    /// read, recipes that fail to load are skipped.
+    pub fn find_file_providers(&self, path: &Path) -> Result<Vec<ResolvedRecipe>> {
+        let path = normalize_path(path);
+        let mut providers = Vec::new();
+
+        for resolved in self.recipes()? {
+            let Ok(recipe) = self.load_recipe(&resolved.path) else {
+                continue;
+            };
+
+            if recipe
+                .files
+                .iter()
+                .any(|file| normalize_path(Path::new(file)) == path)
+            {
+                providers.push(resolved);
+            }
+        }
+
+        Ok(providers)
+    }
+
+    /// Name of the package whose recipes declare that they replace `name` of the
+    /// repository `repo_id`, which recipes of another repository have to qualify with it.
+    /// Only looked up for names without a package, as every recipe has to be read.
+    pub fn find_replacement(&self, repo_id: &str, name: &str) -> Result<Option<String>> {
+        let qualified = format!("{repo_id}/{name}");
+
+        if Self::is_known_missing(&self.missing_replacements, &qualified) {
+            return Ok(None);
+        }
+
+        let own = repo_id == self.id;
+
+        for resolved in self.recipes()? {
+            let Ok(recipe) = self.load_recipe(&resolved.path) else {
+                continue;
+            };
+
+            if recipe
+                .replaces
+                .iter()
+                .any(|r| *r == qualified || (own && *r == name))
+            {
+                return Ok(Some(resolved.id.name));
+            }
+        }
+
+        Self::remember_missing(&self.missing_replacements, &qualified);
+        Ok(None)
+    }
+
+    fn is_known_missing(missing: &Mutex<BTreeMap<String, Instant>>, name: &str) -> bool {
+        let Ok(missing) = missing.lock() else {
+            return false;
+        };
+
+        missing
+            .get(name)
+            .is_some_and(|found| found.elapsed() < Self::MISSING_TTL)
+    }
+
+    fn remember_missing(missing: &Mutex<BTreeMap<String, Instant>>, name: &str) {
+        if let Ok(mut missing) = missing.lock() {
+            missing.insert(name.to_string(), Instant::now());
+        }
+    }
+
+    /// Points the base URL at another host, so every source relative to it is fetched
+    /// from that mirror. Returns the rewritten base URL if the repository has one.
+    pub fn pin_mirror(&mut self, host: &str) -> Result<Option<String>> {
+        let Some(base_url) = &self.base_url else {
+            return Ok(None);
+        };
+
+        let shown = redact_url(base_url);
+        let mut url = Url::parse(base_url).map_err(|e| anyhow!("Invalid base_url {shown}, {e}"))?;
+
+        let mirror = Url::parse(&format!("{}://{host}", url.scheme()))
+            .map_err(|e| anyhow!("Invalid mirror host {host}, {e}"))?;
+
+        url.set_host(mirror.host_str())
+            .map_err(|e| anyhow!("Cannot use mirror host {host} for {shown}, {e}"))?;
+        url.set_port(mirror.port())
+            .map_err(|_| anyhow!("Cannot use mirror host {host} for {shown}"))?;
+
+        self.base_url = Some(url.to_string());
+        Ok(self.base_url.clone())
+    }
+
+    pub fn load_recipe(&self, path: &Path) -> Result<Recipe> {
+        let mut recipe = Recipe::load(path, |p| self.read_recipe_file(p))?;
+        recipe
+            .resolve_source_urls(self.base_url.as_deref())
+            .map_err(|e| anyhow!("Failed to load recipe {path:#?} in repository {}, {e}", self.id))?;
+        Ok(recipe)
+    }
+
+    /// Flavours [`Repository::resolve_package_id`] applies to a package id that names
+    /// none, from the repository's defaults for the arch it resolves for. Defaults the
+    /// package has no recipes for are skipped.
+    pub fn default_flavours(
+        &self,
+        package_id: &PackageId,
+        arches: &[String],
+    ) -> Result<Option<Vec<String>>> {
+        let Some(package) = self.find_package(&package_id.name)? else {
+            return Ok(None);
+        };
+
+        let flavours = package
+            .version(&package_id.version)
+            .and_then(|v| self.arch_default_flavours(package_id, v, arches));
+
+        Ok(flavours.cloned())
+    }
+
+    fn arch_default_flavours<'a>(
+        &'a self,
+        package_id: &PackageId,
+        version: &VersionAvailability,
+        arches: &[String],
+    ) -> Option<&'a Vec<String>> {
+        if !package_id.flavours.is_empty() || !package_id.capabilities.is_empty() {
+            return None;
+        }
+
+        let arch = package_id.arch.as_ref().or(arches.first())?;
+        let flavours = self.arch_flavours.get(arch)?;
+
+        version
+            .variant(flavours)
+            .filter(|v| v.generic.is_some() || v.arches.contains_key(arch))
+            .map(|_| flavours)
+    }
+
+    /// Arches an arch-less recipe declares it is shared by, empty if it applies to any
+    fn shared_arches(&self, recipe_path: &Path) -> Result<Vec<String>> {
+        Ok(self.load_recipe(recipe_path)?.arches)
+    }
+
+    /// Names of the flavours of a variant and the capabilities its recipe declares
+    fn capabilities(&self, flavours: &[String], recipe_path: &Path) -> Result<Vec<String>> {
+        let mut capabilities = flavours.to_vec();
+        capabilities.extend(self.load_recipe(recipe_path)?.capabilities);
+        Ok(capabilities)
+    }
+
+    /// Recipe of a variant that [`Repository::resolve_package_id`] would pick for the arch
+    fn variant_recipe<'a>(
+        variant: &'a VariantAvailability,
+        arch: Option<&str>,
+        arches: &[String],
+        exact: bool,
+    ) -> Option<&'a PathBuf> {
+        match (arch, exact) {
+            (Some(arch), _) => variant.arches.get(arch),
+            (None, true) => variant.generic.as_ref(),
+            (None, false) => arches
+                .iter()
+                .find_map(|a| variant.arches.get(a))
+                .or(variant.generic.as_ref()),
+        }
+    }
+
+    /// Flavours of the one variant whose capabilities match those asked for by the
+    /// package id. No match, or several matches, is an error listing the candidates.
+    fn capability_flavours(
+        &self,
+        package_id: &PackageId,
+        version: &VersionAvailability,
+        arches: &[String],
+        exact: bool,
+    ) -> Result<Vec<String>> {
+        let label = |flavours: &[String], capabilities: &[String]| {
+            let flavours = if flavours.is_empty() {
+                "(none)".to_string()
+            } else {
+                flavours.join(":")
+            };
+            let capabilities = if capabilities.is_empty() {
+                "none".to_string()
+            } else {
+                capabilities.join(", ")
+            };
+            format!("{flavours} [{capabilities}]")
+        };
+
+        let mut available = Vec::new();
+        let mut matching = Vec::new();
+
+        for variant in &version.variants {
+            if !package_id.flavours.is_empty() && variant.flavours != package_id.flavours {
+                continue;
+            }
+
+            let Some(recipe_path) =
+                Self::variant_recipe(variant, package_id.arch.as_deref(), arches, exact)
+            else {
+                continue;
+            };
+
+            let capabilities = self.capabilities(&variant.flavours, recipe_path)?;
+            let matches = package_id
+                .capabilities
+                .iter()
+                .all(|c| c.matches(&capabilities));
+
+            available.push(label(&variant.flavours, &capabilities));
+
+            if matches {
+                matching.push((variant.flavours.clone(), capabilities));
+            }
+        }
+
+        let wanted = package_id
+            .capabilities
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<String>();
+
+        match matching.len() {
+            1 => Ok(matching.remove(0).0),
+            0 => Err(anyhow!(
+                "No flavour combination matches {wanted}, available: {}.",
+                if available.is_empty() {
+                    "none".to_string()
+                } else {
+                    available.join(", ")
+                }
+            )),
+            _ => Err(anyhow!(
+                "Several flavour combinations match {wanted}, add capabilities or name the flavours: {}.",
+                matching
+                    .iter()
+                    .map(|(flavours, capabilities)| label(flavours, capabilities))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+
+    /// Finds the recipe of a package id. An id without an arch resolves to the recipe
+    /// of the most preferred of `arches` the package has one for, then to the arch-less
+    /// recipe. A version that does not exist resolves to the highest version it is a
+    /// prefix of, unless `exact` is set.
+    pub fn resolve_package_id(
+        &self,
+        package_id: &PackageId,
+        arches: &[String],
+        exact: bool,
+    ) -> Result<PathBuf> {
+        if package_id.name.is_empty() {
+            return Err(anyhow!("Package name was empty"));
+        }
+
+        let Some(package) = self.find_package(&package_id.name)? else {
+            let Some(new_name) = self.find_replacement(&self.id, &package_id.name)? else {
+                return Err(anyhow!(
+                    "Package with name {} could not be found.",
+                    &package_id.name
+                ));
+            };
+
+            let mut new_id = package_id.clone();
+            new_id.name = new_name;
+
+            print_warn(&format!(
+                "Package {} was renamed, resolving {new_id} instead",
+                package_id.name
+            ));
+
+            return self.resolve_package_id(&new_id, arches, exact);
+        };
+
+        let Some(version) = package.version(&package_id.version) else {
+            // A partial version is a fallback like the arch ones, exact mode disables it
+            if !exact && let Some(version) = package.version_with_prefix(&package_id.version) {
+                let mut new_id = package_id.clone();
+                new_id.version = version.version.clone();
+
+                log::info!(
+                    "Version {} of {} resolved to {}",
+                    package_id.version,
+                    package_id.name,
+                    new_id.version
+                );
+
+                return self.resolve_package_id(&new_id, arches, exact);
+            }
+
+            let available = package
+                .versions
+                .iter()
+                .map(|v| v.version.as_str())
+                .collect::<Vec<_>>();
+
+            return Err(anyhow!(
+                "Package version {} does not exist, available versions: {}.",
+                &package_id.version,
+                available.join(", ")
+            ));
+        };
+
+        let capability_flavours = if package_id.capabilities.is_empty() {
+            None
+        } else {
+            Some(self.capability_flavours(package_id, version, arches, exact)?)
+        };
+
+        let default_flavours = if exact {
+            None
+        } else {
+            self.arch_default_flavours(package_id, version, arches)
+        };
+        let flavours = capability_flavours
+            .as_ref()
+            .or(default_flavours)
+            .unwrap_or(&package_id.flavours);
+
+        let mut recipe_path = self
+            .package_path(&package_id.name)
+            .join(&package_id.version);
+
+        for flavour in flavours {
+            recipe_path.push(flavour);
+        }
+
+        if !self.is_dir(&recipe_path) {
+            let available = version
+                .variants
+                .iter()
+                .map(|v| {
+                    if v.flavours.is_empty() {
+                        "(none)".to_string()
+                    } else {
+                        v.flavours.join(":")
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            return Err(anyhow!(
+                "Specified package flavour combination does not exist, available flavours: {}.",
+                available.join(", ")
+            ));
+        }
+
+        let generic = self.find_recipe(&recipe_path);
+        let shared_arches = || match &generic {
+            Some(path) => self.shared_arches(path),
+            None => Ok(Vec::new()),
+        };
+
+        if let Some(arch) = &package_id.arch {
+            let arch_dir = recipe_path.join(arch);
+
+            if let Some(path_with_arch) = self.find_recipe(&arch_dir) {
+                return Ok(path_with_arch);
+            }
+
+            let shared_arches = shared_arches()?;
+
+            if let Some(generic) = generic.filter(|_| shared_arches.contains(arch)) {
+                // Declared as shared by the arch, so it is as exact as a copy in its directory
+                return Ok(generic);
+            } else if exact {
+                let path_with_arch = self.expected_recipe_path(&arch_dir);
+                return Err(anyhow!(
+                    "Package architecure was set to {arch}, but exact recipe {path_with_arch:#?} does not exist."
+                ));
+            } else {
+                let mut available = version
+                    .variant(flavours)
+                    .map(|v| v.arches.keys().cloned().collect::<Vec<_>>())
+                    .unwrap_or_default();
+
+                for arch in shared_arches {
+                    if !available.contains(&arch) {
+                        available.push(arch);
+                    }
+                }
+
+                return Err(anyhow!(
+                    "Package architecure was set to {arch}, but package does not supply it, available arches: {}.",
+                    if available.is_empty() {
+                        "none".to_string()
+                    } else {
+                        available.join(", ")
+                    }
+                ));
+            }
+        }
+
+        if exact {
+            // Exact mode: no arch was requested, so only the arch-less recipe matches
+            if let Some(exact_path) = generic {
+                return Ok(exact_path);
+            }
+
+            let exact_path = self.expected_recipe_path(&recipe_path);
+            return Err(anyhow!(
+                "Exact recipe {exact_path:#?} does not exist, default architecture fallback is disabled."
+            ));
+        }
+
+        for arch in arches {
+            if let Some(path_with_arch) = self.find_recipe(&recipe_path.join(arch)) {
+                return Ok(path_with_arch);
+            }
+        }
+
+        if let Some(path_with_recipe) = &generic {
+            let shared_arches = shared_arches()?;
+
+            if !shared_arches.is_empty() && !arches.iter().any(|a| shared_arches.contains(a)) {
+                return Err(anyhow!(
+                    "Package recipe is only shared by arches {}, none of which is preferred: {}.",
+                    shared_arches.join(", "),
+                    arches.join(", ")
+                ));
+            }
+
+            return Ok(path_with_recipe.clone());
+        }
+
+        Err(anyhow!("Package recipe could not be found."))
+    }
+
+    /// Finds every recipe matching a partial package id. Omitted versions, flavours
+    /// and arches match any value, arch-less recipes only match when no arch is given or
+    /// it is one they are shared by without a recipe of its own.
+    pub fn resolve_all(&self, query: &PackageQuery) -> Result<Vec<ResolvedRecipe>> {
+        if query.name.is_empty() {
+            return Err(anyhow!("Package name was empty"));
+        }
+
+        let package = self.find_package(&query.name)?.ok_or(anyhow!(
+            "Package with name {} could not be found.",
+            &query.name
+        ))?;
+
+        let mut resolved = Vec::new();
+
+        for version in &package.versions {
+            if query
+                .version
+                .as_ref()
+                .is_some_and(|v| *v != version.version)
+            {
+                continue;
+            }
+
+            for variant in &version.variants {
+                if !query.matches_flavours(&variant.flavours) {
+                    continue;
+                }
+
+                let id = |arch: Option<&str>| PackageId {
+                    repo: self.id.clone(),
+                    name: query.name.clone(),
+                    version: version.version.clone(),
+                    flavours: variant.flavours.clone(),
+                    arch: arch.map(|a| a.to_string()),
+                    capabilities: Vec::new(),
+                };
+
+                // Capabilities are declared in recipes, only read when asked for
+                let capable = |recipe_path: &Path| -> Result<bool> {
+                    if query.capabilities.is_empty() {
+                        return Ok(true);
+                    }
+
+                    let capabilities = self.capabilities(&variant.flavours, recipe_path)?;
+                    Ok(query.matches_capabilities(&capabilities))
+                };
+
+                if let Some(recipe_path) = &variant.generic {
+                    // An arch only matches a shared recipe if it has none of its own
+                    let arch = match &query.arch {
+                        None => Some(None),
+                        Some(arch) if variant.arches.contains_key(arch) => None,
+                        Some(arch) => self
+                            .shared_arches(recipe_path)?
+                            .contains(arch)
+                            .then_some(Some(arch.as_str())),
+                    };
+
+                    if let Some(arch) = arch
+                        && capable(recipe_path)?
+                    {
+                        resolved.push(ResolvedRecipe {
+                            id: id(arch),
+                            path: recipe_path.clone(),
+                        });
+                    }
+                }
+
+                for (arch, recipe_path) in &variant.arches {
+                    if query.matches_arch(Some(arch)) && capable(recipe_path)? {
+                        resolved.push(ResolvedRecipe {
+                            id: id(Some(arch)),
+                            path: recipe_path.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(resolved)
+    }
+}
+
+/// Orders versions by their dot separated components, comparing numeric components as
+/// numbers and others as text. A version with more components is higher if the shared
+/// ones are equal, e.g. `1.2.10` > `1.2.9` > `1.2`.
+fn compare_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    let mut a_parts = a.split('.');
+    let mut b_parts = b.split('.');
+
+    loop {
+        let ordering = match (a_parts.next(), b_parts.next()) {
+            (None, None) => return std::cmp::Ordering::Equal,
+            (Some(_), None) => return std::cmp::Ordering::Greater,
+            (None, Some(_)) => return std::cmp::Ordering::Less,
+            (Some(a), Some(b)) => match (a.parse::<u64>(), b.parse::<u64>()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                _ => a.cmp(b),
+            },
+        };
+
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use anyhow::{Result, anyhow};
+use ring::signature::{ED25519, UnparsedPublicKey};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    model::Recipe,
+    repo::Repository,
+    util::{decode_hex, normalize_path},
+};
+
+/// Blake3 hashes of the recipe files of a repository, from its `recipes.index`. The
+/// index lists a `<hash>  <path>` line per file, relative to the repository root, and
+/// is signed with the repository's Ed25519 key in `recipes.index.sig`, hex encoded.
+#[derive(Debug, Default)]
+pub struct SignedIndex {
+    hashes: BTreeMap<PathBuf, blake3::Hash>,
+}
+
+impl SignedIndex {
+    pub const FILE_NAME: &str = "recipes.index";
+    pub const SIGNATURE_FILE_NAME: &str = "recipes.index.sig";
+
+    /// Checks the signature of an index with the repository's public key, then reads it
+    pub fn verify(index: &str, signature: &str, key: &[u8; 32]) -> Result<Self> {
+        let signature = decode_hex(signature.trim())
+            .map_err(|e| anyhow!("Invalid {}, {e}", Self::SIGNATURE_FILE_NAME))?;
+
+        UnparsedPublicKey::new(&ED25519, key)
+            .verify(index.as_bytes(), &signature)
+            .map_err(|_| {
+                anyhow!(
+                    "Signature of {} does not match the configured key",
+                    Self::FILE_NAME
+                )
+            })?;
+
+        Self::parse(index)
+    }
+
+    fn parse(index: &str) -> Result<Self> {
+        let mut hashes = BTreeMap::new();
+
+        for (number, line) in index.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (hash, path) = line.split_once(char::is_whitespace).ok_or(anyhow!(
+                "Line {} of {} is not a hash and a path",
+                number + 1,
+                Self::FILE_NAME
+            ))?;
+
+            let hash = blake3::Hash::from_hex(hash).map_err(|e| {
+                anyhow!(
+                    "Line {} of {} has an invalid hash, {e}",
+                    number + 1,
+                    Self::FILE_NAME
+                )
+            })?;
+
+            hashes.insert(normalize_path(Path::new(path.trim())), hash);
+        }
+
+        Ok(Self { hashes })
+    }
+
+    /// Checks a file of the repository against its hash in the index, `path` is
+    /// relative to the repository root
+    pub fn check(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        let expected = self.hashes.get(path).ok_or(anyhow!(
+            "{path:#?} is not listed in the signed {}",
+            Self::FILE_NAME
+        ))?;
+
+        let hash = blake3::hash(contents);
+        if hash != *expected {
+            return Err(anyhow!(
+                "{path:#?} has hash {hash}, but the signed {} lists {expected}, it may have been tampered with",
+                Self::FILE_NAME
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Writes the index of a repository directory for its maintainer to sign, listing
+    /// `repo.yml` and every recipe file below `pkgs`, including the bases they extend and
+    /// the sidecar files they list sources in
+    pub fn generate(repo_path: &Path) -> Result<String> {
+        if !repo_path.join("repo.yml").is_file() {
+            return Err(anyhow!(
+                "{repo_path:#?} is not a repository directory, it has no repo.yml"
+            ));
+        }
+
+        let mut files = vec![PathBuf::from("repo.yml")];
+        Self::collect_recipe_files(repo_path, Path::new("pkgs"), &mut files)?;
+        files.sort();
+
+        let mut index = String::new();
+
+        for file in files {
+            let hash = blake3::hash(&std::fs::read(repo_path.join(&file))?);
+            index.push_str(&format!("{hash}  {}\n", file.display()));
+        }
+
+        Ok(index)
+    }
+
+    fn collect_recipe_files(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        let full_dir = root.join(dir);
+        if !full_dir.is_dir() {
+            return Ok(());
+        }
+
+        for entry in std::fs::read_dir(&full_dir)? {
+            let entry = entry?;
+            let path = dir.join(entry.file_name());
+
+            if entry.file_type()?.is_dir() {
+                Self::collect_recipe_files(root, &path, files)?;
+                continue;
+            }
+
+            let is_recipe = path.extension().is_some_and(|ext| {
+                Repository::RECIPE_EXTENSIONS
+                    .iter()
+                    .chain(Recipe::SIDECAR_EXTENSIONS)
+                    .any(|e| ext == *e)
+            });
+
+            if is_recipe {
+                files.push(path);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,398 @@
+use anyhow::{Result, anyhow};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::Config,
+    repo::Repository,
+    store::Cache,
+    util::{UserDirs, expand_path},
+};
+
+/// Version of the on-disk layout of the root that this tetra reads and writes
+pub const LAYOUT_VERSION: u32 = 1;
+
+/// Layout of roots from before the layout was versioned, with every cache blob in the
+/// prefix directory of its name
+const UNVERSIONED_LAYOUT: u32 = 1;
+
+#[derive(Debug)]
+pub struct TetraRoot {
+    pub root: PathBuf,
+
+    /// Repository search directories in order of precedence, `<root>/repo` when empty
+    pub repo_dirs: Vec<PathBuf>,
+
+    /// Cache location when relocated away from the root
+    pub cache_dir: Option<PathBuf>,
+
+    /// Namespace key of the cache, unkeyed when not set
+    pub cache_key: Option<[u8; 32]>,
+
+    /// Whether new cache blobs are stored compressed
+    pub compress_cache: bool,
+
+    /// Permissions of cache files and directories, left to the umask when not set
+    pub cache_file_mode: Option<u32>,
+    pub cache_dir_mode: Option<u32>,
+
+    /// Whether the sources of each recipe are cached in a namespace of their own
+    pub pin_cache_to_recipe: bool,
+
+    /// Size budget of the cache blobs, in bytes
+    pub max_cache_size: Option<u64>,
+
+    /// Public keys the signed recipe indexes of repositories are verified with, by id
+    pub repo_keys: BTreeMap<String, [u8; 32]>,
+
+    /// Default architecture given on the command line
+    pub arch: Option<String>,
+
+    /// Directories of a per-user install, when tetra is not used system-wide
+    pub user_dirs: Option<UserDirs>,
+}
+
+/// What upgrading a root to the current layout changed, or would change in a dry run
+#[derive(Debug, Default)]
+pub struct Migration {
+    /// Layout version the root had before
+    pub from: u32,
+}
+
+/// Where the default architecture was taken from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchSource {
+    Flag,
+    Env,
+    RootFile(PathBuf),
+    UserFile(PathBuf),
+    SystemFile(PathBuf),
+    Detected,
+}
+
+impl fmt::Display for ArchSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Flag => write!(f, "--arch"),
+            Self::Env => write!(f, "TETRA_ARCH"),
+            Self::RootFile(path) | Self::UserFile(path) | Self::SystemFile(path) => {
+                write!(f, "{}", path.display())
+            }
+            Self::Detected => write!(f, "the running system"),
+        }
+    }
+}
+
+impl TetraRoot {
+    const DEFAULT_TETRA_ROOT: &str = "/var/tetra";
+    const SYSTEM_ARCH_PATH: &str = "/etc/tetra/arch";
+
+    /// Picks the root by precedence: an explicit root, the `TETRA_ROOT` environment
+    /// variable, the configured root and finally `/var/tetra`, or the user data directory
+    /// of a per-user install. Returns `None` for the user data directory.
+    fn get_tetra_root(explicit: Option<&Path>, config: &Config) -> Result<Option<PathBuf>> {
+        let root = match (explicit, std::env::var("TETRA_ROOT"), &config.root) {
+            (Some(root), _, _) => root.to_string_lossy().to_string(),
+            (None, Ok(root), _) => root,
+            (None, Err(_), Some(root)) => root.to_string_lossy().to_string(),
+            (None, Err(_), None) => return Ok(None),
+        };
+
+        expand_path(&root).map(Some)
+    }
+
+    /// A per-user install splits the root into the XDG data and cache directories, unless
+    /// a root is chosen explicitly. That root is then used whole, as it is system-wide.
+    pub fn new(
+        explicit: Option<&Path>,
+        config: &Config,
+        user_dirs: Option<UserDirs>,
+    ) -> Result<Self> {
+        let (root, cache_dir) = match (Self::get_tetra_root(explicit, config)?, &user_dirs) {
+            (Some(root), _) => (root, None),
+            (None, Some(dirs)) => (dirs.data.clone(), Some(dirs.cache.clone())),
+            (None, None) => (PathBuf::from(Self::DEFAULT_TETRA_ROOT), None),
+        };
+
+        Ok(Self {
+            root,
+            repo_dirs: Vec::new(),
+            cache_dir,
+            cache_key: None,
+            compress_cache: config.compress_cache,
+            cache_file_mode: None,
+            cache_dir_mode: None,
+            pin_cache_to_recipe: config.pin_cache_to_recipe,
+            max_cache_size: config.max_cache_size,
+            repo_keys: BTreeMap::new(),
+            arch: None,
+            user_dirs,
+        })
+    }
+
+    pub fn get_repo_dirs(&self) -> Vec<PathBuf> {
+        if self.repo_dirs.is_empty() {
+            vec![self.root.join("repo")]
+        } else {
+            self.repo_dirs.clone()
+        }
+    }
+
+    /// Ids and paths of every repository in the repository directories, in order of
+    /// precedence. Nothing is loaded, shadowed repositories are included.
+    fn repo_paths(&self) -> Result<Vec<(String, PathBuf)>> {
+        let mut repo_paths = Vec::new();
+
+        for repo_dir in self.get_repo_dirs() {
+            let paths = std::fs::read_dir(&repo_dir)
+                .map_err(|e| anyhow!("Failed to read repository directory {repo_dir:#?}, {e}"))?;
+
+            for path in paths {
+                let path = path?.path();
+                if !path.is_dir() && !Repository::is_archive_path(&path) {
+                    continue;
+                }
+
+                repo_paths.push((Repository::path_id(&path)?, path));
+            }
+        }
+
+        Ok(repo_paths)
+    }
+
+    /// Loads the repositories of every repository directory. A repository whose id was
+    /// already loaded from an earlier directory is shadowed and skipped.
+    pub fn repos(&self) -> Result<Vec<Repository>> {
+        let mut repos: Vec<Repository> = Vec::new();
+
+        for (id, path) in self.repo_paths()? {
+            if let Some(shadowing) = repos.iter().find(|r| r.id == id) {
+                log::debug!(
+                    "Repository {id} at {path:#?} is shadowed by {:#?}",
+                    shadowing.path
+                );
+                continue;
+            }
+
+            repos.push(self.load_repo(&path)?);
+        }
+
+        Ok(repos)
+    }
+
+    /// Loads only the repository with the given id, or `None` if no repository
+    /// directory has it. Ids come from paths, so no other repository is read.
+    pub fn repo(&self, id: &str) -> Result<Option<Repository>> {
+        let Some((_, path)) = self.repo_paths()?.into_iter().find(|(i, _)| i == id) else {
+            return Ok(None);
+        };
+
+        log::debug!("Loading repository {id} from {path:#?}");
+        self.load_repo(&path).map(Some)
+    }
+
+    /// Loads a repository, verifying its signed recipe index if a key is configured for it
+    fn load_repo(&self, path: &Path) -> Result<Repository> {
+        let mut repo = Repository::from_path(path)?;
+
+        if let Some(key) = self.repo_keys.get(&repo.id) {
+            repo.verify_signature(key)?;
+        }
+
+        Ok(repo)
+    }
+
+    pub fn get_cache_dir(&self) -> PathBuf {
+        match &self.cache_dir {
+            Some(dir) => dir.clone(),
+            None => self.root.join("cache"),
+        }
+    }
+
+    pub fn cache(&self) -> Result<Cache> {
+        let cache = Cache {
+            cache_dir: self.get_cache_dir(),
+            key: self.cache_key,
+            compress: self.compress_cache,
+            file_mode: self.cache_file_mode,
+            dir_mode: self.cache_dir_mode,
+            pin_to_recipe: self.pin_cache_to_recipe,
+            max_size: self.max_cache_size,
+        };
+
+        if !cache.cache_dir.is_dir() {
+            cache.create_dir(&cache.cache_dir)?;
+
+            // A new cache starts out in the current layout
+            if !self.get_layout_path().is_file() {
+                self.write_layout_version(LAYOUT_VERSION)?;
+            }
+        }
+
+        Ok(cache)
+    }
+
+    /// Servers found down, when their health is kept across runs
+    pub fn get_mirror_health_path(&self) -> PathBuf {
+        self.root.join("mirror-health.yml")
+    }
+
+    /// Socket `tetra daemon` listens on unless another one is given
+    pub fn get_daemon_socket_path(&self) -> PathBuf {
+        self.root.join("daemon.sock")
+    }
+
+    pub fn get_layout_path(&self) -> PathBuf {
+        self.root.join("layout")
+    }
+
+    /// Version of the on-disk layout of the root. A root without a marker predates
+    /// layout versions and has the layout tetra used then, the marker is written for it.
+    pub fn layout_version(&self) -> Result<u32> {
+        let path = self.get_layout_path();
+
+        match std::fs::read_to_string(&path) {
+            Ok(version) => version
+                .trim()
+                .parse()
+                .map_err(|e| anyhow!("Invalid layout version in {path:#?}, {e}")),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                // Recording it is best effort, a read-only root works without the marker
+                if self.root.is_dir()
+                    && let Err(e) = self.write_layout_version(UNVERSIONED_LAYOUT)
+                {
+                    log::debug!("Failed to write the layout version to {path:#?}, {e}");
+                }
+
+                Ok(UNVERSIONED_LAYOUT)
+            }
+            Err(e) => Err(anyhow!("Failed to read {path:#?}, {e}")),
+        }
+    }
+
+    fn write_layout_version(&self, version: u32) -> Result<()> {
+        std::fs::create_dir_all(&self.root)?;
+        std::fs::write(self.get_layout_path(), format!("{version}\n"))?;
+        Ok(())
+    }
+
+    /// Upgrades the root to the current layout. Every step can be run again, and the
+    /// layout version is recorded after each one, so an interrupted migration resumes
+    /// where it stopped. With `dry_run` nothing is changed.
+    pub fn migrate(&self, dry_run: bool) -> Result<Migration> {
+        let from = self.layout_version()?;
+
+        if from > LAYOUT_VERSION {
+            return Err(anyhow!(
+                "Root layout version {from} is newer than version {LAYOUT_VERSION} of this tetra, upgrade tetra instead"
+            ));
+        }
+
+        let migration = Migration { from };
+
+        // Steps upgrading a version to the next go here, each recording its version
+        if !dry_run {
+            self.write_layout_version(LAYOUT_VERSION)?;
+        }
+
+        Ok(migration)
+    }
+
+    pub fn get_temp_dir(&self) -> Result<PathBuf> {
+        let tmp_dir = self.get_temp_dir_path();
+
+        if !tmp_dir.is_dir() {
+            std::fs::create_dir_all(&tmp_dir)?;
+        }
+
+        Ok(tmp_dir)
+    }
+
+    pub fn get_temp_dir_path(&self) -> PathBuf {
+        self.root.join("tmp")
+    }
+
+    pub fn get_default_arch(&self) -> String {
+        self.resolve_default_arch().0
+    }
+
+    /// The most preferred architecture and where the preference list was taken from
+    pub fn resolve_default_arch(&self) -> (String, ArchSource) {
+        let (mut arches, source) = self.resolve_arch_preference();
+        (arches.remove(0), source)
+    }
+
+    /// Architectures the host can use recipes for, most preferred first, e.g. x86_64
+    /// followed by an i686 fallback. Never empty.
+    pub fn get_arch_preference(&self) -> Vec<String> {
+        self.resolve_arch_preference().0
+    }
+
+    /// Picks the architecture preference list by precedence: `--arch`, `TETRA_ARCH`,
+    /// the root-local arch file, the arch file in the user config directory of a per-user
+    /// install, the system arch file and finally the running machine.
+    /// The flag and the variable take comma separated lists, the arch files one
+    /// architecture per line.
+    pub fn resolve_arch_preference(&self) -> (Vec<String>, ArchSource) {
+        if let Some(arches) = self.arch.as_deref().and_then(Self::parse_arches) {
+            return (arches, ArchSource::Flag);
+        }
+
+        if let Ok(arches) = std::env::var("TETRA_ARCH")
+            && let Some(arches) = Self::parse_arches(&arches)
+        {
+            return (arches, ArchSource::Env);
+        }
+
+        let root_file = self.root.join("arch");
+        if let Some(arches) = Self::read_arch_file(&root_file) {
+            return (arches, ArchSource::RootFile(root_file));
+        }
+
+        if let Some(dirs) = &self.user_dirs {
+            let user_file = dirs.config.join("arch");
+            if let Some(arches) = Self::read_arch_file(&user_file) {
+                return (arches, ArchSource::UserFile(user_file));
+            }
+        }
+
+        let system_file = PathBuf::from(Self::SYSTEM_ARCH_PATH);
+        if let Some(arches) = Self::read_arch_file(&system_file) {
+            return (arches, ArchSource::SystemFile(system_file));
+        }
+
+        (vec![Self::detect_arch()], ArchSource::Detected)
+    }
+
+    fn read_arch_file(path: &Path) -> Option<Vec<String>> {
+        Self::parse_arches(&std::fs::read_to_string(path).ok()?)
+    }
+
+    /// Splits a list of architectures on commas and whitespace, `None` if it is empty
+    fn parse_arches(list: &str) -> Option<Vec<String>> {
+        let mut arches = Vec::new();
+
+        for arch in list.split(|c: char| c == ',' || c.is_whitespace()) {
+            if !arch.is_empty() && !arches.iter().any(|a| a == arch) {
+                arches.push(arch.to_string());
+            }
+        }
+
+        (!arches.is_empty()).then_some(arches)
+    }
+
+    /// Maps the architecture tetra was built for onto the names recipes use
+    fn detect_arch() -> String {
+        match std::env::consts::ARCH {
+            "x86" => "i686",
+            "arm" => "armv7",
+            "powerpc64" if cfg!(target_endian = "little") => "ppc64le",
+            arch => arch,
+        }
+        .to_string()
+    }
+}
@@ -0,0 +1,45 @@
+use anyhow::Result;
+use std::io::Read;
+
+use crate::{
+    store::{CacheStatus, Provenance, TempFile},
+    util::sha256_reader,
+};
+
+/// The storage sources are cached in. The disk-backed [`Cache`](crate::store::Cache)
+/// is used unless another backend is given, e.g. one that keeps blobs in memory.
+/// Downloads may be cached from several threads at once.
+pub trait CacheBackend: Sync {
+    /// Checks a cached blob against its hash without changing the cache
+    fn check(&self, hash: blake3::Hash) -> Result<CacheStatus>;
+
+    /// Looks up a blob before it is used, removing it together with its provenance if
+    /// it is corrupt and recording the lookup as a cache hit or miss
+    fn validate(&self, hash: blake3::Hash) -> Result<CacheStatus>;
+
+    /// Opens a cached blob for reading its contents
+    fn open_blob(&self, hash: blake3::Hash) -> Result<Box<dyn Read>>;
+
+    /// Size of the contents of a cached blob
+    fn blob_size(&self, hash: blake3::Hash) -> Option<u64>;
+
+    /// Computes the sha256 digest of the contents of a cached blob
+    fn sha256_blob(&self, hash: blake3::Hash) -> Result<String> {
+        sha256_reader(self.open_blob(hash)?)
+    }
+
+    fn read_provenance(&self, hash: blake3::Hash) -> Result<Option<Provenance>>;
+    fn write_provenance(&self, provenance: &Provenance) -> Result<()>;
+
+    /// Returns the provenance of the last download of a mutable source URL
+    fn read_mutable_record(&self, url: &str) -> Result<Option<Provenance>>;
+    fn write_mutable_record(&self, provenance: &Provenance) -> Result<()>;
+
+    /// Promotes a downloaded temp file to the blob of `hash`, after checking it
+    /// matches. An existing blob is never replaced by a bad download.
+    fn cache_tmp_file(&self, tmp_file: &TempFile, hash: blake3::Hash) -> Result<()>;
+
+    /// Promotes a temp file without hashing it again, for content that was already
+    /// hashed while it was downloaded
+    fn cache_hashed_tmp_file(&self, tmp_file: &TempFile, hash: blake3::Hash) -> Result<()>;
+}
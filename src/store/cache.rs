@@ -1,16 +1,230 @@
 use anyhow::{Result, anyhow};
-use std::path::{Path, PathBuf};
+use ring::{
+    aead::{Aad, CHACHA20_POLY1305, LessSafeKey, NONCE_LEN, Nonce, UnboundKey},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    fs::{File, FileTimes},
+    io::{ErrorKind, Read},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use url::Url;
 
-use crate::TempFile;
+use crate::{
+    model::Recipe,
+    store::{CacheBackend, Provenance, TempFile},
+    util::{
+        Counter, count, decode_hex, encode_hex, format_bytes, move_file, parallel_map, print_warn,
+        set_mode, sha256_reader,
+    },
+};
 
+/// Outcome of checking a cached blob against its hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    Valid,
+    Missing,
+
+    /// The blob did not match its hash. [`Cache::validate`] removes it together with
+    /// its provenance, [`Cache::check`] leaves it in place.
+    Corrupt,
+}
+
+impl CacheStatus {
+    pub fn is_valid(self) -> bool {
+        self == Self::Valid
+    }
+}
+
+/// Outcome of [`Cache::verify_blobs`]
+#[derive(Debug, Default)]
+pub struct BlobVerification {
+    /// Blobs in the cache
+    pub total: usize,
+
+    /// Blobs that were hashed, all of them unless sampling
+    pub checked: usize,
+
+    /// Checked blobs whose content hash is unknown, as their namespace provenance is missing
+    pub unknown: usize,
+
+    pub corrupt: Vec<PathBuf>,
+}
+
+impl BlobVerification {
+    /// Share of the checked blobs with a known hash that were corrupt
+    pub fn error_rate(&self) -> f64 {
+        match self.checked - self.unknown {
+            0 => 0.0,
+            verified => self.corrupt.len() as f64 / verified as f64,
+        }
+    }
+}
+
+/// Blobs operations of this process looked up or stored, with the number of operations
+/// using them. They are never evicted to make room for another blob while in use.
+static IN_USE: Mutex<BTreeMap<PathBuf, usize>> = Mutex::new(BTreeMap::new());
+
+thread_local! {
+    /// Blobs used by the scope entered on this thread, if any
+    static SCOPE: RefCell<Option<BTreeSet<PathBuf>>> = const { RefCell::new(None) };
+}
+
+/// Marks an operation, e.g. a request to the daemon, that keeps the blobs it uses on this
+/// thread from eviction for as long as it lives, and after that for the eviction grace
+/// period. Blobs used outside of a scope are kept until the process exits.
 #[derive(Debug)]
+pub struct CacheUseScope {
+    outermost: bool,
+}
+
+impl CacheUseScope {
+    pub fn enter() -> Self {
+        let outermost = SCOPE.with_borrow_mut(|scope| match scope {
+            Some(_) => false,
+            None => {
+                *scope = Some(BTreeSet::new());
+                true
+            }
+        });
+
+        Self { outermost }
+    }
+}
+
+impl Drop for CacheUseScope {
+    fn drop(&mut self) {
+        if !self.outermost {
+            return;
+        }
+
+        let Some(used) = SCOPE.with_borrow_mut(Option::take) else {
+            return;
+        };
+
+        if let Ok(mut in_use) = IN_USE.lock() {
+            for path in used {
+                if let Some(users) = in_use.get_mut(&path) {
+                    *users -= 1;
+                    if *users == 0 {
+                        in_use.remove(&path);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A download record of a namespaced cache, which cannot be read without its key
+#[derive(Debug, Serialize, Deserialize)]
+struct SealedRecord {
+    /// Hex of the nonce, followed by the encrypted record and its tag
+    sealed: String,
+}
+
+#[derive(Debug, Clone)]
 pub struct Cache {
     pub cache_dir: PathBuf,
+
+    /// Namespace key, blobs are stored under a keyed digest of their hash when set
+    pub key: Option<[u8; 32]>,
+
+    /// Store new blobs zstd-compressed, they are then kept as `<blob>.zst`
+    pub compress: bool,
+
+    /// Permissions of the files written to the cache, the umask decides when unset
+    pub file_mode: Option<u32>,
+
+    /// Permissions of the directories created in the cache
+    pub dir_mode: Option<u32>,
+
+    /// Namespace the blobs of a recipe by the hash of its sources, see [`Cache::for_recipe`]
+    pub pin_to_recipe: bool,
+
+    /// Size budget of the blobs on disk, least recently used blobs are evicted to stay
+    /// within it when a new blob is stored
+    pub max_size: Option<u64>,
 }
 
 impl Cache {
+    const COMPRESSED_EXTENSION: &str = "zst";
+
+    /// Largest size of a zstd frame header
+    const FRAME_HEADER_MAX: u64 = 18;
+
+    const RECIPE_NAMESPACE_CONTEXT: &str = "tetra-pkgmgr 2026-10-15 recipe cache namespace";
+
+    const RECORD_SEAL_CONTEXT: &str = "tetra-pkgmgr 2026-10-15 cache record sealing";
+
+    /// How long after its last use a blob is kept from eviction
+    const EVICTION_GRACE: Duration = Duration::from_secs(15 * 60);
+
+    /// Creates a directory and its missing parents with the configured permissions
+    pub fn create_dir(&self, dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(dir)?;
+
+        match self.dir_mode {
+            Some(mode) => set_mode(dir, mode),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes a record file with the configured permissions
+    fn write_file(&self, path: &Path, contents: String) -> Result<()> {
+        std::fs::write(path, contents)?;
+        self.apply_file_mode(path)
+    }
+
+    fn apply_file_mode(&self, path: &Path) -> Result<()> {
+        match self.file_mode {
+            Some(mode) => set_mode(path, mode),
+            None => Ok(()),
+        }
+    }
+
+    /// The cache the sources of a recipe are looked up in. When pinned to recipes, that is
+    /// a namespace derived from the hash of the recipe's sources, so a recipe whose sources
+    /// changed in place never reuses blobs or download records of the old one.
+    pub fn for_recipe(&self, recipe: &Recipe) -> Cache {
+        if !self.pin_to_recipe {
+            return self.clone();
+        }
+
+        let mut material = self.key.unwrap_or_default().to_vec();
+        material.extend_from_slice(recipe.written_sources_digest().as_bytes());
+
+        Cache {
+            key: Some(blake3::derive_key(
+                Self::RECIPE_NAMESPACE_CONTEXT,
+                &material,
+            )),
+            ..self.clone()
+        }
+    }
+
+    /// Name an entry is stored under, the keyed digest of `data` in a namespaced cache
+    fn entry_name(&self, data: &[u8]) -> blake3::Hash {
+        match &self.key {
+            Some(key) => blake3::keyed_hash(key, data),
+            None => blake3::hash(data),
+        }
+    }
+
+    /// Name a blob is stored under, its content hash unless the cache is namespaced
+    pub fn get_blob_name(&self, hash: blake3::Hash) -> blake3::Hash {
+        match self.key {
+            Some(_) => self.entry_name(hash.as_bytes()),
+            None => hash,
+        }
+    }
+
     pub fn get_cache_path(&self, hash: blake3::Hash) -> PathBuf {
-        let hash_str = hash.to_string();
+        let hash_str = self.get_blob_name(hash).to_string();
         let prefix = hash_str[0..2].to_string();
 
         let mut path = self.cache_dir.join(prefix);
@@ -19,44 +233,836 @@ impl Cache {
         path
     }
 
+    pub fn get_compressed_path(&self, hash: blake3::Hash) -> PathBuf {
+        let mut path = self.get_cache_path(hash);
+        path.set_extension(Self::COMPRESSED_EXTENSION);
+        path
+    }
+
+    /// Path of a cached blob in whichever form it is stored, or the path a new blob
+    /// would be stored at when it is not cached
+    pub fn blob_path(&self, hash: blake3::Hash) -> PathBuf {
+        let plain_path = self.get_cache_path(hash);
+        let compressed_path = self.get_compressed_path(hash);
+
+        if plain_path.is_file() {
+            plain_path
+        } else if compressed_path.is_file() || self.compress {
+            compressed_path
+        } else {
+            plain_path
+        }
+    }
+
+    /// Whether a blob path names a zstd compressed blob
+    pub fn is_compressed(path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|ext| ext == Self::COMPRESSED_EXTENSION)
+    }
+
+    /// Computes the sha256 digest of the contents of a cached blob
+    pub fn sha256_blob(&self, hash: blake3::Hash) -> Result<String> {
+        sha256_reader(self.open_blob(hash)?)
+    }
+
+    /// Opens a cached blob for reading, decompressing it if it is stored compressed
+    pub fn open_blob(&self, hash: blake3::Hash) -> Result<Box<dyn Read>> {
+        let path = self.blob_path(hash);
+        let file = File::open(&path)?;
+
+        if Self::is_compressed(&path) {
+            return Ok(Box::new(zstd::Decoder::new(file)?));
+        }
+
+        Ok(Box::new(file))
+    }
+
+    /// Places an intact cached blob in `dest_dir`, named after the last segment of the URL
+    /// it was downloaded from, or after its hash if that has none. The blob is hardlinked,
+    /// so the file must not be modified, or copied when it is stored compressed or the
+    /// directory is on another filesystem. Returns the path of the file.
+    pub fn link_into(&self, hash: blake3::Hash, dest_dir: &Path) -> Result<PathBuf> {
+        if !self.validate(hash)?.is_valid() {
+            return Err(anyhow!("Blob {hash} is not in the cache"));
+        }
+
+        let name = self
+            .read_provenance(hash)?
+            .and_then(|p| {
+                let url = Url::parse(&p.url).ok()?;
+                let name = url.path_segments()?.next_back()?.to_string();
+                (!name.is_empty()).then_some(name)
+            })
+            .unwrap_or_else(|| hash.to_string());
+
+        let dest = dest_dir.join(name);
+        if dest.exists() {
+            return Err(anyhow!("{dest:#?} already exists"));
+        }
+
+        let blob_path = self.blob_path(hash);
+        if !Self::is_compressed(&blob_path) {
+            match std::fs::hard_link(&blob_path, &dest) {
+                Ok(()) => return Ok(dest),
+                Err(e) if e.kind() == ErrorKind::CrossesDevices => {}
+                Err(e) => return Err(anyhow!("Failed to link {blob_path:#?} to {dest:#?}, {e}")),
+            }
+        }
+
+        // Staged so a failed copy never leaves a partial file under the final name
+        let mut staging_path = dest.as_os_str().to_owned();
+        staging_path.push(".partial");
+        let staging_path = PathBuf::from(staging_path);
+
+        let copied = self.open_blob(hash).and_then(|mut blob| {
+            std::io::copy(&mut blob, &mut File::create(&staging_path)?)?;
+            Ok(std::fs::rename(&staging_path, &dest)?)
+        });
+
+        if let Err(e) = copied {
+            if staging_path.is_file() {
+                let _ = std::fs::remove_file(&staging_path);
+            }
+            return Err(anyhow!("Failed to copy blob {hash} to {dest:#?}, {e}"));
+        }
+
+        Ok(dest)
+    }
+
+    /// Size of the contents of a cached blob, which is not its size on disk when the
+    /// blob is stored compressed
+    pub fn blob_size(&self, hash: blake3::Hash) -> Option<u64> {
+        let path = self.blob_path(hash);
+        let meta = std::fs::metadata(&path).ok()?;
+
+        if !Self::is_compressed(&path) {
+            return Some(meta.len());
+        }
+
+        // Blobs written by tetra record their size in the frame header
+        let mut header = Vec::new();
+        File::open(&path)
+            .ok()?
+            .take(Self::FRAME_HEADER_MAX)
+            .read_to_end(&mut header)
+            .ok()?;
+
+        if let Ok(Some(size)) = zstd::zstd_safe::get_frame_content_size(&header) {
+            return Some(size);
+        }
+
+        let mut reader = self.open_blob(hash).ok()?;
+        std::io::copy(&mut reader, &mut std::io::sink()).ok()
+    }
+
+    pub fn get_provenance_path(&self, hash: blake3::Hash) -> PathBuf {
+        let mut path = self.get_cache_path(hash);
+        path.set_extension("provenance.yml");
+        path
+    }
+
+    pub fn write_provenance(&self, provenance: &Provenance) -> Result<()> {
+        let hash = blake3::Hash::from_hex(&provenance.hash)?;
+        let provenance_s = self.encode_record(provenance)?;
+        self.write_file(&self.get_provenance_path(hash), provenance_s)
+    }
+
+    pub fn read_provenance(&self, hash: blake3::Hash) -> Result<Option<Provenance>> {
+        let path = self.get_provenance_path(hash);
+
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let provenance_s = std::fs::read_to_string(path)?;
+        Ok(Some(self.decode_record(&provenance_s)?))
+    }
+
+    pub fn get_mutable_record_path(&self, url: &str) -> PathBuf {
+        let mut path = self.cache_dir.join("mutable");
+        path.push(format!("{}.yml", self.entry_name(url.as_bytes())));
+        path
+    }
+
+    /// Returns the provenance of the last download of a mutable source URL
+    pub fn read_mutable_record(&self, url: &str) -> Result<Option<Provenance>> {
+        let path = self.get_mutable_record_path(url);
+
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let record_s = std::fs::read_to_string(path)?;
+        Ok(Some(self.decode_record(&record_s)?))
+    }
+
+    pub fn write_mutable_record(&self, provenance: &Provenance) -> Result<()> {
+        let path = self.get_mutable_record_path(&provenance.url);
+
+        if let Some(parent) = path.parent()
+            && !parent.is_dir()
+        {
+            self.create_dir(parent)?;
+        }
+
+        self.write_file(&path, self.encode_record(provenance)?)
+    }
+
+    /// Serializes a download record. In a namespaced cache it is sealed with a key derived
+    /// from the namespace key, as it names the content hash and URL the entry name hides.
+    fn encode_record(&self, provenance: &Provenance) -> Result<String> {
+        let record = serde_yaml::to_string(provenance)?;
+
+        let Some(key) = self.record_key()? else {
+            return Ok(record);
+        };
+
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("Failed to generate a nonce for a download record"))?;
+
+        let mut sealed = record.into_bytes();
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::empty(),
+            &mut sealed,
+        )
+        .map_err(|_| anyhow!("Failed to seal a download record"))?;
+
+        let mut bytes = nonce.to_vec();
+        bytes.extend(sealed);
+
+        Ok(serde_yaml::to_string(&SealedRecord {
+            sealed: encode_hex(&bytes),
+        })?)
+    }
+
+    /// Reads a download record written by [`Cache::encode_record`]. Records of another
+    /// namespace cannot be opened, plain records from before sealing are still read.
+    fn decode_record(&self, record: &str) -> Result<Provenance> {
+        let Some(key) = self.record_key()? else {
+            return Ok(serde_yaml::from_str(record)?);
+        };
+
+        let Ok(SealedRecord { sealed }) = serde_yaml::from_str(record) else {
+            return Ok(serde_yaml::from_str(record)?);
+        };
+
+        let bytes = decode_hex(&sealed)?;
+        let (nonce, sealed) = bytes
+            .split_at_checked(NONCE_LEN)
+            .ok_or(anyhow!("Sealed download record is truncated"))?;
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| anyhow!("Sealed download record is truncated"))?;
+
+        let mut sealed = sealed.to_vec();
+        let opened = key
+            .open_in_place(nonce, Aad::empty(), &mut sealed)
+            .map_err(|_| anyhow!("Download record is corrupt or of another namespace"))?;
+
+        Ok(serde_yaml::from_slice(opened)?)
+    }
+
+    /// The key download records of a namespaced cache are sealed with
+    fn record_key(&self) -> Result<Option<LessSafeKey>> {
+        let Some(key) = &self.key else {
+            return Ok(None);
+        };
+
+        let derived = blake3::derive_key(Self::RECORD_SEAL_CONTEXT, key);
+        let key = UnboundKey::new(&CHACHA20_POLY1305, &derived)
+            .map_err(|_| anyhow!("Invalid download record key"))?;
+
+        Ok(Some(LessSafeKey::new(key)))
+    }
+
     pub fn hash_file(path: &Path) -> Result<blake3::Hash> {
+        let meta = std::fs::metadata(path)?;
+
+        if meta.is_dir() {
+            return Err(anyhow!("Expected a file, found a directory at {path:#?}"));
+        }
+
+        // Empty files cannot be mapped, their hash is the hash of the empty input
+        if meta.len() == 0 {
+            return Ok(blake3::hash(&[]));
+        }
+
         let mut hasher = blake3::Hasher::new();
         hasher.update_mmap(path)?;
         Ok(hasher.finalize())
     }
 
-    pub fn validate(&self, hash: blake3::Hash) -> Result<bool> {
-        let path = self.get_cache_path(hash);
+    /// Hashes a stored blob, compressed blobs are hashed over their decompressed
+    /// contents so the hash stays the content address. Returns None for a compressed
+    /// blob that cannot be decompressed.
+    fn hash_blob(path: &Path) -> Result<Option<blake3::Hash>> {
+        if !Self::is_compressed(path) {
+            return Ok(Some(Self::hash_file(path)?));
+        }
+
+        let file = File::open(path)?;
+        let mut hasher = blake3::Hasher::new();
+        let decoded =
+            zstd::Decoder::new(file).and_then(|decoder| hasher.update_reader(decoder).map(|_| ()));
+
+        Ok(decoded.ok().map(|_| hasher.finalize()))
+    }
+
+    /// Checks a cached blob against its hash without changing the cache
+    pub fn check(&self, hash: blake3::Hash) -> Result<CacheStatus> {
+        let path = self.blob_path(hash);
 
         if !path.is_file() {
-            return Ok(false);
+            return Ok(CacheStatus::Missing);
         }
 
-        let computed_hash = Self::hash_file(&path)?;
-        if hash != computed_hash {
-            // Hash did not match, cached file should be removed
-            std::fs::remove_file(path)?;
-            return Ok(false);
+        if Self::hash_blob(&path)? != Some(hash) {
+            return Ok(CacheStatus::Corrupt);
         }
 
-        Ok(true)
+        Ok(CacheStatus::Valid)
     }
 
-    pub fn cache_tmp_file(&self, tmp_file: &TempFile, hash: blake3::Hash) -> Result<()> {
-        let prefix = hash.to_string()[0..2].to_string();
-        let cache_target_dir = self.cache_dir.join(prefix);
+    /// Looks up a blob before it is used, removing it if it is corrupt and recording
+    /// the lookup as a cache hit or miss
+    pub fn validate(&self, hash: blake3::Hash) -> Result<CacheStatus> {
+        let status = self.validate_blob(hash)?;
+
+        match status {
+            CacheStatus::Valid => {
+                count(Counter::CacheHits, 1);
+                Self::mark_used(&self.blob_path(hash));
+            }
+            CacheStatus::Missing => count(Counter::CacheMisses, 1),
+            CacheStatus::Corrupt => {
+                count(Counter::CacheMisses, 1);
+                count(Counter::ValidationFailures, 1);
+            }
+        }
 
-        if !cache_target_dir.is_dir() {
-            std::fs::create_dir_all(&cache_target_dir)?;
+        Ok(status)
+    }
+
+    fn validate_blob(&self, hash: blake3::Hash) -> Result<CacheStatus> {
+        let status = self.check(hash)?;
+
+        if status == CacheStatus::Corrupt {
+            // Hash did not match, cached file and its provenance should be removed
+            std::fs::remove_file(self.blob_path(hash))?;
+
+            let provenance_path = self.get_provenance_path(hash);
+            if provenance_path.is_file() {
+                std::fs::remove_file(provenance_path)?;
+            }
         }
 
-        let cache_path = self.get_cache_path(hash);
-        std::fs::rename(&tmp_file.path, cache_path)?;
+        Ok(status)
+    }
 
-        if !self.validate(hash)? {
+    pub fn cache_tmp_file(&self, tmp_file: &TempFile, hash: blake3::Hash) -> Result<()> {
+        // Verify before promoting, an existing blob must not be replaced by a bad download
+        if Self::hash_file(&tmp_file.path)? != hash {
             return Err(anyhow!("Temporary file checksum does not match {}", hash));
         }
 
+        self.cache_hashed_tmp_file(tmp_file, hash)?;
+
+        if !self.validate_blob(hash)?.is_valid() {
+            return Err(anyhow!("Cached file checksum does not match {}", hash));
+        }
+
+        Ok(())
+    }
+
+    /// Moves a temporary file into the cache without hashing it again, for content
+    /// that was already hashed while it was downloaded
+    pub fn cache_hashed_tmp_file(&self, tmp_file: &TempFile, hash: blake3::Hash) -> Result<()> {
+        self.store_blob(&tmp_file.path, hash, true)
+    }
+
+    /// Stores a file as the blob of `hash`, compressing it when the cache is configured
+    /// to. A copy of the blob in the other form is removed, so only one is kept.
+    fn store_blob(&self, path: &Path, hash: blake3::Hash, move_in: bool) -> Result<()> {
+        let plain_path = self.get_cache_path(hash);
+        let compressed_path = self.get_compressed_path(hash);
+
+        // The uncompressed size is what a compressed blob takes at most. A blob of the
+        // same hash is replaced, so it does not count against the budget.
+        self.evict_for(
+            std::fs::metadata(path)?.len(),
+            &[&plain_path, &compressed_path],
+        )?;
+
+        if let Some(cache_target_dir) = plain_path.parent()
+            && !cache_target_dir.is_dir()
+        {
+            self.create_dir(cache_target_dir)?;
+        }
+
+        let (cache_path, other_path) = if self.compress {
+            (compressed_path, plain_path)
+        } else {
+            (plain_path, compressed_path)
+        };
+
+        if move_in && !self.compress {
+            move_file(path, &cache_path)?;
+        } else {
+            // Staged like a cross-filesystem move, so the blob never appears half written
+            let mut staging_path = cache_path.as_os_str().to_owned();
+            staging_path.push(".partial");
+            let staging_path = PathBuf::from(staging_path);
+
+            let staged = if self.compress {
+                Self::compress_file(path, &staging_path)
+            } else {
+                std::fs::copy(path, &staging_path)
+                    .map(|_| ())
+                    .map_err(Into::into)
+            }
+            .and_then(|_| Ok(std::fs::rename(&staging_path, &cache_path)?));
+
+            if let Err(e) = staged {
+                if staging_path.is_file() {
+                    let _ = std::fs::remove_file(&staging_path);
+                }
+                return Err(e);
+            }
+
+            if move_in {
+                std::fs::remove_file(path)?;
+            }
+        }
+
+        self.apply_file_mode(&cache_path)?;
+        Self::mark_used(&cache_path);
+
+        if other_path.is_file() {
+            std::fs::remove_file(other_path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Records a use of a blob in its access time, which eviction orders blobs by, and
+    /// keeps it from being evicted until the [`CacheUseScope`] of the operation ends. The
+    /// time is set explicitly, as filesystems mounted with `noatime` or `relatime` do not
+    /// keep it current.
+    fn mark_used(path: &Path) {
+        let touched = File::open(path)
+            .and_then(|file| file.set_times(FileTimes::new().set_accessed(SystemTime::now())));
+
+        if let Err(e) = touched {
+            log::debug!("Failed to update the access time of {path:#?}, {e}");
+        }
+
+        // A blob counts one use per scope, however often the operation looks it up
+        let first_use = SCOPE.with_borrow_mut(|scope| match scope {
+            Some(used) => used.insert(path.to_path_buf()),
+            None => true,
+        });
+
+        if first_use && let Ok(mut in_use) = IN_USE.lock() {
+            *in_use.entry(path.to_path_buf()).or_default() += 1;
+        }
+    }
+
+    /// Evicts the least recently used blobs, together with their provenance, until
+    /// `incoming` more bytes fit in the size budget. The `replaced` blobs are left out of
+    /// the budget and kept, and so are blobs in use even if the budget cannot be met
+    /// without them. Blobs used within [`Cache::EVICTION_GRACE`] are kept as well, as
+    /// another process or a client of the daemon may still be reading them.
+    fn evict_for(&self, incoming: u64, replaced: &[&Path]) -> Result<()> {
+        let Some(max_size) = self.max_size else {
+            return Ok(());
+        };
+
+        let mut blobs = Vec::new();
+        let mut total = 0;
+
+        for (_, path) in self.blob_paths()? {
+            if replaced.contains(&path.as_path()) {
+                continue;
+            }
+
+            let Ok(meta) = std::fs::metadata(&path) else {
+                continue;
+            };
+
+            total += meta.len();
+            blobs.push((meta.accessed().unwrap_or(UNIX_EPOCH), meta.len(), path));
+        }
+
+        if total + incoming <= max_size {
+            return Ok(());
+        }
+
+        let in_use = IN_USE
+            .lock()
+            .map_err(|_| anyhow!("Cache usage state is poisoned"))?;
+        let grace_start = SystemTime::now()
+            .checked_sub(Self::EVICTION_GRACE)
+            .unwrap_or(UNIX_EPOCH);
+        blobs.sort();
+
+        for (accessed, size, path) in blobs {
+            if total + incoming <= max_size {
+                break;
+            }
+
+            if in_use.contains_key(&path) || accessed > grace_start {
+                continue;
+            }
+
+            // Another process may have evicted or replaced the blob since it was listed
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    total -= size;
+                    continue;
+                }
+                removed => removed?,
+            }
+
+            let mut provenance_path = path.clone();
+            provenance_path.set_extension("provenance.yml");
+            match std::fs::remove_file(provenance_path) {
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                removed => removed?,
+            }
+
+            log::info!("Evicted {path:#?} to keep the cache within its size budget");
+            count(Counter::CacheEvictions, 1);
+            total -= size;
+        }
+
+        if total + incoming > max_size {
+            print_warn(&format!(
+                "Cache exceeds its size budget of {}, the remaining blobs are in use or were used recently",
+                format_bytes(max_size)
+            ));
+        }
+
         Ok(())
     }
+
+    fn compress_file(src: &Path, dest: &Path) -> Result<()> {
+        let mut input = File::open(src)?;
+        let size = input.metadata()?.len();
+
+        let mut encoder = zstd::Encoder::new(File::create(dest)?, 0)?;
+        encoder.include_contentsize(true)?;
+        encoder.set_pledged_src_size(Some(size))?;
+
+        std::io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?.sync_all()?;
+        Ok(())
+    }
+
+    /// Provenance records of all blobs in the cache
+    pub fn provenances(&self) -> Result<Vec<Provenance>> {
+        let mut provenances = Vec::new();
+
+        if !self.cache_dir.is_dir() {
+            return Ok(provenances);
+        }
+
+        for prefix_dir in std::fs::read_dir(&self.cache_dir)? {
+            let prefix_dir = prefix_dir?.path();
+
+            if !prefix_dir.is_dir() {
+                continue;
+            }
+
+            for entry in std::fs::read_dir(&prefix_dir)? {
+                let path = entry?.path();
+
+                if !path.to_string_lossy().ends_with(".provenance.yml") {
+                    continue;
+                }
+
+                let provenance_s = std::fs::read_to_string(&path)?;
+                if let Ok(provenance) = self.decode_record(&provenance_s) {
+                    provenances.push(provenance);
+                }
+            }
+        }
+
+        Ok(provenances)
+    }
+
+    /// Inserts a file built outside of tetra into the cache under its content hash,
+    /// checking it against `expected` if given. The file is copied unless `move_in`
+    /// is set. Returns the content hash of the file.
+    pub fn add(
+        &self,
+        path: &Path,
+        expected: Option<blake3::Hash>,
+        move_in: bool,
+    ) -> Result<blake3::Hash> {
+        let hash = Self::hash_file(path)?;
+
+        if let Some(expected) = expected
+            && expected != hash
+        {
+            return Err(anyhow!(
+                "File {path:#?} has checksum {hash}, expected {expected}"
+            ));
+        }
+
+        if self.validate(hash)?.is_valid() {
+            // The cache already holds identical content, a move only drops the original
+            if move_in {
+                std::fs::remove_file(path)?;
+            }
+            return Ok(hash);
+        }
+
+        self.store_blob(path, hash, move_in)?;
+
+        if !self.validate_blob(hash)?.is_valid() {
+            return Err(anyhow!("Cached file checksum does not match {}", hash));
+        }
+
+        Ok(hash)
+    }
+
+    /// Removes corrupt blobs, provenance records without a blob and partially copied
+    /// blobs, returning the removed paths. Blobs whose hash is in `busy` are being
+    /// written by a running process and are left alone. With `dry_run` nothing is removed.
+    pub fn gc(&self, busy: &[String], dry_run: bool) -> Result<Vec<PathBuf>> {
+        let mut removed = Vec::new();
+
+        if !self.cache_dir.is_dir() {
+            return Ok(removed);
+        }
+
+        for prefix_dir in std::fs::read_dir(&self.cache_dir)? {
+            let prefix_dir = prefix_dir?.path();
+
+            if !Self::is_prefix_dir(&prefix_dir) {
+                continue;
+            }
+
+            for entry in std::fs::read_dir(&prefix_dir)? {
+                let path = entry?.path();
+                let file_name = path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default();
+
+                let garbage = if let Some(name) = file_name.strip_suffix(".partial") {
+                    let name = name.strip_suffix(".zst").unwrap_or(name);
+                    !busy
+                        .iter()
+                        .filter_map(|b| blake3::Hash::from_hex(b).ok())
+                        .any(|b| self.get_blob_name(b).to_string() == name)
+                } else if let Some(name) = file_name.strip_suffix(".provenance.yml") {
+                    !prefix_dir.join(name).is_file()
+                        && !prefix_dir.join(format!("{name}.zst")).is_file()
+                } else if let Ok(name) =
+                    blake3::Hash::from_hex(file_name.strip_suffix(".zst").unwrap_or(&file_name))
+                {
+                    path.is_file() && self.is_corrupt_blob(&path, name)?
+                } else {
+                    false
+                };
+
+                if !garbage {
+                    continue;
+                }
+
+                if !dry_run {
+                    std::fs::remove_file(&path)?;
+                }
+                removed.push(path);
+            }
+        }
+
+        // Provenance of a corrupt blob goes together with it
+        let orphaned = removed
+            .iter()
+            .map(|p| {
+                let mut path = p.clone();
+                path.set_extension("provenance.yml");
+                path
+            })
+            .filter(|p| p.is_file() && !removed.contains(p))
+            .collect::<Vec<_>>();
+
+        for path in orphaned {
+            if !dry_run {
+                std::fs::remove_file(&path)?;
+            }
+            removed.push(path);
+        }
+
+        removed.sort();
+        Ok(removed)
+    }
+
+    /// Only the two character prefix directories hold blobs
+    fn is_prefix_dir(path: &Path) -> bool {
+        let is_prefix = path.file_name().is_some_and(|n| {
+            n.len() == 2 && n.to_string_lossy().chars().all(|c| c.is_ascii_hexdigit())
+        });
+
+        is_prefix && path.is_dir()
+    }
+
+    /// Every stored blob in the cache and the name it is stored under, leaving out
+    /// partial copies and provenance records
+    fn blob_paths(&self) -> Result<Vec<(blake3::Hash, PathBuf)>> {
+        let mut blobs = Vec::new();
+
+        if !self.cache_dir.is_dir() {
+            return Ok(blobs);
+        }
+
+        for prefix_dir in std::fs::read_dir(&self.cache_dir)? {
+            let prefix_dir = prefix_dir?.path();
+
+            if !prefix_dir.is_dir() {
+                continue;
+            }
+
+            for entry in std::fs::read_dir(&prefix_dir)? {
+                let path = entry?.path();
+                let file_name = path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default();
+
+                let name = file_name.strip_suffix(".zst").unwrap_or(&file_name);
+
+                if let Ok(name) = blake3::Hash::from_hex(name)
+                    && path.is_file()
+                {
+                    blobs.push((name, path));
+                }
+            }
+        }
+
+        blobs.sort_by(|a, b| a.1.cmp(&b.1));
+        Ok(blobs)
+    }
+
+    /// Hashes the blobs of the cache, or a random share of them when `sample` is set,
+    /// e.g. 0.05 for 5%. At least one blob is checked from a non-empty cache. Corrupt
+    /// blobs are reported but not removed.
+    pub fn verify_blobs(&self, sample: Option<f64>) -> Result<BlobVerification> {
+        let mut blobs = self.blob_paths()?;
+        let total = blobs.len();
+
+        if let Some(share) = sample {
+            // Ordering by a digest keyed with a fresh seed shuffles without a RNG
+            let seed = format!("{:?}{}", std::time::SystemTime::now(), std::process::id());
+            let key = blake3::hash(seed.as_bytes());
+            blobs.sort_by_cached_key(|(name, _)| {
+                blake3::keyed_hash(key.as_bytes(), name.as_bytes()).to_hex()
+            });
+
+            let count = ((total as f64 * share).ceil() as usize).clamp(total.min(1), total);
+            blobs.truncate(count);
+        }
+
+        let results = parallel_map(&blobs, None, |(name, path)| -> Result<_> {
+            let Some(expected) = self.expected_blob_hash(path, *name) else {
+                return Ok(None);
+            };
+
+            if Self::hash_blob(path)? == Some(expected) {
+                Ok(Some(CacheStatus::Valid))
+            } else {
+                Ok(Some(CacheStatus::Corrupt))
+            }
+        });
+
+        let mut verification = BlobVerification {
+            total,
+            checked: blobs.len(),
+            ..Default::default()
+        };
+
+        for ((_, path), result) in blobs.into_iter().zip(results) {
+            match result? {
+                Some(CacheStatus::Corrupt) => verification.corrupt.push(path),
+                Some(_) => {}
+                None => verification.unknown += 1,
+            }
+        }
+
+        Ok(verification)
+    }
+
+    /// The content hash a blob must have. For a namespaced blob it is only known from
+    /// its provenance, `None` if that is missing or belongs to another namespace.
+    fn expected_blob_hash(&self, path: &Path, name: blake3::Hash) -> Option<blake3::Hash> {
+        let mut provenance_path = path.to_path_buf();
+        provenance_path.set_extension("provenance.yml");
+
+        let recorded = std::fs::read_to_string(&provenance_path)
+            .ok()
+            .and_then(|s| self.decode_record(&s).ok())
+            .and_then(|p| blake3::Hash::from_hex(&p.hash).ok());
+
+        match (recorded, self.key) {
+            (Some(hash), _) if self.get_blob_name(hash) == name => Some(hash),
+            (Some(_), _) => None,
+            (None, None) => Some(name),
+            (None, Some(_)) => None,
+        }
+    }
+
+    /// Blobs that cannot be checked, or that belong to another namespace, are kept
+    fn is_corrupt_blob(&self, path: &Path, name: blake3::Hash) -> Result<bool> {
+        let Some(expected) = self.expected_blob_hash(path, name) else {
+            return Ok(false);
+        };
+
+        Ok(Self::hash_blob(path)? != Some(expected))
+    }
+}
+
+impl CacheBackend for Cache {
+    fn check(&self, hash: blake3::Hash) -> Result<CacheStatus> {
+        Cache::check(self, hash)
+    }
+
+    fn validate(&self, hash: blake3::Hash) -> Result<CacheStatus> {
+        Cache::validate(self, hash)
+    }
+
+    fn open_blob(&self, hash: blake3::Hash) -> Result<Box<dyn Read>> {
+        Cache::open_blob(self, hash)
+    }
+
+    fn blob_size(&self, hash: blake3::Hash) -> Option<u64> {
+        Cache::blob_size(self, hash)
+    }
+
+    fn read_provenance(&self, hash: blake3::Hash) -> Result<Option<Provenance>> {
+        Cache::read_provenance(self, hash)
+    }
+
+    fn write_provenance(&self, provenance: &Provenance) -> Result<()> {
+        Cache::write_provenance(self, provenance)
+    }
+
+    fn read_mutable_record(&self, url: &str) -> Result<Option<Provenance>> {
+        Cache::read_mutable_record(self, url)
+    }
+
+    fn write_mutable_record(&self, provenance: &Provenance) -> Result<()> {
+        Cache::write_mutable_record(self, provenance)
+    }
+
+    fn cache_tmp_file(&self, tmp_file: &TempFile, hash: blake3::Hash) -> Result<()> {
+        Cache::cache_tmp_file(self, tmp_file, hash)
+    }
+
+    fn cache_hashed_tmp_file(&self, tmp_file: &TempFile, hash: blake3::Hash) -> Result<()> {
+        Cache::cache_hashed_tmp_file(self, tmp_file, hash)
+    }
 }
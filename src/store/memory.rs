@@ -0,0 +1,144 @@
+use anyhow::{Result, anyhow};
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{
+    store::{Cache, CacheBackend, CacheStatus, Provenance, TempFile},
+    util::{Counter, count},
+};
+
+#[derive(Debug, Default)]
+struct Entries {
+    blobs: HashMap<blake3::Hash, Vec<u8>>,
+    provenances: HashMap<blake3::Hash, Provenance>,
+    mutable_records: HashMap<String, Provenance>,
+}
+
+/// A cache that keeps blobs and records in memory, for testing the lookup, corruption
+/// and promotion logic without a cache directory. Lookups count as cache hits and
+/// misses like they do on disk.
+#[derive(Debug, Default)]
+pub struct MemoryCache {
+    entries: Mutex<Entries>,
+}
+
+impl MemoryCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entries(&self) -> Result<MutexGuard<'_, Entries>> {
+        self.entries
+            .lock()
+            .map_err(|_| anyhow!("Memory cache state is poisoned"))
+    }
+
+    /// Stores content as a blob under its hash, returning the hash
+    pub fn insert(&self, content: &[u8]) -> Result<blake3::Hash> {
+        let hash = blake3::hash(content);
+        self.insert_as(hash, content)?;
+        Ok(hash)
+    }
+
+    /// Stores content as the blob of any hash, e.g. to plant a corrupt blob
+    pub fn insert_as(&self, hash: blake3::Hash, content: &[u8]) -> Result<()> {
+        self.entries()?.blobs.insert(hash, content.to_vec());
+        Ok(())
+    }
+
+    /// Contents of a blob as stored, whether or not they match the hash
+    pub fn blob(&self, hash: blake3::Hash) -> Result<Option<Vec<u8>>> {
+        Ok(self.entries()?.blobs.get(&hash).cloned())
+    }
+
+    /// Hashes of the stored blobs
+    pub fn hashes(&self) -> Result<Vec<blake3::Hash>> {
+        Ok(self.entries()?.blobs.keys().copied().collect())
+    }
+
+    /// Moves the contents of a temp file into memory, like a move into a cache directory
+    fn promote(&self, tmp_file: &TempFile, hash: blake3::Hash) -> Result<()> {
+        let content = std::fs::read(&tmp_file.path)?;
+        self.insert_as(hash, &content)?;
+        std::fs::remove_file(&tmp_file.path)?;
+        Ok(())
+    }
+}
+
+impl CacheBackend for MemoryCache {
+    fn check(&self, hash: blake3::Hash) -> Result<CacheStatus> {
+        Ok(match self.entries()?.blobs.get(&hash) {
+            None => CacheStatus::Missing,
+            Some(content) if blake3::hash(content) != hash => CacheStatus::Corrupt,
+            Some(_) => CacheStatus::Valid,
+        })
+    }
+
+    fn validate(&self, hash: blake3::Hash) -> Result<CacheStatus> {
+        let status = self.check(hash)?;
+
+        match status {
+            CacheStatus::Valid => count(Counter::CacheHits, 1),
+            CacheStatus::Missing => count(Counter::CacheMisses, 1),
+            CacheStatus::Corrupt => {
+                count(Counter::CacheMisses, 1);
+                count(Counter::ValidationFailures, 1);
+
+                let mut entries = self.entries()?;
+                entries.blobs.remove(&hash);
+                entries.provenances.remove(&hash);
+            }
+        }
+
+        Ok(status)
+    }
+
+    fn open_blob(&self, hash: blake3::Hash) -> Result<Box<dyn Read>> {
+        let content = self
+            .blob(hash)?
+            .ok_or(anyhow!("Blob {hash} is not in the cache"))?;
+
+        Ok(Box::new(Cursor::new(content)))
+    }
+
+    fn blob_size(&self, hash: blake3::Hash) -> Option<u64> {
+        let entries = self.entries().ok()?;
+        entries.blobs.get(&hash).map(|content| content.len() as u64)
+    }
+
+    fn read_provenance(&self, hash: blake3::Hash) -> Result<Option<Provenance>> {
+        Ok(self.entries()?.provenances.get(&hash).cloned())
+    }
+
+    fn write_provenance(&self, provenance: &Provenance) -> Result<()> {
+        let hash = blake3::Hash::from_hex(&provenance.hash)?;
+        self.entries()?.provenances.insert(hash, provenance.clone());
+        Ok(())
+    }
+
+    fn read_mutable_record(&self, url: &str) -> Result<Option<Provenance>> {
+        Ok(self.entries()?.mutable_records.get(url).cloned())
+    }
+
+    fn write_mutable_record(&self, provenance: &Provenance) -> Result<()> {
+        self.entries()?
+            .mutable_records
+            .insert(provenance.url.clone(), provenance.clone());
+        Ok(())
+    }
+
+    fn cache_tmp_file(&self, tmp_file: &TempFile, hash: blake3::Hash) -> Result<()> {
+        if Cache::hash_file(&tmp_file.path)? != hash {
+            return Err(anyhow!("Temporary file checksum does not match {}", hash));
+        }
+
+        self.promote(tmp_file, hash)
+    }
+
+    fn cache_hashed_tmp_file(&self, tmp_file: &TempFile, hash: blake3::Hash) -> Result<()> {
+        self.promote(tmp_file, hash)
+    }
+}
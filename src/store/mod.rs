@@ -1,3 +1,13 @@
+mod backend;
 mod cache;
+#[cfg(any(test, feature = "test-util"))]
+mod memory;
+mod provenance;
+mod temp_file;
 
-pub use cache::Cache;
+pub use backend::CacheBackend;
+pub use cache::{BlobVerification, Cache, CacheStatus, CacheUseScope};
+#[cfg(any(test, feature = "test-util"))]
+pub use memory::MemoryCache;
+pub use provenance::Provenance;
+pub use temp_file::TempFile;
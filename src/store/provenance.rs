@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::util::format_unix_time;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Provenance {
+    pub hash: String,
+    pub url: String,
+
+    /// Redacted URL the download was redirected to, if it was redirected
+    #[serde(default)]
+    pub resolved_url: Option<String>,
+
+    pub package: String,
+
+    /// Seconds since the unix epoch at which the download finished
+    pub fetched_at: u64,
+
+    pub status: u32,
+
+    #[serde(default)]
+    pub etag: Option<String>,
+
+    #[serde(default)]
+    pub last_modified: Option<String>,
+}
+
+impl Provenance {
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+
+    /// Time since the download finished
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(Self::now().saturating_sub(self.fetched_at))
+    }
+
+    pub fn fetched_at_utc(&self) -> String {
+        format_unix_time(self.fetched_at)
+    }
+}
@@ -0,0 +1,245 @@
+use anyhow::Result;
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use crate::{
+    TetraRoot,
+    util::{print_warn, process_alive},
+};
+
+/// A file in the temp directory, owned by the running process through a lockfile
+/// holding its pid next to it. Both are removed when the temp file is dropped.
+#[derive(Debug)]
+pub struct TempFile {
+    pub path: PathBuf,
+
+    /// Kept on drop, together with its state file, so a later download can resume it
+    pub resumable: bool,
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Temp files of this process that have not been dropped yet
+static LIVE: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+impl TempFile {
+    const LOCK_EXTENSION: &str = ".lock";
+    const STATE_EXTENSION: &str = ".state";
+
+    /// Temp files are named `<hash>.<pid>.<n>`, so downloads of the same content by
+    /// different processes, or within one process, never share a path
+    pub fn new(root: &TetraRoot, hash: blake3::Hash) -> Result<Self> {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+        let mut path = root.get_temp_dir()?;
+        path.push(format!("{hash}.{}.{id}", std::process::id()));
+
+        std::fs::write(Self::get_lock_path(&path), std::process::id().to_string())?;
+        Self::register(&path);
+
+        Ok(Self {
+            path,
+            resumable: false,
+        })
+    }
+
+    /// A temp file at the stable path `<hash>.resume`, which outlives the process so a
+    /// later download of the same content can continue where this one stopped. Returns
+    /// `None` if a running process is already downloading into it.
+    pub fn resumable(root: &TetraRoot, hash: blake3::Hash) -> Result<Option<Self>> {
+        let mut path = root.get_temp_dir()?;
+        path.push(format!("{hash}.resume"));
+
+        if Self::is_in_use(&path) {
+            return Ok(None);
+        }
+
+        std::fs::write(Self::get_lock_path(&path), std::process::id().to_string())?;
+        Self::register(&path);
+
+        Ok(Some(Self {
+            path,
+            resumable: true,
+        }))
+    }
+
+    fn register(path: &Path) {
+        if let Ok(mut live) = LIVE.lock() {
+            live.push(path.to_path_buf());
+        }
+    }
+
+    /// Cleans up every temp file of this process the way dropping it would, for exits
+    /// that skip destructors. Partial downloads with a recorded resume offset are kept.
+    pub fn release_all() {
+        let paths = match LIVE.lock() {
+            Ok(mut live) => std::mem::take(&mut *live),
+            Err(_) => return,
+        };
+
+        for path in paths {
+            let resumable = Self::get_state_path(&path).is_file();
+            Self::release(&path, resumable);
+        }
+    }
+
+    /// Stops keeping the file for a later download to resume, e.g. once it is complete
+    pub fn discard_resume_state(&mut self) {
+        self.resumable = false;
+
+        let state_path = Self::get_state_path(&self.path);
+        if state_path.is_file() {
+            let _ = std::fs::remove_file(state_path);
+        }
+    }
+
+    /// Removes a temp file and its lock, but only the lock of a resumable partial download
+    fn release(path: &Path, resumable: bool) {
+        let mut paths = vec![Self::get_lock_path(path)];
+
+        let has_content = std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.len() > 0);
+        if !(resumable && has_content) {
+            paths.push(path.to_path_buf());
+            paths.push(Self::get_state_path(path));
+        }
+
+        for path in paths {
+            if !path.is_file() {
+                continue;
+            }
+
+            if let Err(e) = std::fs::remove_file(&path) {
+                print_warn(&format!(
+                    "Failed to remove temporary file {}, {e}",
+                    path.display()
+                ));
+            }
+        }
+    }
+
+    pub fn get_lock_path(path: &Path) -> PathBuf {
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(Self::LOCK_EXTENSION);
+        PathBuf::from(lock_path)
+    }
+
+    pub fn get_state_path(path: &Path) -> PathBuf {
+        let mut state_path = path.as_os_str().to_owned();
+        state_path.push(Self::STATE_EXTENSION);
+        PathBuf::from(state_path)
+    }
+
+    /// Number of bytes at the start of the file that were recorded as completely
+    /// written, always 0 for files that are not resumable
+    pub fn resume_offset(&self) -> u64 {
+        if !self.resumable {
+            return 0;
+        }
+
+        let recorded = std::fs::read_to_string(Self::get_state_path(&self.path))
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        let len = std::fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+
+        recorded.min(len)
+    }
+
+    /// Records that the first `written` bytes of the file are complete
+    pub fn save_resume_offset(&self, written: u64) -> Result<()> {
+        if self.resumable {
+            std::fs::write(Self::get_state_path(&self.path), written.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether the lockfile of a temp path belongs to a process that is still running
+    pub fn is_in_use(path: &Path) -> bool {
+        std::fs::read_to_string(Self::get_lock_path(path))
+            .ok()
+            .and_then(|pid| pid.trim().parse::<u32>().ok())
+            .is_some_and(process_alive)
+    }
+
+    /// Hashes of the temp files held by running processes
+    pub fn in_use(temp_dir: &Path) -> Result<Vec<String>> {
+        let mut hashes = Vec::new();
+
+        if !temp_dir.is_dir() {
+            return Ok(hashes);
+        }
+
+        for entry in std::fs::read_dir(temp_dir)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+
+            if !name.ends_with(Self::LOCK_EXTENSION) && Self::is_in_use(&path) {
+                let hash = name.split('.').next().unwrap_or(&name);
+                hashes.push(hash.to_string());
+            }
+        }
+
+        Ok(hashes)
+    }
+
+    /// Removes everything from the temp directory that is not held by a running
+    /// process, returning the removed paths. With `dry_run` nothing is removed.
+    pub fn sweep(temp_dir: &Path, dry_run: bool) -> Result<Vec<PathBuf>> {
+        let mut removed = Vec::new();
+
+        if !temp_dir.is_dir() {
+            return Ok(removed);
+        }
+
+        for entry in std::fs::read_dir(temp_dir)? {
+            let path = entry?.path();
+
+            // Lock and state files are judged together with the temp file they belong to
+            let path_s = path.to_string_lossy();
+            let owner = match path_s
+                .strip_suffix(Self::LOCK_EXTENSION)
+                .or_else(|| path_s.strip_suffix(Self::STATE_EXTENSION))
+            {
+                Some(owner) => PathBuf::from(owner),
+                None => path.clone(),
+            };
+
+            if Self::is_in_use(&owner) {
+                continue;
+            }
+
+            if !dry_run {
+                if path.is_dir() {
+                    std::fs::remove_dir_all(&path)?;
+                } else {
+                    std::fs::remove_file(&path)?;
+                }
+            }
+
+            removed.push(path);
+        }
+
+        removed.sort();
+        Ok(removed)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Ok(mut live) = LIVE.lock() {
+            live.retain(|p| *p != self.path);
+        }
+
+        Self::release(&self.path, self.resumable);
+    }
+}
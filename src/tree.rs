@@ -0,0 +1,209 @@
+use serde::Serialize;
+use std::collections::HashSet;
+
+use tetra_pkgmgr::{
+    TetraRoot,
+    model::{Checksum, PackageId},
+    repo::{ClosureEntry, resolve_closure},
+    store::Cache,
+    util::{Style, paint, print_error},
+};
+
+use crate::{OutputFormat, print_serialized};
+
+#[derive(Debug, Serialize)]
+struct TreeNode {
+    package: String,
+
+    /// Every source of the package is in the cache, never set for a metapackage
+    cached: bool,
+
+    /// The package depends on itself through this path, its dependencies are not repeated
+    cycle: bool,
+
+    /// The package was shown earlier in the tree together with its dependencies
+    duplicate: bool,
+
+    dependencies: Vec<TreeNode>,
+}
+
+/// Options of how much of the tree is shown
+struct TreeLimits {
+    /// Levels of dependencies below the package, all of them when not set
+    depth: Option<usize>,
+
+    /// Repeat the dependencies of packages reached more than once
+    duplicates: bool,
+}
+
+/// Prints the dependency tree of a package
+pub fn run(
+    tetra_root: &TetraRoot,
+    package_id: String,
+    depth: Option<usize>,
+    duplicates: bool,
+    format: OutputFormat,
+) {
+    let id = PackageId::from_id_str(package_id);
+    let arches = tetra_root.get_arch_preference();
+
+    let repos = match tetra_root.repos() {
+        Ok(r) => r,
+        Err(e) => {
+            print_error(&format!("Failed to locate repositories: {e}"));
+            return;
+        }
+    };
+
+    let cache = match tetra_root.cache() {
+        Ok(c) => c,
+        Err(e) => {
+            print_error(&format!("Failed to obtain cache object: {e}"));
+            return;
+        }
+    };
+
+    let entries = match resolve_closure(&repos, id, &arches) {
+        Ok(e) => e,
+        Err(e) => {
+            print_error(&format!("Failed to resolve dependency closure: {e}"));
+            return;
+        }
+    };
+
+    let cached = entries
+        .iter()
+        .map(|e| is_cached(&cache, e))
+        .collect::<Vec<_>>();
+
+    let limits = TreeLimits { depth, duplicates };
+    let tree = build_node(
+        &entries,
+        &cached,
+        0,
+        &limits,
+        &mut Vec::new(),
+        &mut HashSet::new(),
+    );
+
+    if print_serialized(&tree, format) {
+        return;
+    }
+
+    println!("{}", label(&tree));
+    print_dependencies(&tree, "");
+
+    if has_duplicates(&tree) {
+        println!();
+        println!(
+            "{}",
+            paint(
+                "(*) dependencies shown above, use --duplicates to repeat them",
+                Style::Dim
+            )
+        );
+    }
+}
+
+fn has_duplicates(node: &TreeNode) -> bool {
+    node.duplicate || node.dependencies.iter().any(has_duplicates)
+}
+
+/// Whether every source of a package is cached. Sources without a hash are found through
+/// the record of their last download.
+fn is_cached(cache: &Cache, entry: &ClosureEntry) -> bool {
+    let cache = cache.for_recipe(&entry.recipe);
+    let sources = &entry.recipe.sources;
+
+    !sources.is_empty()
+        && sources.iter().all(|source| {
+            let hash = source.checksum().ok().or_else(|| {
+                cache
+                    .read_mutable_record(&source.url)
+                    .ok()
+                    .flatten()
+                    .and_then(|p| blake3::Hash::from_hex(&p.hash).ok())
+            });
+
+            hash.is_some_and(|h| cache.blob_size(h).is_some())
+        })
+}
+
+fn build_node(
+    entries: &[ClosureEntry],
+    cached: &[bool],
+    index: usize,
+    limits: &TreeLimits,
+    ancestors: &mut Vec<usize>,
+    shown: &mut HashSet<usize>,
+) -> TreeNode {
+    let entry = &entries[index];
+
+    let mut node = TreeNode {
+        package: entry.id.to_string(),
+        cached: cached[index],
+        cycle: ancestors.contains(&index),
+        duplicate: false,
+        dependencies: Vec::new(),
+    };
+
+    if node.cycle {
+        return node;
+    }
+
+    // Only packages with dependencies have anything to collapse
+    if shown.contains(&index) && !limits.duplicates && !entry.dependencies.is_empty() {
+        node.duplicate = true;
+        return node;
+    }
+
+    // A package cut off by the depth is expanded in full where it appears again
+    if limits.depth.is_some_and(|depth| ancestors.len() >= depth) {
+        return node;
+    }
+
+    shown.insert(index);
+    ancestors.push(index);
+
+    for &dependency in &entry.dependencies {
+        let child = build_node(entries, cached, dependency, limits, ancestors, shown);
+        node.dependencies.push(child);
+    }
+
+    ancestors.pop();
+    node
+}
+
+fn label(node: &TreeNode) -> String {
+    let mut label = node.package.clone();
+
+    if node.cached {
+        label.push_str(&format!(" {}", paint("(cached)", Style::Success)));
+    }
+
+    if node.cycle {
+        label.push_str(&format!(" {}", paint("(cycle)", Style::Warn)));
+    }
+
+    if node.duplicate {
+        label.push_str(&format!(" {}", paint("(*)", Style::Dim)));
+    }
+
+    label
+}
+
+fn print_dependencies(node: &TreeNode, prefix: &str) {
+    let count = node.dependencies.len();
+
+    for (i, child) in node.dependencies.iter().enumerate() {
+        let last = i + 1 == count;
+        let (branch, indent) = if last {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+
+        println!("{prefix}{branch}{}", label(child));
+        print_dependencies(child, &format!("{prefix}{indent}"));
+    }
+}
@@ -0,0 +1,98 @@
+use anyhow::{Result, anyhow};
+use std::sync::RwLock;
+
+/// Variables starting with this may always be referenced by URLs
+const URL_VAR_PREFIX: &str = "TETRA_";
+
+/// Other environment variables URLs may reference
+static URL_VARS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Sets the environment variables URLs may reference besides those starting with `TETRA_`
+pub fn set_url_env_vars(names: Vec<String>) {
+    if let Ok(mut current) = URL_VARS.write() {
+        *current = names;
+    }
+}
+
+/// Replaces `${NAME}` references in a URL with the value of the environment variable
+/// `NAME`, where `NAME` consists of ASCII letters, digits and underscores and does not
+/// start with a digit. Anything else, including a bare `$NAME`, is kept literally.
+/// Recipes come from repositories that may not be trusted, so only variables starting
+/// with `TETRA_` or allowed with [`set_url_env_vars`] are filled in, and only in the
+/// path and query, where they cannot send the request to another server. Referencing
+/// any other variable, or an unset one, is an error.
+pub fn interpolate_env(s: &str) -> Result<String> {
+    let mut interpolated = String::new();
+    let mut rest = s;
+    let path_start = path_start(s);
+
+    while let Some(start) = rest.find("${") {
+        interpolated.push_str(&rest[..start]);
+
+        let after = &rest[start + 2..];
+        let end = after.find('}').filter(|&end| is_var_name(&after[..end]));
+
+        match end {
+            Some(end) => {
+                let name = &after[..end];
+
+                // Offset of the reference in the URL as written
+                if s.len() - rest.len() + start < path_start {
+                    return Err(anyhow!(
+                        "Environment variable {name} is referenced in the scheme or host of the URL, only its path and query may reference variables"
+                    ));
+                }
+
+                if !is_url_var(name)? {
+                    return Err(anyhow!(
+                        "Environment variable {name} may not be referenced by URLs, allow it with url_env_vars or use a name starting with {URL_VAR_PREFIX}"
+                    ));
+                }
+
+                interpolated.push_str(&get_var(name)?);
+                rest = &after[end + 1..];
+            }
+            None => {
+                interpolated.push_str("${");
+                rest = after;
+            }
+        }
+    }
+
+    interpolated.push_str(rest);
+    Ok(interpolated)
+}
+
+/// Byte offset where the path of a URL starts, after its scheme and authority. A string
+/// without a scheme is all path.
+fn path_start(url: &str) -> usize {
+    let Some(authority_start) = url.find("://").map(|i| i + 3) else {
+        return 0;
+    };
+
+    url[authority_start..]
+        .find(['/', '?', '#'])
+        .map_or(url.len(), |i| authority_start + i)
+}
+
+fn is_url_var(name: &str) -> Result<bool> {
+    if name.starts_with(URL_VAR_PREFIX) {
+        return Ok(true);
+    }
+
+    let allowed = URL_VARS
+        .read()
+        .map_err(|_| anyhow!("URL variable state is poisoned"))?;
+
+    Ok(allowed.iter().any(|n| n == name))
+}
+
+fn is_var_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+pub(super) fn get_var(name: &str) -> Result<String> {
+    std::env::var(name).map_err(|_| anyhow!("Environment variable {name} is not set"))
+}
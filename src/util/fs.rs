@@ -0,0 +1,62 @@
+use anyhow::{Result, anyhow};
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+/// Sets the permission bits of a file or directory, e.g. `0o644`
+#[cfg(unix)]
+pub fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .map_err(|e| anyhow!("Failed to set permissions of {}, {e}", path.display()))
+}
+
+/// Permissions are only set on Unix, elsewhere this warns once and leaves them alone
+#[cfg(not(unix))]
+pub fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static WARNED: AtomicBool = AtomicBool::new(false);
+
+    if !WARNED.swap(true, Ordering::Relaxed) {
+        crate::util::print_warn("Cache permissions are only applied on Unix, ignoring them");
+    }
+
+    Ok(())
+}
+
+/// Moves a file, falling back to copying when source and destination are on
+/// different filesystems. The copy is staged next to the destination so the
+/// destination path never holds a partially written file.
+pub fn move_file(src: &Path, dest: &Path) -> Result<()> {
+    match std::fs::rename(src, dest) {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() != ErrorKind::CrossesDevices => return Err(e.into()),
+        Err(_) => {}
+    }
+
+    let mut staging_path = dest.as_os_str().to_owned();
+    staging_path.push(".partial");
+    let staging_path = PathBuf::from(staging_path);
+
+    // fs::copy streams the contents, it never holds the whole file in memory
+    let staged = std::fs::copy(src, &staging_path)
+        .and_then(|_| std::fs::rename(&staging_path, dest));
+
+    if let Err(e) = staged {
+        if staging_path.is_file() {
+            let _ = std::fs::remove_file(&staging_path);
+        }
+
+        return Err(anyhow!(
+            "Failed to copy {} across filesystems to {}, {e}",
+            src.display(),
+            dest.display()
+        ));
+    }
+
+    std::fs::remove_file(src)?;
+    Ok(())
+}
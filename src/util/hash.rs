@@ -0,0 +1,94 @@
+use anyhow::{Result, anyhow};
+use sha2::{Digest, Sha256};
+use std::{fs::File, io::Read, path::Path};
+
+/// Computes the lowercase hex sha256 digest of a file
+pub fn sha256_file(path: &Path) -> Result<String> {
+    sha256_reader(File::open(path)?)
+}
+
+/// Incremental sha256 digest, for content that is hashed as it passes through
+#[derive(Debug, Clone)]
+pub struct Sha256Hasher(Sha256);
+
+impl Sha256Hasher {
+    pub fn new() -> Self {
+        Self(Sha256::new())
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    /// The lowercase hex digest of everything hashed
+    pub fn finalize(self) -> String {
+        self.0
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+}
+
+impl Default for Sha256Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Computes the lowercase hex sha256 digest of everything `reader` yields
+pub fn sha256_reader(mut reader: impl Read) -> Result<String> {
+    let mut hasher = Sha256Hasher::new();
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        let read = reader.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+
+    Ok(hasher.finalize())
+}
+
+/// Encodes bytes as a lowercase hex string
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Decodes a hex string such as a key or signature into its bytes
+pub fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(anyhow!("{hex} is not a hex string of whole bytes"));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|e| anyhow!("{e}")))
+        .collect()
+}
+
+/// Looks up the digest of `file` in a checksum manifest, in the format written by
+/// `sha256sum` or in the BSD `SHA256 (file) = digest` format
+pub fn find_manifest_digest(manifest: &str, file: &str) -> Option<String> {
+    let file = file.trim_start_matches("./");
+
+    manifest.lines().find_map(|line| {
+        let line = line.trim();
+
+        let (digest, name) = match line.strip_prefix("SHA256 (") {
+            Some(rest) => {
+                let (name, digest) = rest.rsplit_once(") = ")?;
+                (digest, name)
+            }
+            None => {
+                let (digest, name) = line.split_once(char::is_whitespace)?;
+                (digest, name.trim_start().trim_start_matches('*'))
+            }
+        };
+
+        let is_digest = digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit());
+        (is_digest && name.trim_start_matches("./") == file).then(|| digest.to_lowercase())
+    })
+}
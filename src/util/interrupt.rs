@@ -0,0 +1,32 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static ABORTABLE: AtomicUsize = AtomicUsize::new(0);
+
+/// Records that the user asked to stop, e.g. from a signal handler. Returns whether
+/// an abortable operation is running that will notice it and stop by itself.
+pub fn interrupt() -> bool {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    ABORTABLE.load(Ordering::SeqCst) > 0
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Marks an operation that checks [`is_interrupted`] regularly for as long as it lives
+#[derive(Debug)]
+pub struct AbortableScope(());
+
+impl AbortableScope {
+    pub fn enter() -> Self {
+        ABORTABLE.fetch_add(1, Ordering::SeqCst);
+        Self(())
+    }
+}
+
+impl Drop for AbortableScope {
+    fn drop(&mut self) {
+        ABORTABLE.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Process-wide counters of downloads and cache lookups, exported in the Prometheus
+/// text format by [`render_metrics`]
+#[derive(Debug, Clone, Copy)]
+pub enum Counter {
+    DownloadsStarted,
+    DownloadsSucceeded,
+    DownloadsFailed,
+
+    /// Bytes received by downloads, including those of attempts that were retried
+    BytesFetched,
+    CacheHits,
+    CacheMisses,
+
+    /// Downloads and cached blobs that did not match their expected hash
+    ValidationFailures,
+
+    /// Blobs removed to keep the cache within its size budget
+    CacheEvictions,
+}
+
+impl Counter {
+    const ALL: [Counter; 8] = [
+        Counter::DownloadsStarted,
+        Counter::DownloadsSucceeded,
+        Counter::DownloadsFailed,
+        Counter::BytesFetched,
+        Counter::CacheHits,
+        Counter::CacheMisses,
+        Counter::ValidationFailures,
+        Counter::CacheEvictions,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Counter::DownloadsStarted => "tetra_downloads_started_total",
+            Counter::DownloadsSucceeded => "tetra_downloads_succeeded_total",
+            Counter::DownloadsFailed => "tetra_downloads_failed_total",
+            Counter::BytesFetched => "tetra_fetched_bytes_total",
+            Counter::CacheHits => "tetra_cache_hits_total",
+            Counter::CacheMisses => "tetra_cache_misses_total",
+            Counter::ValidationFailures => "tetra_validation_failures_total",
+            Counter::CacheEvictions => "tetra_cache_evictions_total",
+        }
+    }
+
+    fn help(self) -> &'static str {
+        match self {
+            Counter::DownloadsStarted => "Source downloads started",
+            Counter::DownloadsSucceeded => "Source downloads that completed and verified",
+            Counter::DownloadsFailed => "Source downloads that failed",
+            Counter::BytesFetched => "Bytes received by source downloads",
+            Counter::CacheHits => "Cache lookups that found an intact blob",
+            Counter::CacheMisses => "Cache lookups that found no intact blob",
+            Counter::ValidationFailures => "Downloads and cached blobs that failed verification",
+            Counter::CacheEvictions => "Cached blobs evicted to stay within the size budget",
+        }
+    }
+}
+
+static COUNTERS: [AtomicU64; Counter::ALL.len()] =
+    [const { AtomicU64::new(0) }; Counter::ALL.len()];
+
+pub fn count(counter: Counter, n: u64) {
+    COUNTERS[counter as usize].fetch_add(n, Ordering::Relaxed);
+}
+
+pub fn counter_value(counter: Counter) -> u64 {
+    COUNTERS[counter as usize].load(Ordering::Relaxed)
+}
+
+/// All counters in the Prometheus text exposition format
+pub fn render_metrics() -> String {
+    let mut text = String::new();
+
+    for counter in Counter::ALL {
+        let name = counter.name();
+        text.push_str(&format!("# HELP {name} {}\n", counter.help()));
+        text.push_str(&format!("# TYPE {name} counter\n"));
+        text.push_str(&format!("{name} {}\n", counter_value(counter)));
+    }
+
+    text
+}
+
+/// Serves the counters on `GET /metrics` at `addr` from a background thread, for as
+/// long as the process runs. Returns the address the endpoint listens on.
+#[cfg(feature = "metrics")]
+pub fn serve_metrics(addr: &str) -> anyhow::Result<std::net::SocketAddr> {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    let listener = TcpListener::bind(addr)
+        .map_err(|e| anyhow::anyhow!("Failed to listen for metrics on {addr}, {e}"))?;
+    let local_addr = listener.local_addr()?;
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // A client that never finishes its request must not block the endpoint
+            let _ = stream.set_read_timeout(Some(std::time::Duration::from_secs(5)));
+
+            let mut reader = BufReader::new(&stream);
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).is_err() {
+                continue;
+            }
+
+            // Closing with unread headers would reset the connection before the response
+            let mut header = String::new();
+            while reader.read_line(&mut header).is_ok_and(|n| n > 2) {
+                header.clear();
+            }
+
+            let response = if request_line.starts_with("GET /metrics ") {
+                let body = render_metrics();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string()
+            };
+
+            // A scraper that went away is not worth reporting
+            let _ = (&stream).write_all(response.as_bytes());
+        }
+    });
+
+    Ok(local_addr)
+}
@@ -0,0 +1,35 @@
+mod bytes;
+mod env;
+mod fs;
+mod hash;
+mod interrupt;
+mod metrics;
+mod parallel;
+mod path;
+mod process;
+mod prompt;
+mod redact;
+mod style;
+mod time;
+mod xdg;
+
+pub use bytes::format_bytes;
+pub use env::{interpolate_env, set_url_env_vars};
+pub use fs::{move_file, set_mode};
+pub use hash::{
+    Sha256Hasher, decode_hex, encode_hex, find_manifest_digest, sha256_file, sha256_reader,
+};
+pub use interrupt::{AbortableScope, interrupt, is_interrupted};
+#[cfg(feature = "metrics")]
+pub use metrics::serve_metrics;
+pub use metrics::{Counter, count, counter_value, render_metrics};
+pub use parallel::{max_parallel, parallel_map, set_max_parallel};
+pub use path::{expand_path, normalize_path};
+pub use process::process_alive;
+pub use prompt::confirm;
+pub use redact::redact_url;
+pub use style::{
+    Style, init_color, paint, print_error, print_warn, print_warn_to_stderr, set_messages_to_stderr,
+};
+pub use time::{format_unix_time, parse_unix_time};
+pub use xdg::UserDirs;
@@ -0,0 +1,61 @@
+use std::sync::{
+    Mutex,
+    atomic::{AtomicUsize, Ordering},
+};
+
+/// Global cap on the threads of concurrent operations, 0 until configured
+static MAX_PARALLEL: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the global cap on concurrency, values below 1 are raised to 1
+pub fn set_max_parallel(max: usize) {
+    MAX_PARALLEL.store(max.max(1), Ordering::SeqCst);
+}
+
+/// The global cap on concurrency, the number of CPUs unless configured otherwise
+pub fn max_parallel() -> usize {
+    match MAX_PARALLEL.load(Ordering::SeqCst) {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        max => max,
+    }
+}
+
+/// Maps `f` over `items` on up to `limit` threads, returning the results in the order
+/// of `items`. A command may pass a lower limit for its work but never exceeds the
+/// global [`max_parallel`].
+pub fn parallel_map<T, R, F>(items: &[T], limit: Option<usize>, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let workers = limit
+        .map_or(max_parallel(), |limit| limit.min(max_parallel()))
+        .clamp(1, items.len().max(1));
+
+    if workers == 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(items.len()));
+
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(item) = items.get(index) else {
+                        break;
+                    };
+
+                    let result = f(item);
+                    results.lock().unwrap().push((index, result));
+                }
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
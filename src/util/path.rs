@@ -0,0 +1,78 @@
+use anyhow::Result;
+use std::path::{Component, Path, PathBuf};
+
+use super::env::get_var;
+
+/// Expands shell-style references in a path:
+///
+/// - `~` on its own or followed by `/` at the start of the path is replaced with `$HOME`,
+///   other uses of `~` (e.g. `~user`) are kept literally
+/// - `$NAME` and `${NAME}` are replaced with the value of the environment variable `NAME`,
+///   where `NAME` consists of ASCII letters, digits and underscores and does not start with
+///   a digit. A `$` not followed by a valid name is kept literally
+///
+/// Referencing an unset variable (including `HOME` through `~`) is an error.
+pub fn expand_path(path: &str) -> Result<PathBuf> {
+    let mut expanded = String::new();
+
+    let rest = if path == "~" || path.starts_with("~/") {
+        expanded.push_str(&get_var("HOME")?);
+        &path[1..]
+    } else {
+        path
+    };
+
+    let mut chars = rest.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != '$' {
+            expanded.push(c);
+            continue;
+        }
+
+        let braced = rest[i + 1..].starts_with('{');
+        let name_start = if braced { i + 2 } else { i + 1 };
+        let name_len = rest[name_start..]
+            .char_indices()
+            .take_while(|&(j, c)| c == '_' || c.is_ascii_alphabetic() || (j > 0 && c.is_ascii_digit()))
+            .count();
+
+        let name = &rest[name_start..name_start + name_len];
+        let closed = !braced || rest[name_start + name_len..].starts_with('}');
+
+        if name.is_empty() || !closed {
+            expanded.push(c);
+            continue;
+        }
+
+        expanded.push_str(&get_var(name)?);
+
+        let end = name_start + name_len + if braced { 1 } else { 0 };
+        while chars.peek().is_some_and(|(j, _)| *j < end) {
+            chars.next();
+        }
+    }
+
+    Ok(PathBuf::from(expanded))
+}
+
+/// Lexically resolves `.` and `..` components of a path without touching the
+/// filesystem, so it also works for paths that only exist inside archives.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if matches!(normalized.components().next_back(), Some(Component::Normal(_))) {
+                    normalized.pop();
+                } else if !normalized.has_root() {
+                    normalized.push("..");
+                }
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
@@ -0,0 +1,6 @@
+use std::path::Path;
+
+/// Whether a process with the given pid is currently running
+pub fn process_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
@@ -0,0 +1,14 @@
+use anyhow::Result;
+use std::io::Write;
+
+/// Asks a yes/no question on the terminal, anything but `y` or `yes` is a no.
+pub fn confirm(prompt: &str) -> Result<bool> {
+    print!("{prompt} [y/N] ");
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+
+    let answer = answer.trim().to_lowercase();
+    Ok(answer == "y" || answer == "yes")
+}
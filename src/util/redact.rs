@@ -0,0 +1,66 @@
+/// Query parameters whose values are credentials, matched case-insensitively
+const SENSITIVE_PARAMS: [&str; 8] = [
+    "token",
+    "access_token",
+    "sig",
+    "signature",
+    "x-amz-signature",
+    "key",
+    "api_key",
+    "password",
+];
+
+const REDACTED: &str = "REDACTED";
+
+/// Makes a URL safe to print: userinfo is stripped and the values of sensitive query
+/// parameters are masked. Works on the string itself, so templates with unresolved
+/// `${NAME}` references are redacted as well and nothing else is re-encoded.
+pub fn redact_url(url: &str) -> String {
+    let (rest, fragment) = match url.split_once('#') {
+        Some((rest, fragment)) => (rest, Some(fragment)),
+        None => (url, None),
+    };
+
+    let (base, query) = match rest.split_once('?') {
+        Some((base, query)) => (base, Some(query)),
+        None => (rest, None),
+    };
+
+    let mut redacted = match base.split_once("://") {
+        Some((scheme, after)) => {
+            let (authority, path) = after.split_at(after.find('/').unwrap_or(after.len()));
+
+            match authority.rsplit_once('@') {
+                Some((_, host)) => format!("{scheme}://{host}{path}"),
+                None => base.to_string(),
+            }
+        }
+        None => base.to_string(),
+    };
+
+    if let Some(query) = query {
+        let pairs = query
+            .split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((name, _))
+                    if SENSITIVE_PARAMS
+                        .iter()
+                        .any(|p| p.eq_ignore_ascii_case(name)) =>
+                {
+                    format!("{name}={REDACTED}")
+                }
+                _ => pair.to_string(),
+            })
+            .collect::<Vec<_>>();
+
+        redacted.push('?');
+        redacted.push_str(&pairs.join("&"));
+    }
+
+    if let Some(fragment) = fragment {
+        redacted.push('#');
+        redacted.push_str(fragment);
+    }
+
+    redacted
+}
@@ -0,0 +1,112 @@
+use std::{
+    fmt::Display,
+    io::IsTerminal,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// How a piece of terminal output is colored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Error,
+    Warn,
+    Success,
+
+    /// Less important details, such as hints
+    Dim,
+}
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+static STDERR_COLOR: AtomicBool = AtomicBool::new(false);
+static MESSAGES_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Enables colored output on each of stdout and stderr that is a terminal, unless
+/// `disabled` is set or the `NO_COLOR` environment variable is
+pub fn init_color(disabled: bool) {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let enabled = !disabled && !no_color;
+
+    COLOR.store(
+        enabled && std::io::stdout().is_terminal(),
+        Ordering::Relaxed,
+    );
+    STDERR_COLOR.store(
+        enabled && std::io::stderr().is_terminal(),
+        Ordering::Relaxed,
+    );
+}
+
+/// Wraps text printed to stdout in the color of a style, or returns it unchanged when
+/// color is off
+pub fn paint(text: impl Display, style: Style) -> String {
+    paint_if(COLOR.load(Ordering::Relaxed), text, style)
+}
+
+fn paint_if(enabled: bool, text: impl Display, style: Style) -> String {
+    if !enabled {
+        return text.to_string();
+    }
+
+    let code = match style {
+        Style::Error => "31",
+        Style::Warn => "33",
+        Style::Success => "32",
+        Style::Dim => "2",
+    };
+
+    format!("\x1b[{code}m{text}\x1b[0m")
+}
+
+/// Prints errors and warnings to stderr for the rest of the run, keeping stdout for
+/// data such as a source streamed to it
+pub fn set_messages_to_stderr(enabled: bool) {
+    MESSAGES_TO_STDERR.store(enabled, Ordering::Relaxed);
+}
+
+/// Prints a message to stdout, or to stderr if messages go there, colored as that
+/// stream allows
+fn print_message(text: &str, style: Style, prefix: &str) {
+    if MESSAGES_TO_STDERR.load(Ordering::Relaxed) {
+        eprintln!(
+            "{}",
+            styled(STDERR_COLOR.load(Ordering::Relaxed), text, style, prefix)
+        );
+    } else {
+        println!(
+            "{}",
+            styled(COLOR.load(Ordering::Relaxed), text, style, prefix)
+        );
+    }
+}
+
+/// Paints a message, or only its prefix if it has one
+fn styled(enabled: bool, text: &str, style: Style, prefix: &str) -> String {
+    if prefix.is_empty() {
+        paint_if(enabled, text, style)
+    } else {
+        format!("{} {text}", paint_if(enabled, prefix, style))
+    }
+}
+
+/// Prints an error message
+pub fn print_error(message: &str) {
+    print_message(message, Style::Error, "");
+}
+
+/// Prints a warning, prefixed with `WARN:`
+pub fn print_warn(message: &str) {
+    print_message(message, Style::Warn, "WARN:");
+}
+
+/// Prints a warning to stderr wherever other messages go, for warnings about the run
+/// itself that must stay out of the output of any command
+pub fn print_warn_to_stderr(message: &str) {
+    eprintln!(
+        "{}",
+        styled(
+            STDERR_COLOR.load(Ordering::Relaxed),
+            message,
+            Style::Warn,
+            "WARN:"
+        )
+    );
+}
@@ -0,0 +1,49 @@
+use anyhow::{Result, anyhow};
+use std::path::PathBuf;
+
+/// Locations of a per-user install, following the XDG base directory specification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserDirs {
+    /// `$XDG_CONFIG_HOME/tetra`, holding `config.yml` and the `arch` file
+    pub config: PathBuf,
+
+    /// `$XDG_CACHE_HOME/tetra`, the source cache
+    pub cache: PathBuf,
+
+    /// `$XDG_DATA_HOME/tetra`, the tetra root with the repositories
+    pub data: PathBuf,
+}
+
+impl UserDirs {
+    /// Whether the `TETRA_USER` environment variable asks for a per-user install
+    pub fn enabled_by_env() -> bool {
+        std::env::var("TETRA_USER").is_ok_and(|v| matches!(v.as_str(), "1" | "true" | "yes"))
+    }
+
+    /// Locates the directories, a base directory variable that is unset or not absolute
+    /// falls back to its default under `$HOME`
+    pub fn locate() -> Result<Self> {
+        Ok(Self {
+            config: base_dir("XDG_CONFIG_HOME", ".config")?.join("tetra"),
+            cache: base_dir("XDG_CACHE_HOME", ".cache")?.join("tetra"),
+            data: base_dir("XDG_DATA_HOME", ".local/share")?.join("tetra"),
+        })
+    }
+}
+
+fn base_dir(var: &str, default: &str) -> Result<PathBuf> {
+    // Relative paths are invalid according to the specification and are ignored
+    if let Some(dir) = std::env::var_os(var).map(PathBuf::from)
+        && dir.is_absolute()
+    {
+        return Ok(dir);
+    }
+
+    let home = std::env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .ok_or(anyhow!(
+            "Neither {var} nor HOME is set, cannot locate the user directories"
+        ))?;
+
+    Ok(PathBuf::from(home).join(default))
+}
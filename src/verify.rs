@@ -0,0 +1,172 @@
+use tetra_pkgmgr::{
+    TetraRoot,
+    model::{Checksum, RecipeSource},
+    store::{CacheBackend, CacheStatus},
+    util::{Style, paint},
+};
+
+use crate::output::{self, CheckedPackage, Report, Status};
+
+/// Verifies the cached sources of a package, or only the selected one, against its
+/// recipe without downloading anything, returning false if any check failed
+pub fn run(
+    tetra_root: &TetraRoot,
+    package_id: String,
+    exact: bool,
+    selector: Option<String>,
+) -> bool {
+    let mut report = Report::default();
+
+    let Some(package) = CheckedPackage::load(&mut report, tetra_root, package_id, exact, selector)
+    else {
+        return false;
+    };
+
+    let mut checked = 0;
+    let mut unverified = 0;
+
+    for source in package.sources() {
+        checked += 1;
+        if !verify_source(&mut report, &package.cache, source) {
+            unverified += 1;
+        }
+    }
+
+    println!();
+
+    let verified = checked - unverified;
+    match (report.failed, unverified) {
+        (true, _) => println!("Result: {}", paint("FAIL", Style::Error)),
+        (false, 0) => println!(
+            "Result: {}, {verified} source(s) verified",
+            paint("PASS", Style::Success)
+        ),
+        (false, _) => println!(
+            "Result: {}, {verified} source(s) verified, {unverified} not cached",
+            paint("PASS", Style::Success)
+        ),
+    }
+
+    !report.failed
+}
+
+/// Checks one cached source, returning false if it is not cached and could not be checked
+fn verify_source(report: &mut Report, cache: &dyn CacheBackend, source: &RecipeSource) -> bool {
+    // Hashless mutable sources are checked against the record of their last download
+    let hash = match source.checksum() {
+        Ok(hash) => hash,
+        Err(_) if source.mutable => {
+            let record = cache.read_mutable_record(&source.url).ok().flatten();
+            let Some(hash) = record.and_then(|p| blake3::Hash::from_hex(&p.hash).ok()) else {
+                report.check(
+                    Status::Warn,
+                    "Not cached, nothing to verify",
+                    Some("Run tetra fetch to download it"),
+                );
+                return false;
+            };
+
+            report.check(
+                Status::Warn,
+                "Mutable source without a hash, checked against its last download",
+                Some("Add a hash to the recipe to pin its content"),
+            );
+            hash
+        }
+        Err(e) => {
+            report.check(Status::Fail, &format!("Invalid checksum, {e}"), None);
+            return true;
+        }
+    };
+
+    match cache.check(hash) {
+        Ok(CacheStatus::Valid) => {
+            report.check(Status::Pass, &format!("blake3 {hash} matches"), None)
+        }
+        Ok(CacheStatus::Missing) => {
+            report.check(
+                Status::Warn,
+                "Not cached, nothing to verify",
+                Some("Run tetra fetch to download it"),
+            );
+            return false;
+        }
+        Ok(CacheStatus::Corrupt) => {
+            report.check(
+                Status::Fail,
+                &format!("Cached blob does not match blake3 {hash}"),
+                Some("Run tetra fetch to replace it"),
+            );
+            return true;
+        }
+        Err(e) => {
+            report.check(
+                Status::Fail,
+                &format!("Failed to check cached blob, {e}"),
+                None,
+            );
+            return true;
+        }
+    }
+
+    if let Some(expected) = &source.sha256 {
+        match cache.sha256_blob(hash) {
+            Ok(computed) if computed.eq_ignore_ascii_case(expected) => {
+                report.check(Status::Pass, &format!("sha256 {computed} matches"), None)
+            }
+            Ok(computed) => report.check(
+                Status::Fail,
+                &format!("sha256 {computed} does not match {expected}"),
+                None,
+            ),
+            Err(e) => report.check(
+                Status::Fail,
+                &format!("Failed to compute sha256, {e}"),
+                None,
+            ),
+        }
+    }
+
+    if let Some(manifest) = &source.sha256sums {
+        report.check(
+            Status::Warn,
+            &format!(
+                "sha256sums {} not checked, verify works offline",
+                output::display_url(&manifest.url)
+            ),
+            Some("It was checked when the source was downloaded"),
+        );
+    }
+
+    match cache.read_provenance(hash) {
+        Ok(Some(provenance)) if provenance.url == source.url => report.check(
+            Status::Pass,
+            &format!(
+                "Downloaded from the recipe URL at {}",
+                provenance.fetched_at_utc()
+            ),
+            None,
+        ),
+        Ok(Some(provenance)) => report.check(
+            Status::Warn,
+            &format!(
+                "Downloaded from {} at {}, not the recipe URL",
+                output::display_url(&provenance.url),
+                provenance.fetched_at_utc()
+            ),
+            Some("The content still matches the recipe hash"),
+        ),
+        Ok(None) => report.check(
+            Status::Warn,
+            "No provenance record, the blob was added to the cache by hand",
+            None,
+        ),
+        Err(e) => report.check(
+            Status::Warn,
+            &format!("Provenance record is unreadable, {e}"),
+            None,
+        ),
+    }
+
+    true
+}